@@ -0,0 +1,104 @@
+//! Finding similar names among a million, with the name index and by
+//! scoring every name, and loading what the command line needs to.
+//!
+//! Run with `cargo bench --bench similar_names`, `NAMES=20000000` for
+//! another number of names.
+
+use std::env;
+use std::hint::black_box;
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use diffrust::core::algorithm::Metric;
+use diffrust::core::model::{Collection, ContentType, Dir, File};
+use diffrust::core::ngram::NameIndex;
+
+/// Names of random words and as many numbered photos, in directories of
+/// a thousand entries
+fn tree(names: usize) -> Dir {
+    let mut seed = 42u64;
+    let mut word = |len: usize| -> String {
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                char::from(b'a' + (seed >> 33) as u8 % 26)
+            })
+            .collect()
+    };
+    let mut root = Dir {
+        path: PathBuf::from("/c"),
+        ..Default::default()
+    };
+    for d in 0..names.div_ceil(1000) {
+        let path = root.path.join(format!("{d:05}"));
+        let content = (0..1000.min(names - d * 1000))
+            .map(|i| {
+                let name = match i % 2 {
+                    0 => format!("{} {}.txt", word(3 + i % 5), word(4 + i % 7)),
+                    _ => format!("IMG_{:08}.jpg", d * 1000 + i),
+                };
+                ContentType::ContentFile(File {
+                    path: path.join(name),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        root.content.push(ContentType::ContentDir(Dir {
+            path,
+            content,
+            ..Default::default()
+        }));
+    }
+    root
+}
+
+fn similar_names(c: &mut Criterion) {
+    let names = env::var("NAMES").map_or(1_000_000, |n| n.parse().expect("NAMES"));
+    let dir = tree(names);
+    let index = NameIndex::build(&dir);
+    let names: Vec<String> = diffrust::core::search::entries(&dir)
+        .iter()
+        .map(|e| e.path().file_name().unwrap().to_string_lossy().into_owned())
+        .collect();
+    // a name with a typo, a photo name matching many others and a name
+    // matching none
+    let typo = format!("x{}", &names[names.len() / 2 + 1][1..]);
+    let patterns = [("typo", typo.as_str()), ("photo", "IMG_00120500.jpg"), ("none", "zzqqj")];
+
+    let mut group = c.benchmark_group("similar");
+    group.sample_size(10);
+    for (label, pattern) in patterns {
+        group.bench_function(format!("index/{label}"), |b| {
+            b.iter(|| index.similar(black_box(pattern), Metric::Dice, 0.5))
+        });
+    }
+    group.bench_function("every name/typo", |b| {
+        b.iter(|| {
+            names
+                .iter()
+                .filter(|name| Metric::Dice.similarity(name, black_box(&typo)) >= 0.5)
+                .count()
+        })
+    });
+    group.finish();
+
+    // the command line loads the tree from the database and the index
+    let tempdir = tempfile::tempdir().unwrap();
+    let mut collection = Collection::from(tempdir.path());
+    collection.root_dir = Some(dir);
+    collection.save().unwrap();
+    let (db, saved) = (collection.db_path(), collection.index_path());
+    index.save(&saved, &db).unwrap();
+    let mut group = c.benchmark_group("load");
+    group.sample_size(10);
+    group.bench_function("database", |b| {
+        b.iter(|| Collection::from(tempdir.path()).load().unwrap())
+    });
+    group.bench_function("index", |b| {
+        b.iter(|| NameIndex::load(&saved, &db).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, similar_names);
+criterion_main!(benches);
@@ -1,18 +1,351 @@
 use std::path::PathBuf;
+use std::time::SystemTime;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+
+use crate::core::algorithm::Metric;
+use crate::core::filter::{parse_date, parse_size, Filter};
+use crate::core::hash::HashAlgorithm;
+use crate::core::manifest::ManifestFormat;
+use crate::core::model::SortKey;
 
 #[derive(Parser)]
 #[command(name = env!("CARGO_PKG_NAME"))]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(about = "Diff for the File System", long_about = None)]
 pub struct Config {
-    /// The path to the opened collection
+    /// The path to the opened collection, or @NAME for a registered one
     pub path: PathBuf,
 
     /// A pattern for finding files by name
     #[arg(short, long)]
     pub find: Option<String>,
+
+    /// Match the find pattern as a regular expression, on the names of
+    /// the entries at any depth, e.g. 'IMG_\d{4}\.(jpe?g|heic)'
+    #[arg(short = 'E', long, requires = "find", group = "find_mode")]
+    pub regex: bool,
+
+    /// Match the characters of the find pattern in order, on the names
+    /// of the entries at any depth, best matches first (e.g. 'intalg'
+    /// for Introduction.to.Algorithms.pdf)
+    #[arg(short = 'z', long, requires = "find", group = "find_mode")]
+    pub fuzzy: bool,
+
+    /// Find names similar to the fuzzy pattern by a metric (dice,
+    /// jaccard, levenshtein, damerau, jaro-winkler or token-set) instead
+    /// of matching its characters in order
+    #[arg(long, requires = "fuzzy")]
+    pub metric: Option<Metric>,
+
+    /// Match the regular expression or fuzzy pattern on the path relative
+    /// to the root instead of the name
+    #[arg(long, requires = "find_mode")]
+    pub match_path: bool,
+
+    /// Find the entries, at any depth, matching a query on their
+    /// metadata, e.g. 'size>100M and ext in (mkv,mp4)'
+    #[arg(short = 'Q', long)]
+    pub query: Option<String>,
+
+    /// Fail when any entry cannot be scanned instead of skipping it
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Socket of the daemon (default in XDG_RUNTIME_DIR or the temporary
+    /// directory)
+    #[arg(long, global = true)]
+    pub socket: Option<PathBuf>,
+
+    /// Scan locally even when a daemon is running
+    #[arg(long, global = true)]
+    pub no_daemon: bool,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+
+    #[command(flatten)]
+    pub filters: FilterArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+/// Options controlling how results are printed
+#[derive(Args, Debug, Default)]
+pub struct OutputArgs {
+    /// Output format of show, find, dups, diff, du and stats (default
+    /// text, or the format configured for the collection)
+    #[arg(long, global = true, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// When to color the output (auto honours NO_COLOR)
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub color: ColorChoice,
+
+    /// Only use ASCII characters (no emoji)
+    #[arg(long, global = true)]
+    pub ascii: bool,
+
+    /// Only print results and problems, without banner and summaries
+    #[arg(short, long, global = true, conflicts_with = "brief")]
+    pub quiet: bool,
+
+    /// Print one plain line per result
+    #[arg(short, long, global = true)]
+    pub brief: bool,
+
+    /// Print sizes in bytes instead of human readable units
+    #[arg(long, global = true)]
+    pub bytes: bool,
+
+    /// How to report scan progress on standard error
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub progress: ProgressMode,
+}
+
+/// Options selecting the entries indexed by scans, in addition to the
+/// ones configured for the collection
+#[derive(Args, Debug, Default, PartialEq)]
+pub struct FilterArgs {
+    /// Index only the files of at least SIZE bytes (units K, M, G, T)
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size)]
+    pub min_size: Option<u64>,
+
+    /// Index only the files of at most SIZE bytes (units K, M, G, T)
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Index only the files modified at or after DATE (YYYY-MM-DD or
+    /// RFC 3339)
+    #[arg(long, global = true, value_name = "DATE", value_parser = parse_date)]
+    pub modified_after: Option<SystemTime>,
+
+    /// Index only the files modified before DATE (YYYY-MM-DD or RFC 3339)
+    #[arg(long, global = true, value_name = "DATE", value_parser = parse_date)]
+    pub modified_before: Option<SystemTime>,
+
+    /// Index only the files with these extensions (comma separated)
+    #[arg(long = "ext", global = true, value_name = "EXT", value_delimiter = ',')]
+    pub extensions: Vec<String>,
+
+    /// Leave out the files with these extensions (comma separated)
+    #[arg(
+        long = "exclude-ext",
+        global = true,
+        value_name = "EXT",
+        value_delimiter = ','
+    )]
+    pub exclude_extensions: Vec<String>,
+
+    /// Leave out hidden entries (names starting with '.')
+    #[arg(long, global = true)]
+    pub skip_hidden: bool,
+
+    /// Do not enter directories on other filesystems
+    #[arg(short = 'x', long, global = true)]
+    pub one_file_system: bool,
+}
+
+impl FilterArgs {
+    /// Adds the options to filter, replacing the limits it sets too
+    pub fn apply(&self, filter: &mut Filter) {
+        filter.min_size = self.min_size.or(filter.min_size);
+        filter.max_size = self.max_size.or(filter.max_size);
+        filter.modified_after = self.modified_after.or(filter.modified_after);
+        filter.modified_before = self.modified_before.or(filter.modified_before);
+        if !self.extensions.is_empty() {
+            filter.extensions = self.extensions.clone();
+        }
+        filter
+            .exclude_extensions
+            .extend(self.exclude_extensions.iter().cloned());
+        filter.skip_hidden |= self.skip_hidden;
+        filter.one_filesystem |= self.one_file_system;
+    }
+}
+
+/// Scan progress reporting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ProgressMode {
+    /// A progress bar when standard error is a terminal
+    #[default]
+    Auto,
+    /// Always draw a progress bar
+    Bar,
+    /// A stream of JSON records, one per line
+    Json,
+    /// No progress reporting
+    None,
+}
+
+/// Output formats, see the `json` module for the JSON schema
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// Human readable text
+    #[default]
+    Text,
+    /// A single JSON document
+    Json,
+    /// Newline delimited JSON, one record per line
+    Ndjson,
+}
+
+/// When to color the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ColorChoice {
+    /// Only when printing to a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Create the configuration of a new collection at PATH
+    Init {
+        /// Name of the collection, by default the name of its directory
+        #[arg(long)]
+        name: Option<String>,
+        /// Hash algorithm of manifests and specifications (md5, sha1,
+        /// sha256 or sha512)
+        #[arg(long, default_value_t = HashAlgorithm::Md5)]
+        hash: HashAlgorithm,
+        /// Glob pattern of the entries left out of scans, matched against
+        /// names or, with a '/', paths relative to PATH (repeatable)
+        #[arg(long)]
+        ignore: Vec<String>,
+    },
+    /// Show the content of the collection (the default command)
+    Show {
+        /// Render the whole hierarchy as a tree
+        #[arg(short, long)]
+        tree: bool,
+        /// Maximum depth of the tree, 1 shows the root content only
+        /// (implies --tree)
+        #[arg(short, long)]
+        depth: Option<usize>,
+        /// Sort by name (natural order), size, mtime or path
+        #[arg(short, long, default_value_t = SortKey::Name)]
+        sort: SortKey,
+        /// Reverse the sort order
+        #[arg(short, long)]
+        reverse: bool,
+        /// Mix directories with files instead of listing them first
+        #[arg(long)]
+        mixed: bool,
+    },
+    /// Scan the collection and store it in its database
+    Scan {
+        /// Continue a cancelled scan, files hashed before are not hashed again
+        #[arg(long)]
+        resume: bool,
+    },
+    /// List groups of files with the same content
+    Dups,
+    /// Compare the collection with another directory
+    Diff {
+        /// The path of the other collection, or @NAME
+        other: PathBuf,
+        /// Metric choosing, among removed files with the content of an
+        /// added one, the one with the most similar name as renamed
+        #[arg(long, default_value_t = Metric::default())]
+        metric: Metric,
+    },
+    /// Re-hash stored files and check them against their stored digests
+    Verify,
+    /// Show the disk usage of the largest directories and files
+    Du {
+        /// Number of directories and files to list
+        #[arg(short = 'n', long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Show statistics of the collection by type, age and size
+    Stats,
+    /// Export the collection as a checksum manifest
+    Manifest {
+        /// The file the manifest is written to
+        output: PathBuf,
+        /// Hash algorithm (md5, sha1, sha256 or sha512), by default the
+        /// one configured for the collection or md5
+        #[arg(short, long)]
+        algorithm: Option<HashAlgorithm>,
+        /// Line style (gnu or bsd), --format selects the output format
+        #[arg(long, default_value_t = ManifestFormat::Gnu)]
+        style: ManifestFormat,
+    },
+    /// Check the collection against a checksum manifest
+    Check {
+        /// A manifest in GNU (md5sum, sha256sum, ...) or BSD format
+        manifest: PathBuf,
+    },
+    /// Export the collection as a BSD mtree specification
+    Mtree {
+        /// The file the specification is written to
+        output: PathBuf,
+        /// Digest keywords to emit (comma separated list of md5, sha1,
+        /// sha256 or sha512), by default the hash configured for the
+        /// collection or md5
+        #[arg(short, long, value_delimiter = ',')]
+        algorithms: Vec<HashAlgorithm>,
+    },
+    /// Compare the collection with a BSD mtree specification
+    MtreeCheck {
+        /// A specification in flat or hierarchical format
+        spec: PathBuf,
+    },
+    /// Serve queries on the collections kept in memory, see --socket
+    Daemon {
+        /// Stop the running daemon instead
+        #[arg(long)]
+        stop: bool,
+    },
+    /// Explore the indexed tree in an interactive shell, without reading
+    /// the collection itself
+    Shell {
+        /// Read the index from this database instead of PATH/.diffrust.db
+        #[arg(long)]
+        db: Option<PathBuf>,
+    },
+    /// Browse the collection in a terminal user interface
+    Tui {
+        /// Show the collection side by side with this one (path or @NAME)
+        other: Option<PathBuf>,
+    },
+    /// Manage the registry of named collections
+    Collection {
+        #[command(subcommand)]
+        action: CollectionAction,
+    },
+    /// Keep the collection up to date, reporting changes as they happen
+    Watch {
+        /// Milliseconds without changes before a burst of changes is applied
+        #[arg(long, default_value_t = 200)]
+        debounce: u64,
+        /// Append the changes to this file instead of standard output
+        #[arg(long)]
+        log: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CollectionAction {
+    /// Register the collection at PATH with a name
+    Add {
+        /// Name of the collection, by default its configured name or the
+        /// name of its directory
+        name: Option<String>,
+        /// Database of the collection, when not stored in PATH
+        #[arg(long)]
+        db: Option<PathBuf>,
+    },
+    /// Remove the collection at PATH (or @NAME) from the registry
+    Remove,
+    /// List the registered collections (PATH is not used)
+    List,
 }
 
 impl Config {
@@ -0,0 +1,314 @@
+//! Configuration of a collection, `.diffrust.conf` in its root.
+//!
+//! The configuration is a JSON object or, when it does not start with
+//! `{`, a TOML document with the same keys, all optional:
+//!
+//! ```toml
+//! version = 1              # schema version
+//! name = "photos"
+//! hash = "sha256"          # manifests and specifications (md5, sha1, sha256, sha512)
+//! ignore = ["*.tmp", "build/**"]
+//! symlinks = "record"      # record, skip or follow
+//! max_depth = 8            # 1 indexes the root content only
+//! min_size = 1024          # bytes, files only
+//! max_size = 4294967296
+//! skip_hidden = true       # names starting with '.'
+//! modified_after = "2023-01-01"   # files only, date or RFC 3339 time
+//! modified_before = "2024-01-01T00:00:00Z"
+//! extensions = ["mkv", "mp4"]     # files only, any when empty
+//! exclude_extensions = ["part"]
+//! one_filesystem = true    # do not cross filesystem boundaries
+//! format = "json"          # default output format (text, json, ndjson)
+//! ```
+//!
+//! Unknown keys and invalid values are errors reported with their line
+//! and column.
+
+use std::fmt;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::args::OutputFormat;
+use crate::core::filter::{Filter, Symlinks};
+use crate::core::hash::HashAlgorithm;
+use crate::core::model::Collection;
+
+/// Newest version of the schema, the one written by `init`
+pub const CONFIG_VERSION: u32 = 1;
+
+/// The configuration of a collection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CollectionConfig {
+    /// Version of the schema, the current one when missing
+    #[serde(default = "current_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Hash algorithm of manifests and specifications
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<HashAlgorithm>,
+    /// Ignore rules, see the `filter` module
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub symlinks: Symlinks,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub skip_hidden: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "date")]
+    pub modified_after: Option<SystemTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "date")]
+    pub modified_before: Option<SystemTime>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_extensions: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub one_filesystem: bool,
+    /// Output format when none is given on the command line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+}
+
+impl Default for CollectionConfig {
+    fn default() -> Self {
+        CollectionConfig {
+            version: CONFIG_VERSION,
+            name: None,
+            hash: None,
+            ignore: Vec::new(),
+            symlinks: Symlinks::default(),
+            max_depth: None,
+            min_size: None,
+            max_size: None,
+            skip_hidden: false,
+            modified_after: None,
+            modified_before: None,
+            extensions: Vec::new(),
+            exclude_extensions: Vec::new(),
+            one_filesystem: false,
+            format: None,
+        }
+    }
+}
+
+fn current_version() -> u32 {
+    CONFIG_VERSION
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// Times written as RFC 3339, read as dates or RFC 3339 times
+mod date {
+    use std::time::SystemTime;
+
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::core::filter::parse_date;
+
+    pub fn serialize<S: Serializer>(time: &Option<SystemTime>, s: S) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => {
+                let time: DateTime<Utc> = (*time).into();
+                s.serialize_str(&time.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+            }
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<SystemTime>, D::Error> {
+        let text = String::deserialize(d)?;
+        parse_date(&text).map(Some).map_err(D::Error::custom)
+    }
+}
+
+/// Only the version, read first so that newer configurations are
+/// reported as such rather than for their unknown keys
+#[derive(Deserialize)]
+struct Versioned {
+    #[serde(default = "current_version")]
+    version: u32,
+}
+
+/// A configuration that could not be parsed
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    /// 1-based line and column of the error, when tied to a position
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "line {line}, column {column}: {}", self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ConfigError {
+    fn new(message: impl Into<String>) -> Self {
+        ConfigError {
+            position: None,
+            message: message.into(),
+        }
+    }
+
+    fn from_json(e: serde_json::Error) -> Self {
+        let position = (e.line(), e.column());
+        let message = e.to_string();
+        // the position is reported separately
+        let suffix = format!(" at line {} column {}", position.0, position.1);
+        ConfigError {
+            position: Some(position).filter(|(line, _)| *line > 0),
+            message: message
+                .strip_suffix(&suffix)
+                .unwrap_or(&message)
+                .to_string(),
+        }
+    }
+
+    fn from_toml(e: toml::de::Error, content: &str) -> Self {
+        let position = e.span().map(|span| {
+            let before = &content[..span.start.min(content.len())];
+            let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+            (
+                before.matches('\n').count() + 1,
+                before[line_start..].chars().count() + 1,
+            )
+        });
+        ConfigError {
+            position,
+            message: e.message().trim_end().to_string(),
+        }
+    }
+}
+
+impl CollectionConfig {
+    /// Parses a configuration in JSON or, when content does not start
+    /// with `{`, TOML
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        let is_json = content.trim_start().starts_with('{');
+        let version = match is_json {
+            true => serde_json::from_str::<Versioned>(content).map_err(ConfigError::from_json),
+            false => {
+                toml::from_str::<Versioned>(content).map_err(|e| ConfigError::from_toml(e, content))
+            }
+        }?
+        .version;
+        if version == 0 || version > CONFIG_VERSION {
+            return Err(ConfigError::new(format!(
+                "unsupported version {version}, this version of {} reads version {CONFIG_VERSION}",
+                env!("CARGO_PKG_NAME")
+            )));
+        }
+        let config: CollectionConfig = match is_json {
+            true => serde_json::from_str(content).map_err(ConfigError::from_json),
+            false => toml::from_str(content).map_err(|e| ConfigError::from_toml(e, content)),
+        }?;
+        if config.max_depth == Some(0) {
+            return Err(ConfigError::new("max_depth must be at least 1"));
+        }
+        if let (Some(min), Some(max)) = (config.min_size, config.max_size) {
+            if min > max {
+                return Err(ConfigError::new(format!(
+                    "min_size {min} is larger than max_size {max}"
+                )));
+            }
+        }
+        if let (Some(after), Some(before)) = (config.modified_after, config.modified_before) {
+            if after >= before {
+                return Err(ConfigError::new(
+                    "modified_after is not earlier than modified_before",
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// The configuration as a JSON document
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("configurations are serializable") + "\n"
+    }
+
+    /// Configures collection, whose root must be set, with the settings
+    /// of the configuration
+    pub fn apply(&self, collection: &mut Collection) {
+        if let Some(name) = &self.name {
+            collection.name = name.clone();
+        }
+        collection.hash = self.hash;
+        let mut filter = Filter::new(&collection.root, &self.ignore);
+        filter.symlinks = self.symlinks;
+        filter.max_depth = self.max_depth;
+        filter.min_size = self.min_size;
+        filter.max_size = self.max_size;
+        filter.skip_hidden = self.skip_hidden;
+        filter.modified_after = self.modified_after;
+        filter.modified_before = self.modified_before;
+        filter.extensions = self.extensions.clone();
+        filter.exclude_extensions = self.exclude_extensions.clone();
+        filter.one_filesystem = self.one_filesystem;
+        collection.filter = filter;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_json_and_toml() {
+        let json = r#"{"name": "photos", "hash": "sha256", "ignore": ["*.tmp"],
+            "symlinks": "follow", "max_size": 1000, "format": "ndjson"}"#;
+        let toml = "name = 'photos'\nhash = 'SHA-256'\nignore = ['*.tmp']\n\
+            symlinks = 'follow'\nmax_size = 1000\nformat = 'ndjson'\n";
+        let filters = "modified_after = '2023-01-01T00:00:00Z'\nextensions = ['mkv']\n";
+        let with_filters = CollectionConfig::parse(filters).unwrap();
+        assert_eq!(
+            with_filters,
+            CollectionConfig::parse(&with_filters.to_json()).unwrap()
+        );
+        let config = CollectionConfig::parse(json).unwrap();
+        assert_eq!(config, CollectionConfig::parse(toml).unwrap());
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.hash, Some(HashAlgorithm::Sha256));
+        assert_eq!(config.format, Some(OutputFormat::Ndjson));
+        assert_eq!(config, CollectionConfig::parse(&config.to_json()).unwrap());
+        assert_eq!(
+            CollectionConfig::parse("").unwrap(),
+            CollectionConfig::default()
+        );
+    }
+
+    #[test]
+    fn report_errors() {
+        let error = |content| CollectionConfig::parse(content).unwrap_err();
+        let e = error("{\n  \"name\": \"a\",\n  \"colour\": 1\n}");
+        assert_eq!(e.position, Some((3, 10)));
+        assert!(e.message.starts_with("unknown field `colour`"), "{e}");
+        assert_eq!(error("{\"name\": }").position, Some((1, 10)));
+        let e = error("name = 'a'\nhash = 'md4'\n");
+        assert_eq!(e.position, Some((2, 8)));
+        assert!(e.message.contains("unknown hash algorithm 'md4'"), "{e}");
+        assert_eq!(error("max_depth = -1").position, Some((1, 13)));
+        assert_eq!(error("version = 2\nfuture = 1").position, None);
+        assert_eq!(
+            "min_size 2 is larger than max_size 1",
+            error("min_size = 2\nmax_size = 1").to_string()
+        );
+    }
+}
@@ -1,2 +1,19 @@
-pub mod model;
 pub mod algorithm;
+pub mod diff;
+pub mod dups;
+pub mod filter;
+pub mod hash;
+pub mod manifest;
+pub mod mime;
+pub mod model;
+pub mod mtree;
+pub mod name;
+pub mod ngram;
+pub mod progress;
+pub mod query;
+pub mod registry;
+pub mod search;
+pub mod stats;
+pub mod usage;
+pub mod verify;
+pub mod watch;
@@ -1,19 +1,134 @@
-use std::{ops::Range, path::Path, str::MatchIndices};
+//! Support algorithms for diffrust.
+//!
+//! Contains algorithms for string slices comparison:
+//! - similarity metrics between 0 and 1 selectable by name (`Metric`):
+//!   dice coefficient and jaccard index (with bigrams), levenshtein and
+//!   damerau ratios, jaro-winkler and token set ratio, all ignoring case
+//! - exact substring matching in name part of a Path
+//! - fuzzy subsequence matching with scores (`intalg` matches
+//!   `Introduction.to.Algorithms.pdf`)
+//! - natural ordering of names (`file2` before `file10`)
+//!
+//! This module should only use structs and types from the standard
+//! library so that it could be extracted from the project and used in
+//! other ones (or made a crate out of it). If the modules becomes too
+//! big, consider making a distinct crate within the project.
 
-/// Support algorithms for diffrust.
-///
-/// Contains algorithms for string slices comparison:
-/// - dice coefficient (with bigrams)
-/// - exact substring matching in name part of a Path
-///
-/// This module should only use structs and types from the standard
-/// library so that it could be extracted from the project and used in
-/// other ones (or made a crate out of it). If the modules becomes too
-/// big, consider making a distinct crate within the project.
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    fmt, iter,
+    ops::{Range, RangeInclusive},
+    path::Path,
+    str::FromStr,
+    str::MatchIndices,
+};
+
+/// Two consecutive characters, packed in an integer so that bigrams
+/// are compared without allocating strings
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bigram(u64);
+
+impl Bigram {
+    pub fn new(first: char, second: char) -> Self {
+        Bigram((u64::from(first) << 21) | u64::from(second))
+    }
+
+    /// The packed characters, 21 bits each
+    pub fn key(self) -> u64 {
+        self.0
+    }
+
+    fn chars(self) -> [char; 2] {
+        let char = |bits: u64| char::from_u32(bits as u32).unwrap_or_default();
+        [char(self.0 >> 21), char(self.0 & 0x1f_ffff)]
+    }
+}
 
-fn bigrams(s: &str) -> Vec<String> {
-    let v: Vec<char> = s.chars().collect();
-    v.windows(2).map(|pair| pair.iter().collect()).collect()
+impl fmt::Debug for Bigram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [first, second] = self.chars();
+        write!(f, "{:?}", format!("{first}{second}"))
+    }
+}
+
+impl PartialEq<&str> for Bigram {
+    fn eq(&self, other: &&str) -> bool {
+        let mut chars = other.chars();
+        chars.next() == Some(self.chars()[0])
+            && chars.next() == Some(self.chars()[1])
+            && chars.next().is_none()
+    }
+}
+
+fn bigrams(s: &str) -> Vec<Bigram> {
+    let mut v = vec![];
+    push_bigrams(s.chars(), &mut v);
+    v
+}
+
+fn push_bigrams(mut chars: impl Iterator<Item = char>, v: &mut Vec<Bigram>) {
+    if let Some(mut previous) = chars.next() {
+        for c in chars {
+            v.push(Bigram::new(previous, c));
+            previous = c;
+        }
+    }
+}
+
+/// Folds the case of s for caseless comparisons: lowercases it, also
+/// folding `ß` to `ss` and the final sigma `ς` to `σ` as full Unicode
+/// case folding does
+pub fn fold_case(s: &str) -> String {
+    folded(s).collect()
+}
+
+fn folded(s: &str) -> impl Iterator<Item = char> + '_ {
+    s.chars()
+        .flat_map(char::to_lowercase)
+        .flat_map(|c| match c {
+            'ß' => iter::repeat_n('s', 2),
+            'ς' => iter::repeat_n('σ', 1),
+            c => iter::repeat_n(c, 1),
+        })
+}
+
+fn folded_chars(s: &str) -> Vec<char> {
+    fold_case(s).chars().collect()
+}
+
+/// Bigrams of s after case folding, sorted (repeated ones included),
+/// as compared by `dice_coefficient` and `jaccard_index`
+pub fn sorted_bigrams(s: &str) -> Vec<Bigram> {
+    let mut v = bigrams(&fold_case(s));
+    v.sort_unstable();
+    v
+}
+
+/// Like `sorted_bigrams`, reusing v to compare many strings without
+/// allocating
+pub fn sorted_bigrams_into(s: &str, v: &mut Vec<Bigram>) {
+    v.clear();
+    push_bigrams(folded(s), v);
+    v.sort_unstable();
+}
+
+/// Number of elements common to the sorted x and y, counting repeated
+/// elements as many times as they are in both
+fn common<T: Ord>(x: &[T], y: &[T]) -> usize {
+    let (mut ix, mut iy, mut n) = (0, 0, 0);
+    while ix < x.len() && iy < y.len() {
+        match x[ix].cmp(&y[iy]) {
+            Ordering::Less => ix += 1,
+            Ordering::Greater => iy += 1,
+            Ordering::Equal => {
+                n += 1;
+                ix += 1;
+                iy += 1;
+            }
+        }
+    }
+    n
 }
 
 /// The *Dice coefficient* is used to compare strings for their similarity.
@@ -23,47 +138,461 @@ fn bigrams(s: &str) -> Vec<String> {
 /// `2*nt / (nx+nt)` where nt is the number of bigrams present on both strings,
 /// `nx` is the number of bigrams in `x` and `ny` the number of bigrams in `y`.
 pub fn dice_coefficient(x: &str, y: &str) -> f64 {
-    if x.len() < 2 || y.len() < 2 {
+    dice(&sorted_bigrams(x), &sorted_bigrams(y))
+}
+
+fn dice(vx: &[Bigram], vy: &[Bigram]) -> f64 {
+    if vx.is_empty() || vy.is_empty() {
         return 0.0;
     }
-    let mut vx = bigrams(x.to_lowercase().as_str());
-    let mut vy = bigrams(y.to_ascii_lowercase().as_str());
-    vx.sort();
-    vy.sort();
-    let mut nt = 0.0;
-    let mut ix = 0;
-    let mut iy = 0;
-    while ix < vx.len() {
-        let mut j = iy;
-        while j < vy.len() {
-            if vx[ix] == vy[j] {
-                nt += 1.0;
-                iy = j + 1;
+    2.0 * common(vx, vy) as f64 / (vx.len() + vy.len()) as f64
+}
+
+/// The *Jaccard index* of the bigrams of the two strings, the number of
+/// bigrams in both over the number of bigrams in either.
+pub fn jaccard_index(x: &str, y: &str) -> f64 {
+    jaccard(&sorted_bigrams(x), &sorted_bigrams(y))
+}
+
+fn jaccard(vx: &[Bigram], vy: &[Bigram]) -> f64 {
+    if vx.is_empty() || vy.is_empty() {
+        return 0.0;
+    }
+    let nt = common(vx, vy);
+    nt as f64 / (vx.len() + vy.len() - nt) as f64
+}
+
+/// Number of insertions, deletions and substitutions of characters
+/// turning x into y
+pub fn levenshtein(x: &str, y: &str) -> usize {
+    let (x, y) = (folded_chars(x), folded_chars(y));
+    let mut previous: Vec<usize> = (0..=y.len()).collect();
+    let mut current = vec![0; y.len() + 1];
+    for i in 1..=x.len() {
+        current[0] = i;
+        for j in 1..=y.len() {
+            let substitution = previous[j - 1] + usize::from(x[i - 1] != y[j - 1]);
+            current[j] = substitution.min(previous[j] + 1).min(current[j - 1] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[y.len()]
+}
+
+/// Like `levenshtein`, also counting the transposition of two adjacent
+/// characters as one edit (*optimal string alignment* distance)
+pub fn damerau_levenshtein(x: &str, y: &str) -> usize {
+    let (x, y) = (folded_chars(x), folded_chars(y));
+    let mut d = vec![vec![0; y.len() + 1]; x.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=y.len()).collect();
+    for i in 1..=x.len() {
+        for j in 1..=y.len() {
+            let cost = usize::from(x[i - 1] != y[j - 1]);
+            d[i][j] = (d[i - 1][j - 1] + cost)
+                .min(d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && x[i - 1] == y[j - 2] && x[i - 2] == y[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[x.len()][y.len()]
+}
+
+/// An edit distance as a similarity: 1 for equal strings, 0 when every
+/// character of the longest one is edited
+fn edit_ratio(x: &str, y: &str, distance: fn(&str, &str) -> usize) -> f64 {
+    let longest = x.chars().count().max(y.chars().count());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - distance(x, y) as f64 / longest as f64
+}
+
+/// The *Jaro-Winkler similarity*: the Jaro similarity, counting the
+/// characters matching within a window and their transpositions, raised
+/// for strings sharing a prefix (of up to 4 characters).
+pub fn jaro_winkler(x: &str, y: &str) -> f64 {
+    let (x, y) = (folded_chars(x), folded_chars(y));
+    let jaro = jaro(&x, &y);
+    let prefix = x.iter().zip(&y).take(4).take_while(|(a, b)| a == b).count();
+    jaro + prefix as f64 * 0.1 * (1.0 - jaro)
+}
+
+fn jaro(x: &[char], y: &[char]) -> f64 {
+    if x.is_empty() || y.is_empty() {
+        return if x.len() == y.len() { 1.0 } else { 0.0 };
+    }
+    let window = (x.len().max(y.len()) / 2).saturating_sub(1);
+    let mut matched = vec![false; y.len()];
+    let mut matches_x = vec![];
+    for (i, c) in x.iter().enumerate() {
+        let end = (i + window + 1).min(y.len());
+        for j in i.saturating_sub(window)..end {
+            if !matched[j] && y[j] == *c {
+                matched[j] = true;
+                matches_x.push(*c);
                 break;
             }
-            j += 1;
         }
-        ix += 1;
     }
-    2.0 * nt / (vx.len() + vy.len()) as f64
+    if matches_x.is_empty() {
+        return 0.0;
+    }
+    let matches_y = y.iter().zip(&matched).filter(|(_, m)| **m).map(|(c, _)| c);
+    let transpositions = matches_x.iter().zip(matches_y).filter(|(a, b)| a != b).count() / 2;
+    let m = matches_x.len() as f64;
+    (m / x.len() as f64 + m / y.len() as f64 + (m - transpositions as f64) / m) / 3.0
+}
+
+/// The *token set ratio*: the strings are split in words, ignoring their
+/// order and repetitions, and the words in both are compared (with the
+/// levenshtein ratio) with the words of either string, so that a string
+/// whose words are all in the other one is similar to it.
+pub fn token_set_ratio(x: &str, y: &str) -> f64 {
+    let tokens = |s: &str| -> BTreeSet<String> {
+        fold_case(s)
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    let (tx, ty) = (tokens(x), tokens(y));
+    if tx.is_empty() || ty.is_empty() {
+        return if tx.len() == ty.len() { 1.0 } else { 0.0 };
+    }
+    let join = |words: Vec<&String>| words.into_iter().cloned().collect::<Vec<_>>().join(" ");
+    let both = join(tx.intersection(&ty).collect());
+    let with = |only: Vec<&String>| match only.is_empty() {
+        true => both.clone(),
+        false => format!("{both} {}", join(only)).trim_start().to_string(),
+    };
+    let (all_x, all_y) = (with(tx.difference(&ty).collect()), with(ty.difference(&tx).collect()));
+    let ratio = |a: &str, b: &str| edit_ratio(a, b, levenshtein);
+    let mut best = ratio(&all_x, &all_y);
+    if !both.is_empty() {
+        best = best.max(ratio(&both, &all_x)).max(ratio(&both, &all_y));
+    }
+    best
+}
+
+/// A similarity between two strings, from 0 (different) to 1 (equal
+/// ignoring case)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    /// `dice_coefficient`
+    #[default]
+    Dice,
+    /// `jaccard_index`
+    Jaccard,
+    /// `levenshtein` distance over the length of the longest string
+    Levenshtein,
+    /// `damerau_levenshtein` distance over the length of the longest
+    /// string
+    Damerau,
+    /// `jaro_winkler`
+    JaroWinkler,
+    /// `token_set_ratio`
+    TokenSet,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 6] = [
+        Metric::Dice,
+        Metric::Jaccard,
+        Metric::Levenshtein,
+        Metric::Damerau,
+        Metric::JaroWinkler,
+        Metric::TokenSet,
+    ];
+
+    /// Name of the metric, as accepted by `from_str`
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Dice => "dice",
+            Metric::Jaccard => "jaccard",
+            Metric::Levenshtein => "levenshtein",
+            Metric::Damerau => "damerau",
+            Metric::JaroWinkler => "jaro-winkler",
+            Metric::TokenSet => "token-set",
+        }
+    }
+
+    /// Similarity of x and y, between 0 and 1
+    pub fn similarity(self, x: &str, y: &str) -> f64 {
+        match self {
+            Metric::Dice => dice_coefficient(x, y),
+            Metric::Jaccard => jaccard_index(x, y),
+            Metric::Levenshtein => edit_ratio(x, y, levenshtein),
+            Metric::Damerau => edit_ratio(x, y, damerau_levenshtein),
+            Metric::JaroWinkler => jaro_winkler(x, y),
+            Metric::TokenSet => token_set_ratio(x, y),
+        }
+    }
+
+    /// Least number of bigrams (after case folding, repeated ones
+    /// counted as `common` does) that strings of n and m bigrams at least
+    /// similarity similar share, None for the metrics not bounded by
+    /// common bigrams
+    pub fn min_common_bigrams(self, n: usize, m: usize, similarity: f64) -> Option<usize> {
+        // Dice is 2c/(n+m) and Jaccard c/(n+m-c) with c common bigrams
+        let total = (n + m) as f64;
+        let least = match self {
+            Metric::Dice => similarity * total / 2.0,
+            Metric::Jaccard => similarity * total / (1.0 + similarity),
+            _ => return None,
+        };
+        Some((least - 1e-9).ceil().max(0.0) as usize)
+    }
+
+    /// Numbers of bigrams of the strings at least similarity similar to
+    /// one of n bigrams, None for the metrics not bounded by them
+    pub fn bigram_counts(self, n: usize, similarity: f64) -> Option<RangeInclusive<usize>> {
+        // the common bigrams are at most the ones of the shorter string
+        let (least, most) = match self {
+            Metric::Dice => (similarity / (2.0 - similarity), (2.0 - similarity) / similarity),
+            Metric::Jaccard => (similarity, 1.0 / similarity),
+            _ => return None,
+        };
+        let n = n as f64;
+        let most = match similarity > 0.0 {
+            true => (most * n + 1e-9).floor() as usize,
+            false => usize::MAX,
+        };
+        Some(((least * n - 1e-9).ceil().max(0.0) as usize)..=most)
+    }
+
+    /// Similarity of the strings with the bigrams x and y (see
+    /// `sorted_bigrams`), None for the metrics not computed on bigrams
+    pub fn bigram_similarity(self, x: &[Bigram], y: &[Bigram]) -> Option<f64> {
+        match self {
+            Metric::Dice => Some(dice(x, y)),
+            Metric::Jaccard => Some(jaccard(x, y)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.to_ascii_lowercase().replace('_', "-");
+        Self::ALL
+            .into_iter()
+            .find(|m| m.name() == normalized)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Self::ALL.iter().map(|m| m.name()).collect();
+                format!("unknown metric '{s}', use {}", names.join(", "))
+            })
+    }
 }
 
 /// Returns all matches of the given pattern in the name part of the
-/// given path.
+/// given path, ignoring case. Ranges are byte offsets in the name as it
+/// is, also when lowercasing changes the length of a character (e.g.
+/// `İ` or the Kelvin sign).
 pub fn substrings_in_name(path: &Path, pattern: &str) -> Vec<Range<usize>> {
-    if let Some(name) = path.file_name() {
-        return name
-            .to_str()
-            .unwrap_or("")
-            .to_lowercase()
-            .match_indices(&pattern.to_lowercase())
-            .map(|(i, _)| Range {
-                start: i,
-                end: i + pattern.len(),
-            })
-            .collect();
+    let Some(name) = path.file_name() else {
+        return vec![];
+    };
+    let name = name.to_string_lossy();
+    let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+    // lowercase characters, with the range of the one they come from
+    let lower: Vec<(char, Range<usize>)> = name
+        .char_indices()
+        .flat_map(|(i, c)| c.to_lowercase().map(move |l| (l, i..i + c.len_utf8())))
+        .collect();
+    let mut matches = vec![];
+    let mut i = 0;
+    while !pattern.is_empty() && i + pattern.len() <= lower.len() {
+        let window = &lower[i..i + pattern.len()];
+        let (first, last) = (&window[0].1, &window[pattern.len() - 1].1);
+        // matches start and end with whole characters
+        let whole = (i == 0 || lower[i - 1].1 != *first)
+            && lower.get(i + pattern.len()).is_none_or(|(_, r)| r != last);
+        if whole && window.iter().map(|(c, _)| *c).eq(pattern.iter().copied()) {
+            matches.push(first.start..last.end);
+            i += pattern.len();
+        } else {
+            i += 1;
+        }
     }
-    vec![]
+    matches
+}
+
+/// Score of every matched character
+const SCORE_MATCH: i64 = 16;
+/// Penalty of the first unmatched character between two matched ones
+const GAP_START: i64 = -3;
+/// Penalty of every further unmatched character
+const GAP_EXTENSION: i64 = -1;
+/// Bonus of a match at the start of a path component
+const BONUS_PATH: i64 = 10;
+/// Bonus of a match at the start of a word, after `_`, `.`, ` `...
+const BONUS_BOUNDARY: i64 = 8;
+/// Bonus of a match at a camelCase hump or at the start of a number
+const BONUS_CAMEL: i64 = 7;
+/// Least bonus of a match following the previous one
+const BONUS_CONSECUTIVE: i64 = 4;
+/// The bonus of the first pattern character counts this many times
+const BONUS_FIRST_MULTIPLIER: i64 = 2;
+
+/// A fuzzy match of a pattern in a text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    /// Higher for better matches
+    pub score: i64,
+    /// Byte offsets in the text of the matched characters
+    pub positions: Vec<usize>,
+}
+
+/// Matches the characters of pattern, in order and ignoring case, in
+/// text as *fzf* does. Returns None when pattern is not a subsequence
+/// of text.
+///
+/// Every matched character scores, runs of consecutive matches and
+/// matches at the start of path components, words and camelCase humps
+/// score more, gaps between matches are penalised. Among the possible
+/// alignments the best scoring one is returned, found by dynamic
+/// programming in `O(n*m)` time and space for a text of `n` and a
+/// pattern of `m` characters.
+pub fn fuzzy_match(text: &str, pattern: &str) -> Option<FuzzyMatch> {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<(usize, char)> = text.char_indices().collect();
+    let (n, m) = (text.len(), pattern.len());
+    if m == 0 {
+        return Some(FuzzyMatch {
+            score: 0,
+            positions: vec![],
+        });
+    }
+    if m > n {
+        return None;
+    }
+    let bonus: Vec<i64> = (0..n).map(|j| bonus_at(&text, j)).collect();
+    // score[i][j] is the best score of pattern[..=i] with pattern[i]
+    // matched at text[j], from[i][j] the position of pattern[i - 1]
+    const NONE: i64 = i64::MIN / 2;
+    let mut score = vec![vec![NONE; n]; m];
+    let mut from = vec![vec![0; n]; m];
+    for i in 0..m {
+        // best previous match followed by a gap of one at least
+        let mut gap = (NONE, 0);
+        for j in i..n {
+            if i > 0 && j >= 2 && score[i - 1][j - 2] + GAP_START > gap.0 + GAP_EXTENSION {
+                gap = (score[i - 1][j - 2] + GAP_START, j - 2);
+            } else {
+                gap.0 += GAP_EXTENSION;
+            }
+            if !same_char(text[j].1, pattern[i]) {
+                continue;
+            }
+            if i == 0 {
+                score[0][j] = SCORE_MATCH + bonus[j] * BONUS_FIRST_MULTIPLIER;
+                continue;
+            }
+            let consecutive = score[i - 1][j - 1] + bonus[j].max(BONUS_CONSECUTIVE);
+            let gapped = gap.0 + bonus[j];
+            let (best, previous) = match consecutive >= gapped {
+                true => (consecutive, j - 1),
+                false => (gapped, gap.1),
+            };
+            if best > NONE / 2 {
+                score[i][j] = best + SCORE_MATCH;
+                from[i][j] = previous;
+            }
+        }
+    }
+    // the first of the best ending positions
+    let (mut j, best) = score[m - 1]
+        .iter()
+        .copied()
+        .enumerate()
+        .rev()
+        .max_by_key(|(_, s)| *s)?;
+    if best <= NONE / 2 {
+        return None;
+    }
+    let mut positions = vec![0; m];
+    for i in (0..m).rev() {
+        positions[i] = text[j].0;
+        j = from[i][j];
+    }
+    Some(FuzzyMatch {
+        score: best,
+        positions,
+    })
+}
+
+/// Bonus of a match at text[j], depending on the previous character
+fn bonus_at(text: &[(usize, char)], j: usize) -> i64 {
+    let c = text[j].1;
+    let Some(previous) = j.checked_sub(1).map(|k| text[k].1) else {
+        return BONUS_PATH;
+    };
+    match previous {
+        '/' | '\\' => BONUS_PATH,
+        _ if !previous.is_alphanumeric() && c.is_alphanumeric() => BONUS_BOUNDARY,
+        _ if previous.is_lowercase() && c.is_uppercase() => BONUS_CAMEL,
+        _ if !previous.is_numeric() && c.is_numeric() => BONUS_CAMEL,
+        _ => 0,
+    }
+}
+
+fn same_char(x: char, y: char) -> bool {
+    x == y || x.to_lowercase().eq(y.to_lowercase())
+}
+
+/// Compares two strings in *natural* order: runs of digits are compared
+/// by their numeric value and other characters case-insensitively.
+///
+/// Strings equal under this order are compared as plain strings so that
+/// the result is a total order.
+pub fn natural_cmp(x: &str, y: &str) -> Ordering {
+    let mut ix = x.chars().peekable();
+    let mut iy = y.chars().peekable();
+    loop {
+        match (ix.peek().copied(), iy.peek().copied()) {
+            (None, None) => return x.cmp(y),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(cx), Some(cy)) if cx.is_ascii_digit() && cy.is_ascii_digit() => {
+                let nx = take_digits(&mut ix);
+                let ny = take_digits(&mut iy);
+                let (tx, ty) = (nx.trim_start_matches('0'), ny.trim_start_matches('0'));
+                let ordering = tx.len().cmp(&ty.len()).then_with(|| tx.cmp(ty));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(cx), Some(cy)) => {
+                let ordering = cx.to_lowercase().cmp(cy.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                ix.next();
+                iy.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    digits
 }
 
 /// Splits a string into substrings at the given indexes.
@@ -157,6 +686,64 @@ mod tests {
         assert_eq!(2.0 / 5.0, dice_coefficient(x, y));
     }
 
+    #[test]
+    fn dice_ignores_case() {
+        // both strings are folded, not only the first one
+        assert_eq!(1.0, dice_coefficient("ÉTÉ", "été"));
+        assert_eq!(1.0, dice_coefficient("STRASSE", "straße"));
+        assert_eq!(0.0, dice_coefficient("é", "é"));
+    }
+
+    #[test]
+    fn metrics() {
+        let close = |m: Metric, x, y, expected: f64| {
+            let s = m.similarity(x, y);
+            assert!((s - expected).abs() < 1e-3, "{m} {x} {y}: {s}");
+        };
+        close(Metric::Jaccard, "abab", "acba", 1.0 / 5.0);
+        close(Metric::Levenshtein, "kitten", "sitting", 1.0 - 3.0 / 7.0);
+        close(Metric::Levenshtein, "", "", 1.0);
+        close(Metric::Damerau, "ca", "AC", 0.5);
+        close(Metric::Levenshtein, "ca", "AC", 0.0);
+        close(Metric::JaroWinkler, "MARTHA", "marhta", 0.961);
+        close(Metric::JaroWinkler, "DIXON", "DICKSONX", 0.813);
+        close(Metric::TokenSet, "holiday photos 2019", "2019 Holiday", 1.0);
+        close(Metric::TokenSet, "a b", "c", 0.0);
+        for m in Metric::ALL {
+            assert_eq!(Ok(m), m.name().parse());
+            close(m, "Straße.TXT", "strasse.txt", 1.0);
+        }
+        assert_eq!(Ok(Metric::JaroWinkler), "Jaro_Winkler".parse());
+        assert!("cosine".parse::<Metric>().is_err());
+    }
+
+    #[test]
+    fn common_bigrams_bound() {
+        assert_eq!(Some(3), Metric::Dice.min_common_bigrams(9, 3, 0.5));
+        assert_eq!(Some(5), Metric::Jaccard.min_common_bigrams(9, 5, 0.5));
+        assert_eq!(Some(7), Metric::Dice.min_common_bigrams(9, 18, 0.5));
+        assert_eq!(None, Metric::Levenshtein.min_common_bigrams(9, 9, 0.5));
+        assert_eq!(Some(3..=27), Metric::Dice.bigram_counts(9, 0.5));
+        assert_eq!(Some(5..=18), Metric::Jaccard.bigram_counts(9, 0.5));
+        // every pair at least half similar shares that many bigrams
+        let names = ["a.jpeg", "b.JPG", "abab", "acba", "Straße", "strasse", "photo_01.jpg"];
+        for m in [Metric::Dice, Metric::Jaccard] {
+            for x in names {
+                for y in names.into_iter().filter(|y| m.similarity(x, y) >= 0.5) {
+                    let (bx, by) = (sorted_bigrams(x), sorted_bigrams(y));
+                    let least = m.min_common_bigrams(bx.len(), by.len(), 0.5).unwrap();
+                    assert!(common(&bx, &by) >= least, "{m} {x} {y}");
+                    let mut into = vec![Bigram::new('z', 'z')];
+                    sorted_bigrams_into(x, &mut into);
+                    assert_eq!(bx, into);
+                    let counts = m.bigram_counts(bx.len(), 0.5).unwrap();
+                    assert!(counts.contains(&by.len()), "{m} {x} {y}");
+                    assert_eq!(Some(m.similarity(x, y)), m.bigram_similarity(&bx, &by));
+                }
+            }
+        }
+    }
+
     #[test]
     fn substring_in_path_name() {
         let path = PathBuf::from("/tmp/ab.txt");
@@ -171,16 +758,62 @@ mod tests {
         let path = PathBuf::from("books/Introduction.to.Algorithms.pdf");
         let pattern = "Introduction";
         assert!(
-            substrings_in_name(&path, pattern).len() > 0,
+            !substrings_in_name(&path, pattern).is_empty(),
             "Substring in path name not matched (mixed case test)"
-        )
+        );
+
+        // offsets in the name, not in its lowercase form
+        let path = PathBuf::from("/tmp/\u{130}stanbul \u{212a}elvin.txt");
+        assert_eq!(
+            substrings_in_name(&path, "kelvin"),
+            [Range {start: 10, end: 18}],
+        );
+        assert_eq!(substrings_in_name(&path, "STAN"), [Range {start: 2, end: 6}]);
+    }
+
+    #[test]
+    fn fuzzy_abbreviation() {
+        let m = fuzzy_match("Introduction.to.Algorithms.pdf", "intalg").unwrap();
+        assert_eq!(m.positions, vec![0, 1, 13, 16, 17, 18]);
+        assert!(fuzzy_match("Introduction.to.Algorithms.pdf", "intalgz").is_none());
+        assert!(fuzzy_match("ab", "abc").is_none());
+        assert_eq!(Some(0), fuzzy_match("x", "").map(|m| m.score));
+        // byte offsets of multibyte characters
+        assert_eq!(vec![0, 2], fuzzy_match("été", "ÉT").unwrap().positions);
+    }
+
+    #[test]
+    fn fuzzy_scores() {
+        let score = |text, pattern| fuzzy_match(text, pattern).unwrap().score;
+        // consecutive runs beat scattered characters
+        assert!(score("xalgx", "alg") > score("xaxlxg", "alg"));
+        // word boundaries and path components beat the middle of words
+        assert!(score("big_algae", "alg") > score("bigalgae", "alg"));
+        assert!(score("docs/alg", "alg") > score("docs_alg", "alg"));
+        assert!(score("fooBar", "b") > score("foobar", "b"));
+        // the best alignment is found, not the first one
+        let m = fuzzy_match("xa-b-ab", "ab").unwrap();
+        assert_eq!(vec![5, 6], m.positions);
+    }
+
+    #[test]
+    fn natural_order() {
+        let mut names = vec!["file10.txt", "File2.txt", "file1.txt", "file02.txt", "a"];
+        names.sort_by(|x, y| natural_cmp(x, y));
+        assert_eq!(
+            names,
+            vec!["a", "file1.txt", "File2.txt", "file02.txt", "file10.txt"]
+        );
+        assert_eq!(Ordering::Less, natural_cmp("img9", "img10"));
+        assert_eq!(Ordering::Greater, natural_cmp("b", "A"));
+        assert_eq!(Ordering::Less, natural_cmp("x", "x1"));
     }
 
     #[test]
     fn split_by_indexes() {
         let s = "/A/b/ccc.txt";
         let indexes = vec![0,2,4];
-        let splits = _split_at_indexes(&s, indexes);
+        let splits = _split_at_indexes(s, indexes);
         assert_eq!(splits.len(), 4, "Incorrect size of split result");
         assert_eq!(
             splits,
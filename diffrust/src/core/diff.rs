@@ -0,0 +1,167 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::core::algorithm::Metric;
+use crate::core::model::{Dir, File};
+
+/// A difference between two indexed trees
+#[derive(Debug, PartialEq)]
+pub enum Change<'a> {
+    /// File only present in the new tree
+    Added(&'a File),
+    /// File only present in the old tree
+    Removed(&'a File),
+    /// File present in both trees with different content
+    Modified { old: &'a File, new: &'a File },
+    /// File whose content moved to another path
+    Renamed { from: &'a File, to: &'a File },
+}
+
+impl Change<'_> {
+    /// Short name of the change kind
+    pub fn status(&self) -> &'static str {
+        match self {
+            Change::Added(_) => "added",
+            Change::Removed(_) => "removed",
+            Change::Modified { .. } => "modified",
+            Change::Renamed { .. } => "renamed",
+        }
+    }
+}
+
+/// Compares the files of two trees by their path relative to the tree
+/// root. A removed file whose content re-appears as an added file is
+/// reported as renamed. Changes are sorted by (new) relative path.
+pub fn diff<'a>(old: &'a Dir, new: &'a Dir) -> Vec<Change<'a>> {
+    diff_with(old, new, Metric::default())
+}
+
+/// Like `diff`, pairing an added file with the removed file of the same
+/// content whose name is the most similar by metric when there are
+/// several
+pub fn diff_with<'a>(old: &'a Dir, new: &'a Dir, metric: Metric) -> Vec<Change<'a>> {
+    let old_files = relative_files(old);
+    let new_files = relative_files(new);
+    let mut changes = vec![];
+    let mut removed: HashMap<md5::Digest, Vec<&File>> = HashMap::new();
+    let mut added = vec![];
+
+    for (path, o) in old_files.iter() {
+        match new_files.get(path) {
+            Some(n) if n.md5 != o.md5 => changes.push(Change::Modified { old: o, new: n }),
+            Some(_) => (),
+            None => removed.entry(o.md5).or_default().push(o),
+        }
+    }
+    for (path, n) in new_files.iter() {
+        if !old_files.contains_key(path) {
+            added.push(*n);
+        }
+    }
+    for n in added {
+        let renamed = removed.get_mut(&n.md5).and_then(|candidates| {
+            let name = name_of(n);
+            let (i, _) = candidates
+                .iter()
+                .map(|o| metric.similarity(&name_of(o), &name))
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(&b.1))?;
+            Some(candidates.remove(i))
+        });
+        match renamed {
+            Some(o) => changes.push(Change::Renamed { from: o, to: n }),
+            None => changes.push(Change::Added(n)),
+        }
+    }
+    changes.extend(removed.into_values().flatten().map(Change::Removed));
+
+    changes.sort_by(|a, b| sort_key(a, old, new).cmp(sort_key(b, old, new)));
+    changes
+}
+
+fn relative_files(dir: &Dir) -> BTreeMap<&Path, &File> {
+    dir.all_files()
+        .into_iter()
+        .map(|f| (f.path.strip_prefix(&dir.path).unwrap_or(&f.path), f))
+        .collect()
+}
+
+fn name_of(file: &File) -> String {
+    file.path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn sort_key<'a>(change: &Change<'a>, old: &Dir, new: &Dir) -> &'a Path {
+    match change {
+        Change::Added(f) | Change::Modified { new: f, .. } | Change::Renamed { to: f, .. } => {
+            f.path.strip_prefix(&new.path).unwrap_or(&f.path)
+        }
+        Change::Removed(f) => f.path.strip_prefix(&old.path).unwrap_or(&f.path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::core::model::fixtures::{dir, file, subdir};
+
+    #[test]
+    fn diff_identical() {
+        let old = dir("/old", vec![file("/old/a", b"a")]);
+        let new = dir("/new", vec![file("/new/a", b"a")]);
+        assert!(diff(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn diff_changes() {
+        let old = dir(
+            "/old",
+            vec![
+                file("/old/same", b"same"),
+                file("/old/changed", b"1"),
+                file("/old/gone", b"gone"),
+                file("/old/moved", b"moved"),
+            ],
+        );
+        let new = dir(
+            "/new",
+            vec![
+                file("/new/same", b"same"),
+                file("/new/changed", b"2"),
+                file("/new/created", b"created"),
+                subdir("/new/sub", vec![file("/new/sub/moved", b"moved")]),
+            ],
+        );
+        let statuses: Vec<&str> = diff(&old, &new).iter().map(|c| c.status()).collect();
+        assert_eq!(vec!["modified", "added", "removed", "renamed"], statuses);
+    }
+
+    #[test]
+    fn renames_by_similar_name() {
+        let old = dir(
+            "/old",
+            vec![
+                file("/old/holiday-2019.jpg", b"same"),
+                file("/old/copy of report.pdf", b"same"),
+            ],
+        );
+        let new = dir("/new", vec![file("/new/Report.pdf", b"same")]);
+        for metric in Metric::ALL {
+            let changes = diff_with(&old, &new, metric);
+            let renamed = changes.iter().find_map(|c| match c {
+                Change::Renamed { from, .. } => Some(from.path.clone()),
+                _ => None,
+            });
+            assert_eq!(
+                Some(PathBuf::from("/old/copy of report.pdf")),
+                renamed,
+                "{metric}"
+            );
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use crate::core::model::{Dir, File};
+
+/// A set of files sharing the same content
+#[derive(Debug, PartialEq)]
+pub struct DuplicateGroup<'a> {
+    pub md5: md5::Digest,
+    /// Size of each file in the group
+    pub size: u64,
+    /// Files with this content, sorted by path
+    pub files: Vec<&'a File>,
+}
+
+impl DuplicateGroup<'_> {
+    /// Bytes that could be reclaimed keeping a single copy
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.files.len() as u64 - 1)
+    }
+}
+
+/// Groups all files in dir (recursively) having the same md5, only
+/// groups with more than one file are returned, sorted by first path.
+pub fn find_duplicates(dir: &Dir) -> Vec<DuplicateGroup<'_>> {
+    let mut by_md5: HashMap<md5::Digest, Vec<&File>> = HashMap::new();
+    for file in dir.all_files() {
+        by_md5.entry(file.md5).or_default().push(file);
+    }
+    let mut groups: Vec<DuplicateGroup> = by_md5
+        .into_iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(md5, mut files)| {
+            files.sort_unstable();
+            DuplicateGroup {
+                md5,
+                size: files[0].size,
+                files,
+            }
+        })
+        .collect();
+    groups.sort_unstable_by(|a, b| a.files[0].cmp(b.files[0]));
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::core::model::fixtures::{dir, file, subdir};
+
+    #[test]
+    fn duplicates_across_dirs() {
+        let dir = dir(
+            "/r",
+            vec![
+                file("/r/b.txt", b"same"),
+                file("/r/unique.txt", b"unique"),
+                subdir("/r/sub", vec![file("/r/sub/a.txt", b"same")]),
+            ],
+        );
+        let groups = find_duplicates(&dir);
+        assert_eq!(1, groups.len(), "Unexpected number of groups");
+        let paths: Vec<&PathBuf> = groups[0].files.iter().map(|f| &f.path).collect();
+        assert_eq!(vec!["/r/b.txt", "/r/sub/a.txt"], paths);
+        assert_eq!(4, groups[0].wasted());
+    }
+
+    #[test]
+    fn no_duplicates() {
+        let dir = Dir {
+            content: vec![file("/a", b"a"), file("/b", b"b")],
+            ..Default::default()
+        };
+        assert!(find_duplicates(&dir).is_empty());
+    }
+}
@@ -0,0 +1,270 @@
+//! Selection of the entries indexed by a scan.
+//!
+//! Ignore rules are glob patterns (`*`, `?`, `[...]`, `{a,b}` and `**`).
+//! A pattern without `/` is matched against the name of every entry, at
+//! any depth (e.g. `*.tmp` or `node_modules`); a pattern with `/` is
+//! matched against the path relative to the collection root (e.g.
+//! `build/**` or `/cache`). Ignored directories are not entered.
+//!
+//! Entries can also be left out by depth, when hidden (names starting
+//! with `.`) or on another filesystem than their parent directory, files
+//! by size, modification time and extension. Symbolic links can be
+//! recorded, skipped or followed.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use glob_match::glob_match;
+use serde::{Deserialize, Serialize};
+
+/// What a scan does with symbolic links
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Symlinks {
+    /// Index the link itself, never following it
+    #[default]
+    Record,
+    /// Leave links out
+    Skip,
+    /// Index the target as if it was at the link, links that are broken
+    /// or lead back to a parent directory are recorded
+    Follow,
+}
+
+/// Decides which entries a scan indexes
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Filter {
+    /// Root the patterns with `/` and the depth are relative to
+    root: PathBuf,
+    ignore: Vec<String>,
+    pub symlinks: Symlinks,
+    /// Maximum depth of the indexed entries, 1 for the root content only
+    pub max_depth: Option<usize>,
+    /// Smallest size of the indexed files
+    pub min_size: Option<u64>,
+    /// Largest size of the indexed files
+    pub max_size: Option<u64>,
+    /// Leave out hidden entries
+    pub skip_hidden: bool,
+    /// Index only the files modified at or after this time
+    pub modified_after: Option<SystemTime>,
+    /// Index only the files modified before this time
+    pub modified_before: Option<SystemTime>,
+    /// Index only the files with one of these extensions (any when
+    /// empty), compared ignoring case
+    pub extensions: Vec<String>,
+    /// Leave out the files with one of these extensions
+    pub exclude_extensions: Vec<String>,
+    /// Do not enter directories on another filesystem than their parent
+    pub one_filesystem: bool,
+}
+
+impl Filter {
+    /// Filter leaving out the entries matching any ignore rule
+    pub fn new(root: &Path, ignore: &[String]) -> Self {
+        let ignore = ignore
+            .iter()
+            .map(|p| p.trim_end_matches('/').to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+        Filter {
+            root: root.to_path_buf(),
+            ignore,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the entry at path is indexed
+    pub fn accepts(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if self.skip_hidden && name.starts_with('.') {
+            return false;
+        }
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if self
+            .max_depth
+            .is_some_and(|depth| relative.components().count() > depth)
+        {
+            return false;
+        }
+        if self.ignore.is_empty() {
+            return true;
+        }
+        let relative = relative.to_string_lossy();
+        !self
+            .ignore
+            .iter()
+            .any(|pattern| match pattern.contains('/') {
+                true => glob_match(pattern.trim_start_matches('/'), &relative),
+                false => glob_match(pattern, &name),
+            })
+    }
+
+    /// Whether the entry at path is indexed by a scan of the root, that
+    /// is neither the entry nor one of its parents is rejected
+    pub fn includes(&self, path: &Path) -> bool {
+        path.ancestors()
+            .take_while(|p| p.starts_with(&self.root) && *p != self.root)
+            .all(|p| self.accepts(p))
+    }
+
+    /// Whether files are checked with `accepts_file`, so that their
+    /// metadata is only read when needed
+    pub fn checks_files(&self) -> bool {
+        self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.modified_after.is_some()
+            || self.modified_before.is_some()
+            || !self.extensions.is_empty()
+            || !self.exclude_extensions.is_empty()
+    }
+
+    /// Whether the file at path with metadata is indexed
+    pub fn accepts_file(&self, path: &Path, metadata: &fs::Metadata) -> bool {
+        let size = metadata.len();
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+        if self.modified_after.is_some() || self.modified_before.is_some() {
+            let Ok(modified) = metadata.modified() else {
+                return false;
+            };
+            if self.modified_after.is_some_and(|t| modified < t)
+                || self.modified_before.is_some_and(|t| modified >= t)
+            {
+                return false;
+            }
+        }
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        let listed = |list: &[String]| {
+            list.iter()
+                .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+        };
+        (self.extensions.is_empty() || listed(&self.extensions))
+            && !listed(&self.exclude_extensions)
+    }
+}
+
+/// Parses a size in bytes with an optional binary unit, e.g. `100`,
+/// `64K`, `1.5GiB` or `2 MB` (1 MB = 1024 KB)
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let text = s.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{s}'"))?;
+    let unit = unit.trim().to_ascii_uppercase();
+    // a single suffix, KiB or KB but not KBB
+    let prefix = unit
+        .strip_suffix("IB")
+        .filter(|p| !p.is_empty())
+        .or_else(|| unit.strip_suffix('B'))
+        .unwrap_or(&unit);
+    let exponent = match prefix {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => {
+            return Err(format!(
+                "invalid size unit in '{s}', use B, K, M, G, T or P"
+            ))
+        }
+    };
+    Ok((number * 1024f64.powi(exponent)).round() as u64)
+}
+
+/// Parses a date (`2023-01-31`, midnight local time) or an RFC 3339
+/// time (`2023-01-31T12:00:00Z`)
+pub fn parse_date(s: &str) -> Result<SystemTime, String> {
+    let text = s.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.into());
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| {
+            Local
+                .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+                .earliest()
+        })
+        .map(SystemTime::from)
+        .ok_or_else(|| format!("invalid date '{s}', use YYYY-MM-DD or RFC 3339"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignore_rules() {
+        let rules: Vec<String> = ["*.tmp", "node_modules/", "/build/**", "docs/*.pdf"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let filter = Filter::new(Path::new("/c"), &rules);
+        assert!(filter.accepts(Path::new("/c/a.txt")));
+        assert!(!filter.accepts(Path::new("/c/a.tmp")));
+        assert!(!filter.accepts(Path::new("/c/deep/er/b.tmp")));
+        assert!(!filter.accepts(Path::new("/c/web/node_modules")));
+        assert!(!filter.accepts(Path::new("/c/build/out/x")));
+        assert!(filter.accepts(Path::new("/c/src/build")));
+        assert!(!filter.accepts(Path::new("/c/docs/manual.pdf")));
+        assert!(filter.accepts(Path::new("/c/docs/old/manual.pdf")));
+        assert!(Filter::default().accepts(Path::new("/c/a.tmp")));
+        assert!(!filter.includes(Path::new("/c/web/node_modules/x/y.js")));
+        assert!(filter.includes(Path::new("/c/web/x/y.js")));
+    }
+
+    #[test]
+    fn depth_and_hidden() {
+        let filter = Filter {
+            max_depth: Some(2),
+            skip_hidden: true,
+            ..Filter::new(Path::new("/c"), &[])
+        };
+        assert!(filter.accepts(Path::new("/c/a/b")));
+        assert!(!filter.accepts(Path::new("/c/a/b/c")));
+        assert!(!filter.accepts(Path::new("/c/.git")));
+        assert!(!filter.includes(Path::new("/c/.git/x")));
+        assert!(!filter.checks_files());
+    }
+
+    #[test]
+    fn file_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.MKV");
+        fs::write(&path, b"0123456789").unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        let mut filter = Filter::new(dir.path(), &[]);
+        filter.extensions = vec![String::from("mkv"), String::from(".mp4")];
+        filter.min_size = Some(10);
+        filter.modified_after = Some(parse_date("2000-01-01").unwrap());
+        assert!(filter.checks_files());
+        assert!(filter.accepts_file(&path, &metadata));
+        assert!(!filter.accepts_file(&dir.path().join("a.txt"), &metadata));
+        filter.max_size = Some(9);
+        assert!(!filter.accepts_file(&path, &metadata));
+        filter.max_size = None;
+        filter.modified_before = Some(parse_date("2000-01-01T00:00:00Z").unwrap());
+        assert!(!filter.accepts_file(&path, &metadata));
+
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("1.5K"), Ok(1536));
+        assert_eq!(parse_size("2 MiB"), Ok(2 << 20));
+        assert_eq!(parse_size("1gb"), Ok(1 << 30));
+        assert!(parse_size("12 apples").is_err());
+        for invalid in ["1BB", "2KBBB", "1IBIB", "1IB"] {
+            assert!(parse_size(invalid).is_err(), "{invalid}");
+        }
+        assert_eq!(parse_size("3B"), Ok(3));
+        assert!(parse_date("31/01/2023").is_err());
+    }
+}
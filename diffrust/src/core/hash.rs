@@ -0,0 +1,179 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::Digest;
+
+use crate::core::model::compute_md5;
+
+/// Hash algorithms supported for checksums
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 4] = [
+        HashAlgorithm::Md5,
+        HashAlgorithm::Sha1,
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Sha512,
+    ];
+
+    /// Length in bytes of a digest produced by this algorithm
+    pub fn digest_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Md5 => 16,
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha512 => 64,
+        }
+    }
+
+    /// Guesses the algorithm from the length of a hex encoded digest
+    pub fn from_hex_len(len: usize) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.digest_len() * 2 == len)
+    }
+
+    /// Tag used in BSD style checksum lines (e.g. `SHA256`)
+    pub fn tag(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "MD5",
+            HashAlgorithm::Sha1 => "SHA1",
+            HashAlgorithm::Sha256 => "SHA256",
+            HashAlgorithm::Sha512 => "SHA512",
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.tag().to_lowercase())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s.to_ascii_uppercase().replace('-', "");
+        Self::ALL
+            .into_iter()
+            .find(|a| a.tag() == normalized)
+            .ok_or_else(|| format!("unknown hash algorithm '{s}'"))
+    }
+}
+
+impl Serialize for HashAlgorithm {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HashAlgorithm {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(D::Error::custom)
+    }
+}
+
+/// Computes the digest of the file at path with the given algorithm
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> Result<Vec<u8>, io::Error> {
+    match algorithm {
+        HashAlgorithm::Md5 => Ok(compute_md5(path)?.0.to_vec()),
+        HashAlgorithm::Sha1 => digest_file::<sha1::Sha1>(path),
+        HashAlgorithm::Sha256 => digest_file::<sha2::Sha256>(path),
+        HashAlgorithm::Sha512 => digest_file::<sha2::Sha512>(path),
+    }
+}
+
+fn digest_file<D: Digest>(path: &Path) -> Result<Vec<u8>, io::Error> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = D::new();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Encodes bytes as a lowercase hex string
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Decodes a hex string (any case), returns None if not valid hex
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn algorithm_from_str() {
+        assert_eq!(Ok(HashAlgorithm::Sha256), "sha256".parse());
+        assert_eq!(Ok(HashAlgorithm::Sha256), "SHA-256".parse());
+        assert_eq!(Ok(HashAlgorithm::Md5), "MD5".parse());
+        assert!("crc32".parse::<HashAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn algorithm_from_hex_len() {
+        assert_eq!(Some(HashAlgorithm::Md5), HashAlgorithm::from_hex_len(32));
+        assert_eq!(
+            Some(HashAlgorithm::Sha512),
+            HashAlgorithm::from_hex_len(128)
+        );
+        assert_eq!(None, HashAlgorithm::from_hex_len(10));
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let bytes = vec![0x00, 0xab, 0xff, 0x10];
+        assert_eq!("00abff10", to_hex(&bytes));
+        assert_eq!(Some(bytes), from_hex("00ABff10"));
+        assert_eq!(None, from_hex("abc"));
+        assert_eq!(None, from_hex("zz"));
+    }
+
+    #[test]
+    fn hash_known_values() {
+        let tempdir = tempdir().unwrap();
+        let path = tempdir.path().join("abc");
+        fs::write(&path, b"abc").unwrap();
+        assert_eq!(
+            "900150983cd24fb0d6963f7d28e17f72",
+            to_hex(&hash_file(&path, HashAlgorithm::Md5).unwrap())
+        );
+        assert_eq!(
+            "a9993e364706816aba3e25717850c26c9cd0d89d",
+            to_hex(&hash_file(&path, HashAlgorithm::Sha1).unwrap())
+        );
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            to_hex(&hash_file(&path, HashAlgorithm::Sha256).unwrap())
+        );
+    }
+}
@@ -0,0 +1,364 @@
+//! Checksum manifests compatible with GNU coreutils (`md5sum`,
+//! `sha256sum`, ...) and BSD tagged output (`md5`, `sha256`, or the
+//! coreutils `--tag` option).
+//!
+//! Manifests store paths relative to the collection root, so they can be
+//! checked with `md5sum -c` from within the root directory.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::core::hash::{from_hex, hash_file, to_hex, HashAlgorithm};
+use crate::core::model::{Dir, ScanError};
+use crate::core::name;
+
+/// Line format of a checksum manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// `<hex>  <path>` as written by `md5sum` and friends
+    Gnu,
+    /// `MD5 (<path>) = <hex>` as written by BSD tools
+    Bsd,
+}
+
+impl fmt::Display for ManifestFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ManifestFormat::Gnu => write!(f, "gnu"),
+            ManifestFormat::Bsd => write!(f, "bsd"),
+        }
+    }
+}
+
+impl FromStr for ManifestFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gnu" | "coreutils" => Ok(ManifestFormat::Gnu),
+            "bsd" | "tag" => Ok(ManifestFormat::Bsd),
+            _ => Err(format!("unknown manifest format '{s}'")),
+        }
+    }
+}
+
+/// A single checksum line of a manifest
+#[derive(Debug, PartialEq)]
+pub struct ManifestEntry {
+    /// Path of the file, relative to the checked directory
+    pub path: PathBuf,
+    pub algorithm: HashAlgorithm,
+    pub digest: Vec<u8>,
+}
+
+impl ManifestEntry {
+    /// Formats the entry as a manifest line (without line terminator).
+    ///
+    /// Names are written byte by byte, like coreutils. Names containing a
+    /// backslash or a newline are escaped and the line is prefixed with a
+    /// backslash.
+    pub fn to_line(&self, format: ManifestFormat) -> Vec<u8> {
+        let name = name::to_bytes(self.path.as_os_str());
+        let escaped = name.iter().any(|b| matches!(b, b'\\' | b'\n' | b'\r'));
+        let name = if escaped { escape(name) } else { name.to_vec() };
+        let prefix = if escaped { "\\" } else { "" };
+        let (head, tail) = match format {
+            ManifestFormat::Gnu => (format!("{prefix}{}  ", to_hex(&self.digest)), String::new()),
+            ManifestFormat::Bsd => (
+                format!("{prefix}{} (", self.algorithm.tag()),
+                format!(") = {}", to_hex(&self.digest)),
+            ),
+        };
+        [head.as_bytes(), &name, tail.as_bytes()].concat()
+    }
+
+    /// Parses a line in either GNU or BSD format.
+    ///
+    /// Returns None if the line is not a valid checksum line.
+    pub fn parse(line: &(impl AsRef<[u8]> + ?Sized)) -> Option<ManifestEntry> {
+        let line = line.as_ref();
+        let (escaped, line) = match line.strip_prefix(b"\\") {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (algorithm, hex, name) = parse_bsd(line).or_else(|| parse_gnu(line))?;
+        let digest = from_hex(std::str::from_utf8(hex).ok()?)?;
+        if digest.len() != algorithm.digest_len() || name.is_empty() {
+            return None;
+        }
+        let name = if escaped {
+            unescape(name)?
+        } else {
+            name.to_vec()
+        };
+        Some(ManifestEntry {
+            path: PathBuf::from(name::from_bytes(name)),
+            algorithm,
+            digest,
+        })
+    }
+}
+
+fn parse_bsd(line: &[u8]) -> Option<(HashAlgorithm, &[u8], &[u8])> {
+    let i = find(line, b" (")?;
+    let (tag, rest) = (&line[..i], &line[i + 2..]);
+    let j = rfind(rest, b") = ")?;
+    let (name, hex) = (&rest[..j], &rest[j + 4..]);
+    let algorithm = std::str::from_utf8(tag).ok()?.parse().ok()?;
+    Some((algorithm, hex.trim_ascii_end(), name))
+}
+
+fn parse_gnu(line: &[u8]) -> Option<(HashAlgorithm, &[u8], &[u8])> {
+    let i = find(line, b" ")?;
+    let (hex, rest) = (&line[..i], &line[i + 1..]);
+    let algorithm = HashAlgorithm::from_hex_len(hex.len())?;
+    // two spaces (text mode), space and star (binary mode) or, as
+    // written by `md5 -r`, a single space
+    let name = rest
+        .strip_prefix(b" ")
+        .or_else(|| rest.strip_prefix(b"*"))
+        .unwrap_or(rest);
+    Some((algorithm, hex, name))
+}
+
+/// Position of the first occurrence of needle in haystack
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Position of the last occurrence of needle in haystack
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+fn escape(name: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(name.len() + 2);
+    for &b in name {
+        match b {
+            b'\\' => result.extend_from_slice(b"\\\\"),
+            b'\n' => result.extend_from_slice(b"\\n"),
+            b'\r' => result.extend_from_slice(b"\\r"),
+            b => result.push(b),
+        }
+    }
+    result
+}
+
+fn unescape(name: &[u8]) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(name.len());
+    let mut bytes = name.iter();
+    while let Some(&b) = bytes.next() {
+        if b != b'\\' {
+            result.push(b);
+            continue;
+        }
+        match bytes.next()? {
+            b'\\' => result.push(b'\\'),
+            b'n' => result.push(b'\n'),
+            b'r' => result.push(b'\r'),
+            _ => return None,
+        }
+    }
+    Some(result)
+}
+
+/// A parsed manifest, keeping track of lines that could not be parsed
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+    /// Line numbers (1-based) of improperly formatted lines
+    pub malformed: Vec<usize>,
+}
+
+impl Manifest {
+    /// Parses a manifest skipping empty lines and `#` comments
+    pub fn parse(content: &(impl AsRef<[u8]> + ?Sized)) -> Self {
+        let mut manifest = Manifest::default();
+        for (i, line) in content.as_ref().split(|b| *b == b'\n').enumerate() {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.trim_ascii().is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+            match ManifestEntry::parse(line) {
+                Some(entry) => manifest.entries.push(entry),
+                None => manifest.malformed.push(i + 1),
+            }
+        }
+        manifest
+    }
+
+    /// Builds the manifest of all files in dir, with paths relative to
+    /// root. Md5 digests are taken from the index, other algorithms
+    /// require re-hashing the files.
+    pub fn from_dir(dir: &Dir, root: &Path, algorithm: HashAlgorithm) -> Result<Self, ScanError> {
+        let mut files = dir.all_files();
+        files.sort_unstable();
+        let mut manifest = Manifest::default();
+        for file in files {
+            let digest = match algorithm {
+                HashAlgorithm::Md5 => file.md5.0.to_vec(),
+                _ => hash_file(&file.path, algorithm).map_err(|e| ScanError::new(&file.path, e))?,
+            };
+            manifest.entries.push(ManifestEntry {
+                path: file
+                    .path
+                    .strip_prefix(root)
+                    .unwrap_or(&file.path)
+                    .to_path_buf(),
+                algorithm,
+                digest,
+            });
+        }
+        Ok(manifest)
+    }
+
+    /// Renders the manifest, one line per entry
+    pub fn render(&self, format: ManifestFormat) -> Vec<u8> {
+        let mut rendered = vec![];
+        for entry in self.entries.iter() {
+            rendered.extend(entry.to_line(format));
+            rendered.push(b'\n');
+        }
+        rendered
+    }
+
+    /// Checks every entry against the files found under root
+    pub fn check(&self, root: &Path) -> Vec<(&ManifestEntry, CheckStatus)> {
+        self.entries
+            .iter()
+            .map(|e| (e, check_entry(root, e)))
+            .collect()
+    }
+}
+
+/// Result of checking a manifest line, as reported by `md5sum -c`
+#[derive(Debug, PartialEq)]
+pub enum CheckStatus {
+    Ok,
+    Failed,
+    Missing,
+}
+
+fn check_entry(root: &Path, entry: &ManifestEntry) -> CheckStatus {
+    match hash_file(&root.join(&entry.path), entry.algorithm) {
+        Ok(digest) if digest == entry.digest => CheckStatus::Ok,
+        Ok(_) => CheckStatus::Failed,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => CheckStatus::Missing,
+        Err(_) => CheckStatus::Failed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::core::model::Collection;
+
+    const ABC_MD5: &str = "900150983cd24fb0d6963f7d28e17f72";
+
+    #[test]
+    fn parse_gnu_line() {
+        let entry = ManifestEntry::parse(&format!("{ABC_MD5}  dir/a b.txt")).unwrap();
+        assert_eq!(entry.path, PathBuf::from("dir/a b.txt"));
+        assert_eq!(entry.algorithm, HashAlgorithm::Md5);
+        assert_eq!(to_hex(&entry.digest), ABC_MD5);
+
+        let binary = ManifestEntry::parse(&format!("{ABC_MD5} *a.txt")).unwrap();
+        assert_eq!(binary.path, PathBuf::from("a.txt"));
+    }
+
+    #[test]
+    fn parse_bsd_line() {
+        let line = "SHA1 (a (1).txt) = a9993e364706816aba3e25717850c26c9cd0d89d";
+        let entry = ManifestEntry::parse(line).unwrap();
+        assert_eq!(entry.path, PathBuf::from("a (1).txt"));
+        assert_eq!(entry.algorithm, HashAlgorithm::Sha1);
+    }
+
+    #[test]
+    fn parse_invalid_lines() {
+        assert!(ManifestEntry::parse("not a checksum").is_none());
+        assert!(ManifestEntry::parse("abcd  a.txt").is_none());
+        // digest length not matching the tag
+        assert!(ManifestEntry::parse(&format!("SHA256 (a.txt) = {ABC_MD5}")).is_none());
+    }
+
+    #[test]
+    fn escaped_names_round_trip() {
+        let entry = ManifestEntry {
+            path: PathBuf::from("new\nline\\name"),
+            algorithm: HashAlgorithm::Md5,
+            digest: from_hex(ABC_MD5).unwrap(),
+        };
+        for format in [ManifestFormat::Gnu, ManifestFormat::Bsd] {
+            let line = entry.to_line(format);
+            assert!(line.starts_with(b"\\"), "Escaped line without prefix");
+            assert!(!line.contains(&b'\n'));
+            assert_eq!(Some(&entry), ManifestEntry::parse(&line).as_ref());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_round_trip() {
+        let entry = ManifestEntry {
+            path: PathBuf::from(name::from_bytes(b"caf\xe9.txt".to_vec())),
+            algorithm: HashAlgorithm::Md5,
+            digest: from_hex(ABC_MD5).unwrap(),
+        };
+        let line = entry.to_line(ManifestFormat::Gnu);
+        assert!(
+            line.ends_with(b"  caf\xe9.txt"),
+            "Name not written verbatim"
+        );
+        assert_eq!(Some(&entry), ManifestEntry::parse(&line).as_ref());
+    }
+
+    #[test]
+    fn manifest_skips_comments_and_counts_malformed() {
+        let content = format!("# comment\n\n{ABC_MD5}  a.txt\ngarbage\n");
+        let manifest = Manifest::parse(&content);
+        assert_eq!(1, manifest.entries.len());
+        assert_eq!(vec![4], manifest.malformed);
+    }
+
+    #[test]
+    fn export_and_check() {
+        let tempdir = tempdir().unwrap();
+        fs::create_dir(tempdir.path().join("sub")).unwrap();
+        fs::write(tempdir.path().join("a.txt"), b"abc").unwrap();
+        fs::write(tempdir.path().join("sub").join("b.txt"), b"b").unwrap();
+        let mut collection = Collection::from(tempdir.path());
+        collection.scan().unwrap();
+        let dir = collection.root_dir.as_ref().unwrap();
+
+        let manifest = Manifest::from_dir(dir, tempdir.path(), HashAlgorithm::Md5).unwrap();
+        let rendered = manifest.render(ManifestFormat::Gnu);
+        assert!(rendered.starts_with(format!("{ABC_MD5}  a.txt\n").as_bytes()));
+
+        fs::write(tempdir.path().join("a.txt"), b"abd").unwrap();
+        fs::remove_file(tempdir.path().join("sub").join("b.txt")).unwrap();
+        let parsed = Manifest::parse(&rendered);
+        let statuses: Vec<CheckStatus> = parsed
+            .check(tempdir.path())
+            .into_iter()
+            .map(|(_, s)| s)
+            .collect();
+        assert_eq!(vec![CheckStatus::Failed, CheckStatus::Missing], statuses);
+    }
+
+    #[test]
+    fn check_sha256_bsd() {
+        let tempdir = tempdir().unwrap();
+        fs::write(tempdir.path().join("a.txt"), b"abc").unwrap();
+        let line = "SHA256 (a.txt) = \
+            ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let manifest = Manifest::parse(line);
+        assert_eq!(CheckStatus::Ok, manifest.check(tempdir.path())[0].1);
+    }
+}
@@ -0,0 +1,68 @@
+//! Detection of the MIME type of indexed files.
+//!
+//! The type is detected from the magic number at the beginning of the
+//! content and, when that is not conclusive (e.g. plain text formats),
+//! guessed from the file extension.
+
+use std::path::Path;
+
+/// MIME type of empty files, as reported by `file --mime-type`
+pub const EMPTY: &str = "inode/x-empty";
+/// MIME type of content that could not be recognized
+pub const UNKNOWN: &str = "application/octet-stream";
+
+/// Number of leading bytes needed to detect the type
+pub const HEADER_LEN: usize = 8192;
+
+/// Detects the MIME type of the file at path from header, the first
+/// bytes of its content
+pub fn detect(path: &Path, header: &[u8]) -> &'static str {
+    if header.is_empty() {
+        return EMPTY;
+    }
+    if let Some(kind) = infer::get(header) {
+        return kind.mime_type();
+    }
+    guess(path).unwrap_or(if is_text(header) {
+        "text/plain"
+    } else {
+        UNKNOWN
+    })
+}
+
+/// Guesses the MIME type from the extension of path only
+pub fn guess(path: &Path) -> Option<&'static str> {
+    mime_guess::from_path(path).first_raw()
+}
+
+/// Whether header looks like text: valid UTF-8 (possibly truncated in
+/// the middle of a character) without NUL bytes
+fn is_text(header: &[u8]) -> bool {
+    if header.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(header) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_types() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!("image/png", detect(Path::new("noext"), png));
+        // content wins over the extension
+        assert_eq!("image/png", detect(Path::new("a.txt"), png));
+        assert_eq!("text/csv", detect(Path::new("a.csv"), b"a,b\n1,2\n"));
+        assert_eq!(
+            "text/plain",
+            detect(Path::new("README"), "città\n".as_bytes())
+        );
+        assert_eq!(UNKNOWN, detect(Path::new("blob"), b"\x00\x01\x02\xff"));
+        assert_eq!(EMPTY, detect(Path::new("a.txt"), b""));
+    }
+}
@@ -1,32 +1,156 @@
-use md5;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::io::Read;
+use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use crate::core::algorithm::natural_cmp;
+use crate::core::filter::{Filter, Symlinks};
+use crate::core::hash::HashAlgorithm;
+use crate::core::mime;
+use crate::core::name;
+use crate::core::progress::{self, ScanObserver};
+
+/// Name of the collection configuration file stored in the root
+pub const CONFIG_FILE_NAME: &str = ".diffrust.conf";
+/// Name of the default collection database stored in the root
+pub const DB_FILE_NAME: &str = ".diffrust.db";
+/// Name of the index of the names in the default database, see
+/// `core::ngram`
+pub const INDEX_FILE_NAME: &str = ".diffrust.idx";
+/// Appended to the name of a file being written, see `write_replacing`
+const TEMP_SUFFIX: &str = ".tmp";
 
 /// The types of content that a directory can contain
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum ContentType {
     /// Directory content
     ContentDir(Dir),
     /// File content
     ContentFile(File),
-    /// Hard or symbolic link content (not yet implemented)
-    ContentLink,
+    /// Symbolic link content
+    ContentLink(Link),
+}
+
+impl ContentType {
+    /// Path of the content
+    pub fn path(&self) -> &Path {
+        match self {
+            ContentType::ContentDir(d) => &d.path,
+            ContentType::ContentFile(f) => &f.path,
+            ContentType::ContentLink(l) => &l.path,
+        }
+    }
+
+    /// Last modification time of the content
+    pub fn modified(&self) -> SystemTime {
+        match self {
+            ContentType::ContentDir(d) => d.modified,
+            ContentType::ContentFile(f) => f.modified,
+            ContentType::ContentLink(l) => l.modified,
+        }
+    }
+
+    /// Size of the content, for directories the size of all files within
+    pub fn size(&self) -> u64 {
+        match self {
+            ContentType::ContentDir(d) => d.total_size(),
+            ContentType::ContentFile(f) => f.size,
+            ContentType::ContentLink(_) => 0,
+        }
+    }
+}
+
+/// Keys for sorting the content of a directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortKey {
+    /// Full path, in plain byte order
+    Path,
+    /// Name, in natural order (`file2` before `file10`)
+    #[default]
+    Name,
+    /// Size, largest first
+    Size,
+    /// Modification time, newest first
+    Modified,
+}
+
+impl SortKey {
+    /// Compares two contents by this key, ties are broken by path
+    pub fn compare(&self, a: &ContentType, b: &ContentType) -> Ordering {
+        let ordering = match self {
+            SortKey::Path => Ordering::Equal,
+            SortKey::Name => natural_cmp(
+                &a.path().file_name().unwrap_or_default().to_string_lossy(),
+                &b.path().file_name().unwrap_or_default().to_string_lossy(),
+            ),
+            SortKey::Size => b.size().cmp(&a.size()),
+            SortKey::Modified => b.modified().cmp(&a.modified()),
+        };
+        ordering.then_with(|| a.path().cmp(b.path()))
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SortKey::Path => "path",
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "mtime",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(SortKey::Path),
+            "name" => Ok(SortKey::Name),
+            "size" => Ok(SortKey::Size),
+            "mtime" | "time" | "modified" => Ok(SortKey::Modified),
+            _ => Err(format!("unknown sort key '{s}'")),
+        }
+    }
 }
 
 /// A directory that is indexed by diffrust
-#[derive(Debug)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Collection {
     /// Collection name
     pub name: String,
     /// The root path of the collection
+    #[serde(with = "name::serde_path")]
     pub root: PathBuf,
-    /// Path to db file. If None path is root/.diffrust.conf
+    /// Path to db file. If None path is root/.diffrust.db
+    #[serde(skip)]
     pub db: Option<PathBuf>,
     /// The root Dir struct. Can be None if not present or initialized.
     pub root_dir: Option<Dir>,
+    /// Files hashed by a scan that did not complete, None if the last
+    /// scan completed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checkpoint: Option<Checkpoint>,
+    /// Root of the enclosing collection when root is one of its
+    /// subdirectories, None when root is the collection root
+    #[serde(skip)]
+    pub enclosing: Option<PathBuf>,
+    /// Entries indexed by scans (from the ignore rules of the
+    /// configuration)
+    #[serde(skip)]
+    pub filter: Filter,
+    /// Configured hash algorithm of exported checksums
+    #[serde(skip)]
+    pub hash: Option<HashAlgorithm>,
 }
 
 impl Collection {
@@ -46,14 +170,11 @@ impl Collection {
     /// assert_eq!(c.root, PathBuf::new());
     /// assert!(c.db.is_none());
     /// assert!(c.root_dir.is_none());
+    /// assert!(c.checkpoint.is_none());
+    /// assert!(c.enclosing.is_none());
     /// ```
     pub fn new() -> Self {
-        Collection {
-            name: String::new(),
-            root: PathBuf::new(),
-            db: None,
-            root_dir: None,
-        }
+        Collection::from(Path::new(""))
     }
 
     pub fn from(path: &Path) -> Self {
@@ -62,65 +183,401 @@ impl Collection {
             root: PathBuf::from(path),
             db: None,
             root_dir: None,
+            checkpoint: None,
+            enclosing: None,
+            filter: Filter::default(),
+            hash: None,
         }
     }
 
+    /// Returns the path of the database file for this collection, for a
+    /// subdirectory the one of the enclosing collection
+    pub fn db_path(&self) -> PathBuf {
+        self.db.clone().unwrap_or_else(|| {
+            self.enclosing
+                .as_ref()
+                .unwrap_or(&self.root)
+                .join(DB_FILE_NAME)
+        })
+    }
+
+    /// Returns the path of the name index saved with the database
+    pub fn index_path(&self) -> PathBuf {
+        self.db_path().with_extension("idx")
+    }
+
+    /// Opens the whole enclosing collection instead of a subdirectory,
+    /// e.g. to scan it into its database
+    pub fn widen(&mut self) {
+        if let Some(root) = self.enclosing.take() {
+            self.root = root;
+            self.root_dir = None;
+        }
+    }
+
+    /// Writes the collection (including the scanned tree) to its
+    /// database, which is replaced only once completely written
     pub fn save(&self) -> Result<(), std::io::Error> {
-        // let config_path = self.db.as_ref().unwrap_or(&self.root.join(".diffrust.conf"));
-        // let json = serde_json::to_string(self)?;
-        // fs::write(config_path, json)
-        Ok(())
+        write_replacing(&self.db_path(), |out| Ok(serde_json::to_writer(out, self)?))
     }
 
-    pub fn scan(&mut self) -> Result<(), std::io::Error> {
-        let dir = self.root_dir.get_or_insert_with(|| Dir {
+    /// Loads the scanned tree (and checkpoint, if any) stored in the
+    /// database of this collection. For a subdirectory of an enclosing
+    /// collection only its subtree is loaded (None if it was not indexed).
+    ///
+    /// Returns `Ok(false)` when no database exists yet, in this case the
+    /// collection is left untouched.
+    pub fn load(&mut self) -> Result<bool, std::io::Error> {
+        let db = self.db_path();
+        if !db.is_file() {
+            return Ok(false);
+        }
+        let stored: Collection = serde_json::from_str(&fs::read_to_string(&db)?)?;
+        self.root_dir = match &self.enclosing {
+            // the subtree, with paths as opened (stored paths are the
+            // ones given to scan)
+            Some(enclosing) => {
+                let relative = self.root.strip_prefix(enclosing).unwrap_or(&self.root);
+                stored
+                    .root_dir
+                    .and_then(|d| d.into_subdir(relative))
+                    .map(|mut d| {
+                        d.rebase(&self.root);
+                        d
+                    })
+            }
+            None => stored.root_dir,
+        };
+        self.checkpoint = stored.checkpoint;
+        Ok(true)
+    }
+
+    /// Scans the root directory, see `Dir::scan`
+    pub fn scan(&mut self) -> Result<Vec<ScanError>, std::io::Error> {
+        self.scan_with(&mut ())
+    }
+
+    /// Scans the root directory reporting progress to observer
+    pub fn scan_with(
+        &mut self,
+        observer: &mut dyn ScanObserver,
+    ) -> Result<Vec<ScanError>, std::io::Error> {
+        self.scan_known(observer, &HashMap::new())
+    }
+
+    /// Scans the root directory like `scan_with`, taking the hashes of
+    /// the files in the checkpoint that did not change (same size and
+    /// modification time) instead of hashing them again. The checkpoint
+    /// is consumed.
+    pub fn resume_with(
+        &mut self,
+        observer: &mut dyn ScanObserver,
+    ) -> Result<Vec<ScanError>, std::io::Error> {
+        let known = self
+            .checkpoint
+            .take()
+            .map(Checkpoint::into_index)
+            .unwrap_or_default();
+        self.scan_known(observer, &known)
+    }
+
+    /// Moves the files of the loaded tree into the checkpoint, so that
+    /// `resume_with` only hashes the files changed since it was stored
+    pub fn reuse_hashes(&mut self) {
+        if let Some(dir) = self.root_dir.take() {
+            let checkpoint = self.checkpoint.get_or_insert_with(Checkpoint::default);
+            checkpoint
+                .files
+                .extend(dir.all_files().into_iter().cloned());
+        }
+    }
+
+    fn scan_known(
+        &mut self,
+        observer: &mut dyn ScanObserver,
+        known: &HashMap<PathBuf, File>,
+    ) -> Result<Vec<ScanError>, std::io::Error> {
+        observer.started(&self.root, &self.filter);
+        let dir = self.root_dir.insert(Dir {
             path: self.root.clone(),
-            content: Vec::new(),
+            ..Default::default()
         });
-        dir.scan()
+        let result = dir.scan_known(observer, known, &self.filter);
+        observer.finished();
+        result
+    }
+}
+
+/// Files hashed by a scan that was interrupted, stored in the database
+/// so that the scan can be resumed without hashing them again
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub files: Vec<File>,
+}
+
+impl Checkpoint {
+    /// Returns the files indexed by path
+    fn into_index(self) -> HashMap<PathBuf, File> {
+        self.files
+            .into_iter()
+            .map(|f| (f.path.clone(), f))
+            .collect()
     }
 }
 
 /// An indexed directory
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Dir {
     /// Absolute path of the directory
+    #[serde(with = "name::serde_path")]
     pub path: PathBuf,
     /// Directory content
     pub content: Vec<ContentType>,
+    /// Permissions and ownership at scan time
+    pub attributes: Attributes,
+    /// Last modification time at scan time
+    pub modified: SystemTime,
+}
+
+impl Default for Dir {
+    fn default() -> Self {
+        Dir {
+            path: PathBuf::new(),
+            content: Vec::new(),
+            attributes: Attributes::default(),
+            modified: SystemTime::UNIX_EPOCH,
+        }
+    }
 }
 
 impl Eq for Dir {}
 
+impl PartialOrd for Dir {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl Ord for Dir {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        return self.path.cmp(&other.path);
+        self.path.cmp(&other.path)
     }
 }
 
 impl Dir {
-    pub fn scan(&mut self) -> Result<(), io::Error> {
+    /// Recursively scans the directory, hashing every file found.
+    ///
+    /// Symbolic links are recorded but never followed (see `Symlinks` for
+    /// filtered scans). The collection configuration and database files
+    /// are skipped.
+    ///
+    /// Fails only when the directory itself cannot be read, entries that
+    /// cannot be indexed (e.g. unreadable or vanished while scanning) are
+    /// left out and returned.
+    pub fn scan(&mut self) -> Result<Vec<ScanError>, io::Error> {
+        self.scan_with(&mut ())
+    }
+
+    /// Scans the directory like `scan`, reporting progress to observer
+    pub fn scan_with(
+        &mut self,
+        observer: &mut dyn ScanObserver,
+    ) -> Result<Vec<ScanError>, io::Error> {
+        self.scan_known(observer, &HashMap::new(), &Filter::default())
+    }
+
+    /// Scans the directory like `scan_with`, leaving out the entries
+    /// rejected by filter
+    pub fn scan_filtered(
+        &mut self,
+        observer: &mut dyn ScanObserver,
+        filter: &Filter,
+    ) -> Result<Vec<ScanError>, io::Error> {
+        self.scan_known(observer, &HashMap::new(), filter)
+    }
+
+    /// Scans the directory, files found unchanged in known are not hashed
+    fn scan_known(
+        &mut self,
+        observer: &mut dyn ScanObserver,
+        known: &HashMap<PathBuf, File>,
+        filter: &Filter,
+    ) -> Result<Vec<ScanError>, io::Error> {
+        observer.dir_entered(&self.path);
+        let metadata = fs::metadata(&self.path)?;
+        let device = device(&metadata);
+        self.attributes = Attributes::from(&metadata);
+        self.modified = metadata.modified()?;
+        let mut skipped = vec![];
         for entry in fs::read_dir(self.path.as_path())? {
-            let entry = entry?;
+            if observer.cancelled() {
+                return Err(progress::cancelled());
+            }
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(error) => {
+                    let error = ScanError::new(&self.path, error);
+                    observer.error(&error);
+                    skipped.push(error);
+                    continue;
+                }
+            };
             let path = entry.path();
-            if path.is_file() {
-                let mut file = fs::File::open(&path)?;
-                let mut contents = Vec::new();
-                file.read_to_end(&mut contents)?;
-                self.content.push(ContentType::ContentFile(File {
-                    path: PathBuf::from(path),
-                    md5: md5::compute(&contents),
-                }));
-            } else {
-                if path.is_dir() {
-                    self.content.push(ContentType::ContentDir(Dir {
-                        path: PathBuf::from(path),
-                        content: Vec::new(),
-                    }));
+            if is_collection_file(&path) || !filter.accepts(&path) {
+                continue;
+            }
+            match Dir::scan_entry(&entry, device, &mut skipped, observer, known, filter) {
+                Ok(Some(item)) => self.content.push(item),
+                Ok(None) => (),
+                Err(error) if progress::is_cancelled(&error) => return Err(error),
+                Err(error) => {
+                    let error = ScanError::new(&path, error);
+                    observer.error(&error);
+                    skipped.push(error);
+                }
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Indexes a single entry of a directory on device, None for
+    /// unsupported types (e.g. sockets) and entries left out by filter
+    fn scan_entry(
+        entry: &fs::DirEntry,
+        device: u64,
+        skipped: &mut Vec<ScanError>,
+        observer: &mut dyn ScanObserver,
+        known: &HashMap<PathBuf, File>,
+        filter: &Filter,
+    ) -> Result<Option<ContentType>, io::Error> {
+        let path = entry.path();
+        let mut file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            let link = || Ok(Some(ContentType::ContentLink(Link::from_path(&path)?)));
+            match filter.symlinks {
+                Symlinks::Record => return link(),
+                Symlinks::Skip => return Ok(None),
+                Symlinks::Follow => match fs::metadata(&path) {
+                    Ok(target) if !(target.is_dir() && is_loop(&path)) => {
+                        file_type = target.file_type()
+                    }
+                    _ => return link(),
+                },
+            }
+        }
+        let item = if file_type.is_file() {
+            if filter.checks_files() && !filter.accepts_file(&path, &fs::metadata(&path)?) {
+                return Ok(None);
+            }
+            let file = match known.get(&path) {
+                Some(previous) => File::from_previous(previous, observer)?,
+                None => File::from_path_with(&path, observer)?,
+            };
+            observer.file_hashed(&file);
+            ContentType::ContentFile(file)
+        } else if file_type.is_dir() {
+            if filter.one_filesystem && self::device(&fs::metadata(&path)?) != device {
+                return Ok(None);
+            }
+            let mut dir = Dir {
+                path,
+                ..Default::default()
+            };
+            skipped.extend(dir.scan_known(observer, known, filter)?);
+            ContentType::ContentDir(dir)
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(item))
+    }
+
+    /// Returns the content sorted by key, with directories before files
+    /// and links when dirs_first is set
+    pub fn sorted_content(&self, key: SortKey, dirs_first: bool) -> Vec<&ContentType> {
+        let mut content: Vec<&ContentType> = self.content.iter().collect();
+        content.sort_by(|a, b| {
+            let is_dir = |c: &ContentType| matches!(c, ContentType::ContentDir(_));
+            let group = match dirs_first {
+                true => is_dir(b).cmp(&is_dir(a)),
+                false => Ordering::Equal,
+            };
+            group.then_with(|| key.compare(a, b))
+        });
+        content
+    }
+
+    /// Number of files in this directory and its subdirectories
+    pub fn file_count(&self) -> usize {
+        self.content
+            .iter()
+            .map(|c| match c {
+                ContentType::ContentDir(d) => d.file_count(),
+                ContentType::ContentFile(_) => 1,
+                ContentType::ContentLink(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Number of subdirectories, at any depth, of this directory
+    pub fn dir_count(&self) -> usize {
+        self.content
+            .iter()
+            .map(|c| match c {
+                ContentType::ContentDir(d) => 1 + d.dir_count(),
+                _ => 0,
+            })
+            .sum()
+    }
+
+    /// Size of all files in this directory and its subdirectories
+    pub fn total_size(&self) -> u64 {
+        self.content.iter().map(ContentType::size).sum()
+    }
+
+    /// Takes the subdirectory at a path relative to this directory
+    /// (matching entry names, whatever the form of the stored paths)
+    pub fn into_subdir(self, relative: &Path) -> Option<Dir> {
+        let mut dir = self;
+        for name in relative.iter() {
+            dir = dir.content.into_iter().find_map(|c| match c {
+                ContentType::ContentDir(d) if d.path.file_name() == Some(name) => Some(d),
+                _ => None,
+            })?;
+        }
+        Some(dir)
+    }
+
+    /// Moves the tree to root, replacing the path of this directory in
+    /// the paths of all entries
+    pub fn rebase(&mut self, root: &Path) {
+        let old = std::mem::replace(&mut self.path, root.to_path_buf());
+        for item in self.content.iter_mut() {
+            match item {
+                ContentType::ContentDir(d) => {
+                    let path = root.join(d.path.strip_prefix(&old).unwrap_or(&d.path));
+                    d.rebase(&path);
                 }
+                ContentType::ContentFile(f) => {
+                    f.path = root.join(f.path.strip_prefix(&old).unwrap_or(&f.path))
+                }
+                ContentType::ContentLink(l) => {
+                    l.path = root.join(l.path.strip_prefix(&old).unwrap_or(&l.path))
+                }
+            }
+        }
+    }
+
+    /// Returns all files in this directory and its subdirectories
+    pub fn all_files(&self) -> Vec<&File> {
+        let mut files = vec![];
+        for item in self.content.iter() {
+            match item {
+                ContentType::ContentFile(f) => files.push(f),
+                ContentType::ContentDir(d) => files.extend(d.all_files()),
+                ContentType::ContentLink(_) => (),
             }
         }
-        Ok(())
+        files
     }
 
     pub fn sorted_dirs(&self) -> Vec<&Dir> {
@@ -156,24 +613,337 @@ impl Dir {
     }
 }
 
+/// Unix permission bits and ownership of an indexed entry.
+///
+/// On platforms without these notions all fields are zero.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attributes {
+    /// Permission bits (including setuid, setgid and sticky bits)
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl From<&fs::Metadata> for Attributes {
+    #[cfg(unix)]
+    fn from(metadata: &fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Attributes {
+            mode: metadata.mode() & 0o7777,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn from(_metadata: &fs::Metadata) -> Self {
+        Attributes::default()
+    }
+}
+
+/// An indexed symbolic link
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Link {
+    /// Path of the link itself
+    #[serde(with = "name::serde_path")]
+    pub path: PathBuf,
+    /// Path the link points to, as stored in the link
+    #[serde(with = "name::serde_path")]
+    pub target: PathBuf,
+    /// Permissions and ownership of the link
+    pub attributes: Attributes,
+    /// Last modification time of the link
+    pub modified: SystemTime,
+}
+
+impl Link {
+    /// Builds a `Link` reading the link at path without following it
+    pub fn from_path(path: &Path) -> Result<Self, io::Error> {
+        let metadata = fs::symlink_metadata(path)?;
+        Ok(Link {
+            path: PathBuf::from(path),
+            target: fs::read_link(path)?,
+            attributes: Attributes::from(&metadata),
+            modified: metadata.modified()?,
+        })
+    }
+}
+
+/// An entry left out of a scan because it could not be indexed
+#[derive(Debug)]
+pub struct ScanError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl ScanError {
+    pub fn new(path: &Path, error: io::Error) -> Self {
+        ScanError {
+            path: path.to_path_buf(),
+            error,
+        }
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", name::escape_path(&self.path), self.error)
+    }
+}
+
+pub(crate) fn is_collection_file(path: &Path) -> bool {
+    path.file_name().is_some_and(|n| {
+        let n = n.to_string_lossy();
+        let n = n.strip_suffix(TEMP_SUFFIX).unwrap_or(&n);
+        n == CONFIG_FILE_NAME || n == DB_FILE_NAME || n == INDEX_FILE_NAME
+    })
+}
+
+/// Writes the file at path with write, through a temporary file in the
+/// same directory that is synced and renamed over path: a write
+/// interrupted (even by a crash) leaves the previous file intact
+pub(crate) fn write_replacing(
+    path: &Path,
+    write: impl FnOnce(&mut io::BufWriter<fs::File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(TEMP_SUFFIX);
+    let temp = PathBuf::from(temp);
+    let mut out = io::BufWriter::new(fs::File::create(&temp)?);
+    let written = write(&mut out)
+        .and_then(|_| out.flush())
+        .and_then(|_| out.get_ref().sync_all());
+    drop(out);
+    match written {
+        Ok(()) => fs::rename(&temp, path),
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// Device of the filesystem holding an entry, 0 where not available
+#[cfg(unix)]
+pub(crate) fn device(metadata: &fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::dev(metadata)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn device(_metadata: &fs::Metadata) -> u64 {
+    0
+}
+
+/// Whether the link at path leads to one of its parent directories, so
+/// that following it would scan the same directories forever
+pub(crate) fn is_loop(path: &Path) -> bool {
+    let Ok(target) = fs::canonicalize(path) else {
+        return true;
+    };
+    path.ancestors()
+        .skip(1)
+        .any(|p| fs::canonicalize(p).is_ok_and(|p| p == target))
+}
+
 /// And indexed file
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct File {
     /// File path
+    #[serde(with = "name::serde_path")]
     pub path: PathBuf,
     /// The hash of the file
+    #[serde(with = "digest_hex")]
     pub md5: md5::Digest,
+    /// Size in bytes at scan time
+    pub size: u64,
+    /// Last modification time at scan time
+    pub modified: SystemTime,
+    /// Permissions and ownership at scan time
+    pub attributes: Attributes,
+    /// Identity of the file on its device, shared by hard links
+    #[serde(default)]
+    pub id: Option<FileId>,
+    /// MIME type detected from the content at scan time
+    #[serde(default)]
+    pub mime: Option<String>,
+}
+
+impl Default for File {
+    fn default() -> Self {
+        File {
+            path: PathBuf::new(),
+            md5: md5::compute(b""),
+            size: 0,
+            modified: SystemTime::UNIX_EPOCH,
+            attributes: Attributes::default(),
+            id: None,
+            mime: None,
+        }
+    }
+}
+
+/// Device and inode numbers identifying a file, all hard links to the
+/// same file share it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileId {
+    pub dev: u64,
+    pub ino: u64,
+}
+
+impl FileId {
+    /// Returns the identity from metadata, None on platforms without
+    /// inode numbers
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &fs::Metadata) -> Option<Self> {
+        use std::os::unix::fs::MetadataExt;
+        Some(FileId {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_metadata(_metadata: &fs::Metadata) -> Option<Self> {
+        None
+    }
+}
+
+impl File {
+    /// Builds a `File` reading metadata and hashing the content at path
+    pub fn from_path(path: &Path) -> Result<Self, io::Error> {
+        File::from_path_with(path, &mut ())
+    }
+
+    /// Indexes the file at path reporting the hashed bytes to observer
+    pub fn from_path_with(path: &Path, observer: &mut dyn ScanObserver) -> Result<Self, io::Error> {
+        let metadata = fs::metadata(path)?;
+        let (md5, mime) = read_content(path, observer)?;
+        Ok(File {
+            path: PathBuf::from(path),
+            md5,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+            attributes: Attributes::from(&metadata),
+            id: FileId::from_metadata(&metadata),
+            mime: Some(mime.to_string()),
+        })
+    }
+
+    /// Indexes the file at the path of previous, keeping the hash and
+    /// MIME type of previous when size and modification time did not
+    /// change (the skipped bytes are still reported to observer)
+    fn from_previous(previous: &File, observer: &mut dyn ScanObserver) -> Result<Self, io::Error> {
+        let metadata = fs::metadata(&previous.path)?;
+        if metadata.len() != previous.size || metadata.modified()? != previous.modified {
+            return File::from_path_with(&previous.path, observer);
+        }
+        observer.bytes_hashed(previous.size);
+        Ok(File {
+            attributes: Attributes::from(&metadata),
+            id: FileId::from_metadata(&metadata),
+            ..previous.clone()
+        })
+    }
 }
 
 impl PartialOrd for File {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.path.cmp(&other.path))
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for File {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        return self.path.cmp(&other.path);
+        self.path.cmp(&other.path)
+    }
+}
+
+/// Computes the md5 digest of the file at path reading it in chunks
+pub fn compute_md5(path: &Path) -> Result<md5::Digest, io::Error> {
+    Ok(read_content(path, &mut ())?.0)
+}
+
+/// Reads the file at path once, computing its md5 digest and detecting
+/// its MIME type from the first bytes
+fn read_content(
+    path: &Path,
+    observer: &mut dyn ScanObserver,
+) -> Result<(md5::Digest, &'static str), io::Error> {
+    let mut file = fs::File::open(path)?;
+    let mut context = md5::Context::new();
+    let mut header = Vec::with_capacity(mime::HEADER_LEN);
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        if observer.cancelled() {
+            return Err(progress::cancelled());
+        }
+        let n = match file.read(&mut buffer) {
+            Ok(n) => n,
+            // a signal arrived, not to be confused with `progress::cancelled`
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        if n == 0 {
+            break;
+        }
+        if header.len() < mime::HEADER_LEN {
+            let missing = (mime::HEADER_LEN - header.len()).min(n);
+            header.extend_from_slice(&buffer[..missing]);
+        }
+        context.consume(&buffer[..n]);
+        observer.bytes_hashed(n as u64);
+    }
+    Ok((context.compute(), mime::detect(path, &header)))
+}
+
+/// (De)serializes an md5 digest as a lowercase hex string
+mod digest_hex {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(digest: &md5::Digest, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&format!("{:x}", digest))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<md5::Digest, D::Error> {
+        let hex = String::deserialize(d)?;
+        let mut bytes = [0u8; 16];
+        if hex.len() != 32 || !hex.is_ascii() {
+            return Err(D::Error::custom(format!("invalid md5 digest '{hex}'")));
+        }
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(D::Error::custom)?;
+        }
+        Ok(md5::Digest(bytes))
+    }
+}
+
+/// Trees built in the tests of other modules
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use std::path::PathBuf;
+
+    use super::{ContentType, Dir, File};
+
+    /// A file with the digest and size of content
+    pub fn file(path: &str, content: &[u8]) -> ContentType {
+        ContentType::ContentFile(File {
+            path: PathBuf::from(path),
+            md5: md5::compute(content),
+            size: content.len() as u64,
+            ..Default::default()
+        })
+    }
+
+    pub fn dir(path: &str, content: Vec<ContentType>) -> Dir {
+        Dir {
+            path: PathBuf::from(path),
+            content,
+            ..Default::default()
+        }
+    }
+
+    pub fn subdir(path: &str, content: Vec<ContentType>) -> ContentType {
+        ContentType::ContentDir(dir(path, content))
     }
 }
 
@@ -207,10 +977,66 @@ mod tests {
 
     #[test]
     fn collection_save_ok() {
-        let collection = Collection::new();
+        let tempdir = tempdir().unwrap();
+        fs::write(tempdir.path().join("a.txt"), b"abc").unwrap();
+        let mut collection = Collection::from(tempdir.path());
+        collection.name = String::from("test");
+        collection.scan().unwrap();
         let result = collection.save();
         assert!(result.is_ok());
-        assert!(false, "Need to implement file checking test");
+        assert!(
+            tempdir.path().join(DB_FILE_NAME).is_file(),
+            "Database file not written"
+        );
+
+        let mut loaded = Collection::from(tempdir.path());
+        assert!(loaded.load().unwrap(), "Database file not found on load");
+        assert_eq!(
+            collection.root_dir, loaded.root_dir,
+            "Loaded tree differs from saved one"
+        );
+    }
+
+    #[test]
+    fn failed_save_keeps_database() {
+        let tempdir = tempdir().unwrap();
+        let mut collection = Collection::from(tempdir.path());
+        collection.scan().unwrap();
+        collection.save().unwrap();
+        let saved = fs::read(collection.db_path()).unwrap();
+        assert!(!tempdir.path().join(".diffrust.db.tmp").exists());
+        // the temporary file cannot be created
+        fs::create_dir(tempdir.path().join(".diffrust.db.tmp")).unwrap();
+        fs::write(tempdir.path().join("a.txt"), b"abc").unwrap();
+        collection.scan().unwrap();
+        assert!(collection.save().is_err());
+        assert_eq!(saved, fs::read(collection.db_path()).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_round_trip() {
+        let tempdir = tempdir().unwrap();
+        let latin1 = tempdir.path().join(name::from_bytes(b"caf\xe9".to_vec()));
+        fs::write(&latin1, b"abc").unwrap();
+        fs::write(tempdir.path().join("plain"), b"abc").unwrap();
+        let mut collection = Collection::from(tempdir.path());
+        collection.scan().unwrap();
+        collection.save().unwrap();
+
+        let mut loaded = Collection::from(tempdir.path());
+        assert!(loaded.load().unwrap());
+        assert_eq!(collection.root_dir, loaded.root_dir);
+        let files = loaded.root_dir.as_ref().unwrap().sorted_files();
+        assert!(files.iter().any(|f| f.path == latin1));
+    }
+
+    #[test]
+    fn collection_load_missing() {
+        let tempdir = tempdir().unwrap();
+        let mut collection = Collection::from(tempdir.path());
+        assert!(!collection.load().unwrap());
+        assert!(collection.root_dir.is_none());
     }
 
     #[test]
@@ -239,7 +1065,108 @@ mod tests {
 
     #[test]
     fn scan_content() {
-        assert!(false, "Need to implement scan of directory test");
+        let tempdir = tempdir().unwrap();
+        fs::write(tempdir.path().join("a.txt"), b"abc").unwrap();
+        fs::create_dir(tempdir.path().join("sub")).unwrap();
+        fs::write(tempdir.path().join(CONFIG_FILE_NAME), b"{}").unwrap();
+        let mut collection = Collection::from(tempdir.path());
+        collection.scan().unwrap();
+
+        let dir = collection.root_dir.unwrap();
+        assert_eq!(
+            2,
+            dir.content.len(),
+            "Unexpected content (or config scanned)"
+        );
+        let files = dir.sorted_files();
+        assert_eq!(files[0].md5, md5::compute(b"abc"));
+        assert_eq!(files[0].size, 3);
+        assert_eq!(dir.sorted_dirs()[0].path, tempdir.path().join("sub"));
+    }
+
+    #[test]
+    fn load_subtree() {
+        let tempdir = tempdir().unwrap();
+        let root = tempdir.path();
+        fs::create_dir_all(root.join("sub").join("deep")).unwrap();
+        fs::write(root.join("sub").join("deep").join("a"), b"a").unwrap();
+        fs::write(root.join("sub").join("a.tmp"), b"t").unwrap();
+        let mut collection = Collection::from(root);
+        collection.filter = Filter::new(root, &[String::from("*.tmp")]);
+        collection.scan().unwrap();
+        collection.save().unwrap();
+
+        let mut sub = Collection::from(root.join("sub").as_path());
+        sub.enclosing = Some(root.to_path_buf());
+        assert_eq!(sub.db_path(), root.join(DB_FILE_NAME));
+        assert!(sub.load().unwrap());
+        let dir = sub.root_dir.as_ref().unwrap();
+        assert_eq!(dir.path, root.join("sub"));
+        assert_eq!(1, dir.content.len(), "Ignored file indexed");
+        let deep = dir.sorted_dirs()[0];
+        assert_eq!(deep.sorted_files()[0].path, root.join("sub/deep/a"));
+
+        sub.widen();
+        assert_eq!(sub.root, root);
+        assert!(sub.root_dir.is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scan_symlink() {
+        let tempdir = tempdir().unwrap();
+        fs::create_dir(tempdir.path().join("sub")).unwrap();
+        // a link back to the root would loop if followed
+        std::os::unix::fs::symlink("..", tempdir.path().join("sub").join("up")).unwrap();
+        let mut dir = Dir {
+            path: PathBuf::from(tempdir.path()),
+            ..Default::default()
+        };
+        dir.scan().unwrap();
+
+        let sub = dir.sorted_dirs()[0];
+        match &sub.content[0] {
+            ContentType::ContentLink(l) => assert_eq!(l.target, PathBuf::from("..")),
+            c => panic!("Expected link, found {:?}", c),
+        }
+
+        // followed, except the loop
+        fs::write(tempdir.path().join("a"), b"a").unwrap();
+        std::os::unix::fs::symlink("../a", tempdir.path().join("sub").join("b")).unwrap();
+        let mut filter = Filter::new(tempdir.path(), &[]);
+        filter.symlinks = Symlinks::Follow;
+        let mut dir = Dir {
+            path: PathBuf::from(tempdir.path()),
+            ..Default::default()
+        };
+        dir.scan_filtered(&mut (), &filter).unwrap();
+        let sub = dir.sorted_dirs()[0];
+        assert_eq!(sub.sorted_files()[0].md5, md5::compute(b"a"));
+        assert!(matches!(&sub.content[..], [_, _]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scan_skips_unreadable() {
+        use std::os::unix::fs::PermissionsExt;
+        let tempdir = tempdir().unwrap();
+        let secret = tempdir.path().join("secret");
+        fs::write(&secret, b"abc").unwrap();
+        fs::write(tempdir.path().join("public"), b"abc").unwrap();
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o000)).unwrap();
+        if fs::read(&secret).is_ok() {
+            // running as a privileged user, permissions are not enforced
+            return;
+        }
+        let mut dir = Dir {
+            path: PathBuf::from(tempdir.path()),
+            ..Default::default()
+        };
+        let skipped = dir.scan().unwrap();
+        assert_eq!(1, skipped.len());
+        assert_eq!(secret, skipped[0].path);
+        assert_eq!(io::ErrorKind::PermissionDenied, skipped[0].error.kind());
+        assert_eq!(1, dir.content.len());
     }
 
     #[test]
@@ -247,14 +1174,17 @@ mod tests {
         let d1 = Dir {
             path: PathBuf::from("/abc"),
             content: vec![],
+            ..Default::default()
         };
         let d2 = Dir {
             path: PathBuf::from("/abc/aaa"),
             content: vec![],
+            ..Default::default()
         };
         let d3 = Dir {
             path: PathBuf::from("/abf"),
             content: vec![],
+            ..Default::default()
         };
         assert_eq!(Ordering::Less, d1.cmp(&d3));
         assert_eq!(Ordering::Greater, d2.cmp(&d1));
@@ -263,7 +1193,65 @@ mod tests {
 
     #[test]
     fn dir_scan() {
-        assert!(false, "Need to implement Dir scan test");
+        let tempdir = tempdir().unwrap();
+        let sub = tempdir.path().join("a").join("b");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(sub.join("deep.txt"), b"deep").unwrap();
+        let mut dir = Dir {
+            path: PathBuf::from(tempdir.path()),
+            ..Default::default()
+        };
+        dir.scan().unwrap();
+
+        let files = dir.all_files();
+        assert_eq!(1, files.len(), "Nested file not scanned");
+        assert_eq!(files[0].path, sub.join("deep.txt"));
+        assert_eq!(files[0].md5, md5::compute(b"deep"));
+    }
+
+    #[test]
+    fn sort_content() {
+        let file = |name: &str, size: u64| {
+            ContentType::ContentFile(File {
+                path: PathBuf::from("/d").join(name),
+                size,
+                ..Default::default()
+            })
+        };
+        let dir = Dir {
+            path: PathBuf::from("/d"),
+            content: vec![
+                file("img10.jpg", 1),
+                ContentType::ContentDir(Dir {
+                    path: PathBuf::from("/d/sub"),
+                    content: vec![file("sub/big", 100)],
+                    ..Default::default()
+                }),
+                file("img9.jpg", 5),
+            ],
+            ..Default::default()
+        };
+        let names = |content: Vec<&ContentType>| -> Vec<String> {
+            content
+                .iter()
+                .map(|c| c.path().file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(
+            vec!["sub", "img9.jpg", "img10.jpg"],
+            names(dir.sorted_content(SortKey::Name, true))
+        );
+        assert_eq!(
+            vec!["img9.jpg", "img10.jpg", "sub"],
+            names(dir.sorted_content(SortKey::Name, false))
+        );
+        assert_eq!(
+            vec!["sub", "img9.jpg", "img10.jpg"],
+            names(dir.sorted_content(SortKey::Size, false))
+        );
+        assert_eq!(3, dir.file_count());
+        assert_eq!(1, dir.dir_count());
+        assert_eq!(106, dir.total_size());
     }
 
     #[test]
@@ -271,6 +1259,7 @@ mod tests {
         let dir = Dir {
             path: PathBuf::from("/"),
             content: content_vector(),
+            ..Default::default()
         };
         let sorted = dir.sorted_files();
         let v = files_vector();
@@ -282,6 +1271,7 @@ mod tests {
         let dir = Dir {
             path: PathBuf::from(""),
             content: content_vector(),
+            ..Default::default()
         };
         let sorted = dir.sorted_dirs();
         let v = dirs_vector();
@@ -293,18 +1283,22 @@ mod tests {
             ContentType::ContentFile(File {
                 path: PathBuf::from("/README.md"),
                 md5: md5::compute(b"README"),
+                ..Default::default()
             }),
             ContentType::ContentDir(Dir {
                 path: PathBuf::from("/"),
                 content: vec![],
+                ..Default::default()
             }),
             ContentType::ContentFile(File {
                 path: PathBuf::from("~/Documents/hello.txt"),
                 md5: md5::compute(b"Hello World!"),
+                ..Default::default()
             }),
             ContentType::ContentDir(Dir {
                 path: PathBuf::from("/root"),
                 content: vec![],
+                ..Default::default()
             }),
         ]
     }
@@ -314,10 +1308,12 @@ mod tests {
             File {
                 path: PathBuf::from("/README.md"),
                 md5: md5::compute(b"README"),
+                ..Default::default()
             },
             File {
                 path: PathBuf::from("~/Documents/hello.txt"),
                 md5: md5::compute(b"Hello World!"),
+                ..Default::default()
             },
         ]
     }
@@ -327,11 +1323,13 @@ mod tests {
             Dir {
                 path: PathBuf::from("/"),
                 content: vec![],
+                ..Default::default()
             },
             Dir {
                 path: PathBuf::from("/root"),
                 content: vec![],
-            }
+                ..Default::default()
+            },
         ]
     }
 }
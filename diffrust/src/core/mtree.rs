@@ -0,0 +1,566 @@
+//! BSD `mtree(5)` specifications.
+//!
+//! Specifications are written in the flat format (one full path per
+//! line, as produced by `mtree -C` or libarchive) and read in both flat
+//! and hierarchical formats, including `/set` and `/unset` directives.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::core::hash::{hash_file, to_hex, HashAlgorithm};
+use crate::core::model::{self, Attributes, ContentType, Dir, ScanError};
+use crate::core::name;
+
+/// An entry of a specification: a path relative to the root and its
+/// keywords (after applying `/set` defaults)
+#[derive(Debug, PartialEq)]
+pub struct SpecEntry {
+    pub path: PathBuf,
+    pub keywords: BTreeMap<String, String>,
+}
+
+/// Error found while parsing a specification
+#[derive(Debug, PartialEq)]
+pub struct SpecError {
+    /// 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+/// Writes the specification of dir, with paths relative to root.
+///
+/// Digests for the given algorithms are emitted as `<alg>digest`
+/// keywords, md5 is taken from the index while the others require
+/// re-hashing the files.
+pub fn write_spec(
+    dir: &Dir,
+    root: &Path,
+    algorithms: &[HashAlgorithm],
+) -> Result<String, ScanError> {
+    let mut spec = String::from("#mtree\n");
+    write_dir(&mut spec, dir, root, algorithms)?;
+    Ok(spec)
+}
+
+fn write_dir(
+    spec: &mut String,
+    dir: &Dir,
+    root: &Path,
+    algorithms: &[HashAlgorithm],
+) -> Result<(), ScanError> {
+    push_line(
+        spec,
+        &dir.path,
+        root,
+        "dir",
+        &dir.attributes,
+        dir.modified,
+        &[],
+    );
+    let mut entries: Vec<&ContentType> = dir.content.iter().collect();
+    entries.sort_unstable_by_key(|c| c.path());
+    for entry in entries {
+        match entry {
+            ContentType::ContentDir(d) => write_dir(spec, d, root, algorithms)?,
+            ContentType::ContentFile(f) => {
+                let mut extra = vec![format!("size={}", f.size)];
+                for algorithm in algorithms {
+                    let digest = match algorithm {
+                        HashAlgorithm::Md5 => f.md5.0.to_vec(),
+                        _ => hash_file(&f.path, *algorithm)
+                            .map_err(|e| ScanError::new(&f.path, e))?,
+                    };
+                    extra.push(format!("{}digest={}", algorithm, to_hex(&digest)));
+                }
+                push_line(
+                    spec,
+                    &f.path,
+                    root,
+                    "file",
+                    &f.attributes,
+                    f.modified,
+                    &extra,
+                );
+            }
+            ContentType::ContentLink(l) => {
+                let extra = vec![format!("link={}", encode_name(&l.target))];
+                push_line(
+                    spec,
+                    &l.path,
+                    root,
+                    "link",
+                    &l.attributes,
+                    l.modified,
+                    &extra,
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn push_line(
+    spec: &mut String,
+    path: &Path,
+    root: &Path,
+    kind: &str,
+    attributes: &Attributes,
+    modified: SystemTime,
+    extra: &[String],
+) {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let name = if relative.as_os_str().is_empty() {
+        String::from(".")
+    } else {
+        format!("./{}", encode_name(relative))
+    };
+    spec.push_str(&format!(
+        "{name} type={kind} mode={:04o} uid={} gid={} time={}",
+        attributes.mode,
+        attributes.uid,
+        attributes.gid,
+        format_time(modified)
+    ));
+    for keyword in extra {
+        spec.push(' ');
+        spec.push_str(keyword);
+    }
+    spec.push('\n');
+}
+
+fn format_time(time: SystemTime) -> String {
+    let d = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}", d.as_secs(), d.subsec_nanos())
+}
+
+/// Encodes a name with `vis(3)` octal escapes for whitespace, non
+/// printable and glob characters.
+pub fn encode_name(path: &Path) -> String {
+    let mut encoded = String::new();
+    for &b in name::to_bytes(path.as_os_str()) {
+        if b.is_ascii_graphic() && !b"\\#*?[".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("\\{:03o}", b));
+        }
+    }
+    encoded
+}
+
+/// Decodes a `vis(3)` encoded name, returns None on invalid escapes.
+pub fn decode_name(name: &str) -> Option<PathBuf> {
+    let bytes = name.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            decoded.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let rest = &bytes[i + 1..];
+        if rest.len() >= 3 && rest[..3].iter().all(|c| (b'0'..=b'7').contains(c)) {
+            let value = u32::from_str_radix(std::str::from_utf8(&rest[..3]).ok()?, 8).ok()?;
+            decoded.push(u8::try_from(value).ok()?);
+            i += 4;
+            continue;
+        }
+        decoded.push(match rest.first()? {
+            b'\\' => b'\\',
+            b's' => b' ',
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b'#' => b'#',
+            _ => return None,
+        });
+        i += 2;
+    }
+    Some(PathBuf::from(name::from_bytes(decoded)))
+}
+
+/// Parses a specification in flat or hierarchical format
+pub fn parse_spec(content: &str) -> Result<Vec<SpecEntry>, SpecError> {
+    let mut entries = vec![];
+    let mut defaults: BTreeMap<String, String> = BTreeMap::new();
+    let mut cwd = PathBuf::new();
+    let mut logical = String::new();
+    let mut start = 0;
+    for (i, raw) in content.lines().enumerate() {
+        if logical.is_empty() {
+            start = i + 1;
+        }
+        // a trailing backslash continues the line
+        if let Some(stripped) = raw.strip_suffix('\\') {
+            logical.push_str(stripped);
+            logical.push(' ');
+            continue;
+        }
+        logical.push_str(raw);
+        let line = std::mem::take(&mut logical);
+        let error = |message: String| SpecError {
+            line: start,
+            message,
+        };
+        let mut tokens = line.split_whitespace();
+        let Some(first) = tokens.next() else {
+            continue;
+        };
+        if first.starts_with('#') {
+            continue;
+        }
+        match first {
+            "/set" => {
+                for token in tokens {
+                    let (k, v) = parse_keyword(token);
+                    defaults.insert(k, v);
+                }
+            }
+            "/unset" => {
+                for token in tokens {
+                    if token == "all" {
+                        defaults.clear();
+                    } else {
+                        defaults.remove(token);
+                    }
+                }
+            }
+            ".." => {
+                cwd.pop();
+            }
+            _ if first.starts_with('/') => {
+                return Err(error(format!("unknown directive '{first}'")));
+            }
+            _ => {
+                let name = decode_name(first)
+                    .ok_or_else(|| error(format!("invalid escape in '{first}'")))?;
+                let mut keywords = defaults.clone();
+                for token in tokens {
+                    let (k, v) = parse_keyword(token);
+                    keywords.insert(k, v);
+                }
+                let is_dir = keywords.get("type").is_some_and(|t| t == "dir");
+                let path = if first.contains('/') {
+                    normalize(&name)
+                } else {
+                    let path = normalize(&cwd.join(&name));
+                    if is_dir {
+                        cwd = path.clone();
+                    }
+                    path
+                };
+                entries.push(SpecEntry { path, keywords });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn parse_keyword(token: &str) -> (String, String) {
+    match token.split_once('=') {
+        Some((k, v)) => (k.to_string(), v.to_string()),
+        None => (token.to_string(), String::new()),
+    }
+}
+
+/// Removes `.` components so that `./a/b` and `a/b` are the same path
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, std::path::Component::CurDir))
+        .collect()
+}
+
+/// A keyword whose value on disk differs from the specification
+#[derive(Debug, PartialEq)]
+pub struct KeywordDiff {
+    pub keyword: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Outcome of comparing a path with the specification
+#[derive(Debug, PartialEq)]
+pub enum MtreeStatus {
+    /// Some keywords differ
+    Differs(Vec<KeywordDiff>),
+    /// Present in the specification but not on disk
+    Missing,
+    /// Present on disk but not in the specification
+    Extra,
+}
+
+/// Compares the directory at root with the specification, returning
+/// only the paths that do not match.
+pub fn compare(root: &Path, spec: &[SpecEntry]) -> Vec<(PathBuf, MtreeStatus)> {
+    let mut report = vec![];
+    for entry in spec {
+        let full = root.join(&entry.path);
+        match fs::symlink_metadata(&full) {
+            Ok(metadata) => {
+                let diffs = compare_entry(&full, &metadata, &entry.keywords);
+                if !diffs.is_empty() {
+                    report.push((entry.path.clone(), MtreeStatus::Differs(diffs)));
+                }
+            }
+            Err(_) if entry.keywords.contains_key("optional") => (),
+            Err(_) => report.push((entry.path.clone(), MtreeStatus::Missing)),
+        }
+    }
+    let known: HashSet<&Path> = spec.iter().map(|e| e.path.as_path()).collect();
+    let mut extra = vec![];
+    collect_extra(root, root, &known, &mut extra);
+    extra.sort();
+    report.extend(extra.into_iter().map(|p| (p, MtreeStatus::Extra)));
+    report
+}
+
+fn collect_extra(root: &Path, dir: &Path, known: &HashSet<&Path>, extra: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if dir == root && model::is_collection_file(&path) {
+            continue;
+        }
+        let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+        if !known.contains(relative.as_path()) {
+            // an unknown directory is reported once, not its content
+            extra.push(relative);
+        } else if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            collect_extra(root, &path, known, extra);
+        }
+    }
+}
+
+fn compare_entry(
+    path: &Path,
+    metadata: &fs::Metadata,
+    keywords: &BTreeMap<String, String>,
+) -> Vec<KeywordDiff> {
+    let mut diffs = vec![];
+    for (keyword, expected) in keywords {
+        let Some(actual) = actual_value(path, metadata, keyword) else {
+            continue;
+        };
+        if !values_match(keyword, expected, &actual) {
+            diffs.push(KeywordDiff {
+                keyword: keyword.clone(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+    diffs
+}
+
+/// Returns the value of keyword for the entry on disk, None for
+/// unsupported keywords (which are then ignored).
+fn actual_value(path: &Path, metadata: &fs::Metadata, keyword: &str) -> Option<String> {
+    let attributes = Attributes::from(metadata);
+    let value = match keyword {
+        "type" => file_type_name(metadata).to_string(),
+        "mode" => format!("{:04o}", attributes.mode),
+        "uid" => attributes.uid.to_string(),
+        "gid" => attributes.gid.to_string(),
+        "size" => metadata.len().to_string(),
+        "time" => format_time(metadata.modified().ok()?),
+        "link" => encode_name(&fs::read_link(path).ok()?),
+        _ => {
+            let name = keyword.strip_suffix("digest").unwrap_or(keyword);
+            let algorithm: HashAlgorithm = name.parse().ok()?;
+            if !metadata.is_file() {
+                return None;
+            }
+            match hash_file(path, algorithm) {
+                Ok(digest) => to_hex(&digest),
+                Err(e) => format!("<{e}>"),
+            }
+        }
+    };
+    Some(value)
+}
+
+fn values_match(keyword: &str, expected: &str, actual: &str) -> bool {
+    match keyword {
+        "mode" => u32::from_str_radix(expected, 8).ok() == u32::from_str_radix(actual, 8).ok(),
+        "time" => parse_time(expected) == parse_time(actual),
+        "link" => decode_name(expected) == decode_name(actual),
+        _ if keyword.ends_with("digest") || keyword.starts_with("sha") || keyword == "md5" => {
+            expected.eq_ignore_ascii_case(actual)
+        }
+        _ => expected == actual,
+    }
+}
+
+/// Parses `seconds.fraction` into seconds and nanoseconds
+fn parse_time(value: &str) -> Option<(u64, u32)> {
+    let (secs, frac) = value.split_once('.').unwrap_or((value, "0"));
+    let frac = format!("{:0<9}", frac);
+    Some((secs.parse().ok()?, frac.get(..9)?.parse().ok()?))
+}
+
+#[cfg(unix)]
+fn file_type_name(metadata: &fs::Metadata) -> &'static str {
+    use std::os::unix::fs::FileTypeExt;
+    let t = metadata.file_type();
+    if t.is_symlink() {
+        "link"
+    } else if t.is_dir() {
+        "dir"
+    } else if t.is_file() {
+        "file"
+    } else if t.is_block_device() {
+        "block"
+    } else if t.is_char_device() {
+        "char"
+    } else if t.is_fifo() {
+        "fifo"
+    } else {
+        "socket"
+    }
+}
+
+#[cfg(not(unix))]
+fn file_type_name(metadata: &fs::Metadata) -> &'static str {
+    let t = metadata.file_type();
+    if t.is_symlink() {
+        "link"
+    } else if t.is_dir() {
+        "dir"
+    } else {
+        "file"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::core::model::Collection;
+    use crate::core::ngram::NameIndex;
+
+    #[test]
+    fn name_encoding_round_trip() {
+        let path = PathBuf::from("a dir/#x*.txt");
+        let encoded = encode_name(&path);
+        assert_eq!("a\\040dir/\\043x\\052.txt", encoded);
+        assert_eq!(Some(path), decode_name(&encoded));
+        assert_eq!(Some(PathBuf::from("a b")), decode_name("a\\sb"));
+        assert_eq!(None, decode_name("a\\q"));
+    }
+
+    #[test]
+    fn parse_flat_spec_with_set() {
+        let spec =
+            "#mtree\n/set type=file uid=0\n. type=dir\n./a/b.txt size=3 \\\n  md5digest=abc\n";
+        let entries = parse_spec(spec).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(PathBuf::new(), entries[0].path);
+        assert_eq!("dir", entries[0].keywords["type"]);
+        assert_eq!(PathBuf::from("a/b.txt"), entries[1].path);
+        assert_eq!("file", entries[1].keywords["type"]);
+        assert_eq!("0", entries[1].keywords["uid"]);
+        assert_eq!("abc", entries[1].keywords["md5digest"]);
+    }
+
+    #[test]
+    fn parse_hierarchical_spec() {
+        let spec = ". type=dir\nsub type=dir\nx.txt type=file\n..\ny.txt type=file\n..\n";
+        let paths: Vec<PathBuf> = parse_spec(spec)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.path)
+            .collect();
+        assert_eq!(
+            vec![
+                PathBuf::new(),
+                PathBuf::from("sub"),
+                PathBuf::from("sub/x.txt"),
+                PathBuf::from("y.txt")
+            ],
+            paths
+        );
+    }
+
+    #[test]
+    fn parse_unknown_directive() {
+        let err = parse_spec("#mtree\n/bogus x\n").unwrap_err();
+        assert_eq!(2, err.line);
+    }
+
+    #[test]
+    fn time_precision() {
+        assert_eq!(parse_time("10.5"), Some((10, 500_000_000)));
+        assert_eq!(parse_time("10.000000001"), Some((10, 1)));
+        assert_eq!(parse_time("10"), Some((10, 0)));
+    }
+
+    #[test]
+    fn write_and_compare() {
+        let tempdir = tempdir().unwrap();
+        fs::create_dir(tempdir.path().join("sub")).unwrap();
+        fs::write(tempdir.path().join("a.txt"), b"abc").unwrap();
+        fs::write(tempdir.path().join("sub").join("b.txt"), b"b").unwrap();
+        let mut collection = Collection::from(tempdir.path());
+        collection.scan().unwrap();
+        let dir = collection.root_dir.as_ref().unwrap();
+
+        let spec = write_spec(dir, tempdir.path(), &[HashAlgorithm::Md5]).unwrap();
+        assert!(spec.contains("md5digest=900150983cd24fb0d6963f7d28e17f72"));
+        let entries = parse_spec(&spec).unwrap();
+        assert!(compare(tempdir.path(), &entries).is_empty());
+
+        fs::write(tempdir.path().join("a.txt"), b"abcd").unwrap();
+        fs::remove_file(tempdir.path().join("sub").join("b.txt")).unwrap();
+        fs::write(tempdir.path().join("new.txt"), b"").unwrap();
+        let report = compare(tempdir.path(), &entries);
+        let (_, status) = report
+            .iter()
+            .find(|(p, _)| p == Path::new("a.txt"))
+            .expect("Changed file not reported");
+        let MtreeStatus::Differs(diffs) = status else {
+            panic!("Expected differences, found {:?}", status);
+        };
+        let keywords: Vec<&str> = diffs.iter().map(|d| d.keyword.as_str()).collect();
+        assert!(keywords.contains(&"size") && keywords.contains(&"md5digest"));
+        assert!(report.contains(&(PathBuf::from("sub/b.txt"), MtreeStatus::Missing)));
+        assert!(report.contains(&(PathBuf::from("new.txt"), MtreeStatus::Extra)));
+    }
+
+    #[test]
+    fn database_round_trip() {
+        let tempdir = tempdir().unwrap();
+        fs::write(tempdir.path().join("a.txt"), b"abc").unwrap();
+        let mut collection = Collection::from(tempdir.path());
+        collection.scan().unwrap();
+        // saved as by scan, with the temporary file of an interrupted save
+        collection.save().unwrap();
+        let index = NameIndex::build(collection.root_dir.as_ref().unwrap());
+        index
+            .save(&collection.index_path(), &collection.db_path())
+            .unwrap();
+        fs::write(tempdir.path().join(".diffrust.db.tmp"), b"{").unwrap();
+        collection.scan().unwrap();
+        let dir = collection.root_dir.as_ref().unwrap();
+        let spec = write_spec(dir, tempdir.path(), &[HashAlgorithm::Md5]).unwrap();
+        let entries = parse_spec(&spec).unwrap();
+        assert_eq!(
+            vec![PathBuf::from(""), PathBuf::from("a.txt")],
+            entries.iter().map(|e| e.path.clone()).collect::<Vec<_>>()
+        );
+        assert!(compare(tempdir.path(), &entries).is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+//! File names that are not valid UTF-8.
+//!
+//! On Unix a file name is an arbitrary sequence of bytes (e.g. a Latin-1
+//! name copied from an old share). Names are kept as `OsStr`/`Path`
+//! everywhere, these helpers convert them losslessly to bytes (for
+//! storage) and to escaped text (for display).
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+
+/// Returns the bytes of name, exactly as found on the file system on Unix
+pub fn to_bytes(name: &OsStr) -> &[u8] {
+    name.as_encoded_bytes()
+}
+
+/// Builds a name from its bytes, the inverse of `to_bytes`
+#[cfg(unix)]
+pub fn from_bytes(bytes: Vec<u8>) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes)
+}
+
+#[cfg(not(unix))]
+pub fn from_bytes(bytes: Vec<u8>) -> OsString {
+    OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Escapes name for display on a terminal.
+///
+/// Backslashes and control characters (e.g. newlines) are escaped Rust
+/// style, bytes that are not valid UTF-8 are printed as `\xNN`, so that
+/// different names are always displayed differently.
+pub fn escape(name: &OsStr) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for chunk in to_bytes(name).utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\t' => escaped.push_str("\\t"),
+                c if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+                c => escaped.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            escaped.push_str(&format!("\\x{b:02x}"));
+        }
+    }
+    escaped
+}
+
+/// Escapes path for display, see `escape`
+pub fn escape_path(path: &Path) -> String {
+    escape(path.as_os_str())
+}
+
+/// (De)serializes a path as a string when it is valid UTF-8, as
+/// `{"hex": "<bytes>"}` otherwise
+pub mod serde_path {
+    use std::path::{Path, PathBuf};
+
+    use serde::{de::Error, ser::SerializeMap, Deserialize, Deserializer, Serializer};
+
+    use super::{from_bytes, to_bytes};
+    use crate::core::hash::{from_hex, to_hex};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Text(String),
+        Bytes { hex: String },
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, s: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(text) => s.serialize_str(text),
+            None => {
+                let mut map = s.serialize_map(Some(1))?;
+                map.serialize_entry("hex", &to_hex(to_bytes(path.as_os_str())))?;
+                map.end()
+            }
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<PathBuf, D::Error> {
+        match Repr::deserialize(d)? {
+            Repr::Text(text) => Ok(PathBuf::from(text)),
+            Repr::Bytes { hex } => from_hex(&hex)
+                .map(|bytes| PathBuf::from(from_bytes(bytes)))
+                .ok_or_else(|| D::Error::custom(format!("invalid hex path '{hex}'"))),
+        }
+    }
+
+    /// `serde_path` for optional paths
+    pub mod option {
+        use std::path::PathBuf;
+
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "super")] PathBuf);
+
+        pub fn serialize<S: Serializer>(path: &Option<PathBuf>, s: S) -> Result<S::Ok, S::Error> {
+            match path {
+                Some(path) => super::serialize(path, s),
+                None => s.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<PathBuf>, D::Error> {
+            Ok(Option::<Wrapper>::deserialize(d)?.map(|w| w.0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn latin1_names() {
+        // "café" in Latin-1
+        let name = from_bytes(b"caf\xe9".to_vec());
+        assert_eq!(b"caf\xe9", to_bytes(&name));
+        assert_eq!("caf\\xe9", escape(&name));
+    }
+
+    #[test]
+    fn escape_controls() {
+        assert_eq!("città", escape(OsStr::new("città")));
+        assert_eq!("a\\nb\\\\c\\u{1b}", escape(OsStr::new("a\nb\\c\x1b")));
+    }
+}
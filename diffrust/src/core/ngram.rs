@@ -0,0 +1,489 @@
+//! Inverted index of entry names by bigrams.
+//!
+//! Scoring a pattern against every name of a large collection is slow,
+//! so the names are indexed by their bigrams (after case folding) and a
+//! search only scores the entries that may share enough bigrams with
+//! the pattern to be similar (see `Metric::min_common_bigrams`), with a
+//! number of bigrams close enough to the one of the pattern (see
+//! `Metric::bigram_counts`): no name similar enough is missed. An entry
+//! sharing enough bigrams has one of the rarest bigrams of the pattern,
+//! so only the postings of these are read (prefix filtering). Entries
+//! are numbered in the order of `search::entries` and located in the
+//! tree by their position in the content of their parent, so that a
+//! lookup does not walk the tree.
+//!
+//! The index is saved next to the database of the collection, stamped
+//! with the size and modification time of the database it was built
+//! from: an index not matching the database is not loaded.
+//!
+//! Postings (the entries of a bigram) are sorted lists of entry numbers
+//! stored as differences in LEB128 variable length integers, usually one
+//! or two bytes per entry.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::core::algorithm::{sorted_bigrams, sorted_bigrams_into, Bigram, Metric};
+use crate::core::model::{self, ContentType, Dir};
+
+/// First bytes of an index file, the last one is the format version
+const MAGIC: &[u8; 8] = b"DRNGRAM\x03";
+
+/// Parent of the entries in the content of the indexed directory
+const ROOT: u32 = u32::MAX;
+
+/// An indexed entry
+#[derive(Debug, Clone, PartialEq)]
+struct Indexed {
+    parent: u32,
+    /// Position in the content of the parent
+    position: u32,
+    /// Number of bigrams of the name, repeated ones included
+    bigrams: u32,
+    name: String,
+}
+
+/// Entries of a bigram, in increasing order
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Postings {
+    count: u32,
+    last: u32,
+    bytes: Vec<u8>,
+}
+
+impl Postings {
+    fn push(&mut self, id: u32) {
+        let mut delta = id - self.last;
+        loop {
+            let byte = (delta & 0x7f) as u8;
+            delta >>= 7;
+            if delta == 0 {
+                self.bytes.push(byte);
+                break;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+        self.count += 1;
+        self.last = id;
+    }
+
+    fn ids(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut bytes = self.bytes.iter();
+        let mut id = 0;
+        (0..self.count).map(move |_| {
+            let (mut delta, mut shift) = (0, 0);
+            for byte in bytes.by_ref() {
+                delta |= u32::from(byte & 0x7f) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    break;
+                }
+            }
+            id += delta;
+            id
+        })
+    }
+}
+
+/// Index of the names of the entries of a tree
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NameIndex {
+    entries: Vec<Indexed>,
+    postings: HashMap<u64, Postings>,
+}
+
+impl NameIndex {
+    /// Indexes the entries of dir at any depth
+    pub fn build(dir: &Dir) -> Self {
+        let mut index = NameIndex::default();
+        index.add_content(dir, ROOT);
+        index
+    }
+
+    fn add_content(&mut self, dir: &Dir, parent: u32) {
+        for (position, item) in dir.content.iter().enumerate() {
+            let id = self.entries.len() as u32;
+            let name = item
+                .path()
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            let mut keys = sorted_bigrams(&name);
+            let bigrams = keys.len() as u32;
+            keys.dedup();
+            for key in keys {
+                self.postings.entry(key.key()).or_default().push(id);
+            }
+            self.entries.push(Indexed {
+                parent,
+                position: position as u32,
+                bigrams,
+                name,
+            });
+            if let ContentType::ContentDir(d) = item {
+                self.add_content(d, id);
+            }
+        }
+    }
+
+    /// Number of indexed entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Numbers of the entries whose name is at least min similar to
+    /// pattern by metric, with their similarity, in tree order. None for
+    /// the metrics not bounded by common bigrams.
+    pub fn similar(&self, pattern: &str, metric: Metric, min: f64) -> Option<Vec<(u32, f64)>> {
+        let keys = sorted_bigrams(pattern);
+        let counts = metric.bigram_counts(keys.len(), min)?;
+        let least = |m| metric.min_common_bigrams(keys.len(), m, min).unwrap_or(0);
+        let mut name = vec![];
+        let similar = self
+            .candidates(&keys, &counts, least)
+            .into_iter()
+            .filter_map(|id| {
+                sorted_bigrams_into(&self.entries[id as usize].name, &mut name);
+                let similarity = metric.bigram_similarity(&keys, &name)?;
+                Some((id, similarity)).filter(|_| similarity >= min)
+            })
+            .collect();
+        Some(similar)
+    }
+
+    /// Numbers of the entries with a number m of bigrams in counts
+    /// sharing least(m) of keys (sorted, repeated ones included), in tree
+    /// order. least grows with m.
+    fn candidates(
+        &self,
+        keys: &[Bigram],
+        counts: &RangeInclusive<usize>,
+        least: impl Fn(usize) -> usize,
+    ) -> Vec<u32> {
+        let bigrams = |id: u32| self.entries[id as usize].bigrams as usize;
+        let fits = |id: &u32| counts.contains(&bigrams(*id));
+        let fewest = least(*counts.start());
+        if fewest == 0 {
+            return (0..self.entries.len() as u32).filter(fits).collect();
+        }
+        // the distinct keys with their occurrences, rarest first
+        let mut runs: Vec<(Option<&Postings>, usize)> = keys
+            .chunk_by(|a, b| a == b)
+            .map(|run| (self.postings.get(&run[0].key()), run.len()))
+            .collect();
+        runs.sort_by_key(|(postings, _)| postings.map_or(0, |p| p.count));
+        // an entry sharing least of the keys lacks at most the others, so
+        // it has one of the rarest keys occurring once more: only their
+        // entries are counted
+        let mut needed = (keys.len() + 1).saturating_sub(fewest);
+        let (mut hits, mut others) = (vec![], vec![]);
+        for (postings, occurrences) in runs {
+            match postings {
+                Some(postings) if needed == 0 => others.push((postings, occurrences as u32)),
+                Some(postings) => hits.extend(
+                    postings
+                        .ids()
+                        .filter(fits)
+                        .map(|id| (id, occurrences as u32)),
+                ),
+                None => (),
+            }
+            needed = needed.saturating_sub(occurrences);
+        }
+        hits.sort_unstable();
+        let mut shared: Vec<(u32, u32)> = vec![];
+        for (id, occurrences) in hits {
+            match shared.last_mut() {
+                Some((last, n)) if *last == id => *n += occurrences,
+                _ => shared.push((id, occurrences)),
+            }
+        }
+        for (postings, occurrences) in others {
+            let mut i = 0;
+            for id in postings.ids() {
+                while i < shared.len() && shared[i].0 < id {
+                    i += 1;
+                }
+                match shared.get_mut(i) {
+                    Some((candidate, n)) if *candidate == id => *n += occurrences,
+                    Some(_) => (),
+                    None => break,
+                }
+            }
+        }
+        shared
+            .into_iter()
+            .filter(|&(id, n)| n as usize >= least(bigrams(id)))
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    /// The entry numbered id in dir, None when dir is not the indexed
+    /// tree
+    pub fn resolve<'a>(&self, id: u32, dir: &'a Dir) -> Option<&'a ContentType> {
+        let mut chain = vec![];
+        let mut id = id;
+        while id != ROOT {
+            let indexed = self.entries.get(id as usize)?;
+            chain.push(indexed);
+            id = indexed.parent;
+        }
+        let mut content = &dir.content;
+        let mut found: Option<&ContentType> = None;
+        for indexed in chain.into_iter().rev() {
+            if let Some(ContentType::ContentDir(d)) = found {
+                content = &d.content;
+            } else if found.is_some() {
+                return None;
+            }
+            let item = content.get(indexed.position as usize)?;
+            if item.path().file_name()?.to_string_lossy() != indexed.name {
+                return None;
+            }
+            found = Some(item);
+        }
+        found
+    }
+
+    /// Writes the index to path, stamped with the database at db
+    pub fn save(&self, path: &Path, db: &Path) -> io::Result<()> {
+        let stamp = stamp(db)?;
+        model::write_replacing(path, |out| self.write(out, stamp))
+    }
+
+    fn write(&self, out: &mut impl Write, stamp: [u64; 3]) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        for n in stamp {
+            out.write_all(&n.to_le_bytes())?;
+        }
+        out.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        for e in self.entries.iter() {
+            out.write_all(&e.parent.to_le_bytes())?;
+            out.write_all(&e.position.to_le_bytes())?;
+            out.write_all(&e.bigrams.to_le_bytes())?;
+            out.write_all(&(e.name.len() as u32).to_le_bytes())?;
+            out.write_all(e.name.as_bytes())?;
+        }
+        out.write_all(&(self.postings.len() as u32).to_le_bytes())?;
+        for (key, postings) in self.postings.iter() {
+            out.write_all(&key.to_le_bytes())?;
+            out.write_all(&postings.count.to_le_bytes())?;
+            out.write_all(&postings.last.to_le_bytes())?;
+            out.write_all(&(postings.bytes.len() as u32).to_le_bytes())?;
+            out.write_all(&postings.bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the index at path, None when there is none or it was not
+    /// built from the database at db as it is
+    pub fn load(path: &Path, db: &Path) -> io::Result<Option<Self>> {
+        let file = match fs::File::open(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            file => file?,
+        };
+        let mut input = BufReader::new(file);
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        let stamped = [
+            read_u64(&mut input)?,
+            read_u64(&mut input)?,
+            read_u64(&mut input)?,
+        ];
+        if &magic != MAGIC || stamped != stamp(db)? {
+            return Ok(None);
+        }
+        let mut index = NameIndex::default();
+        for _ in 0..read_u32(&mut input)? {
+            let parent = read_u32(&mut input)?;
+            let position = read_u32(&mut input)?;
+            let bigrams = read_u32(&mut input)?;
+            let name = String::from_utf8(read_bytes(&mut input)?)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            index.entries.push(Indexed {
+                parent,
+                position,
+                bigrams,
+                name,
+            });
+        }
+        for _ in 0..read_u32(&mut input)? {
+            let key = read_u64(&mut input)?;
+            let postings = Postings {
+                count: read_u32(&mut input)?,
+                last: read_u32(&mut input)?,
+                bytes: read_bytes(&mut input)?,
+            };
+            index.postings.insert(key, postings);
+        }
+        Ok(Some(index))
+    }
+}
+
+/// Size and modification time of the database
+fn stamp(db: &Path) -> io::Result<[u64; 3]> {
+    let metadata = fs::metadata(db)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok([
+        metadata.len(),
+        modified.as_secs(),
+        u64::from(modified.subsec_nanos()),
+    ])
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_bytes(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; read_u32(input)? as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::core::algorithm::Metric;
+    use crate::core::model::fixtures::{dir, file, subdir};
+
+    fn tree() -> Dir {
+        dir(
+            "/c",
+            vec![
+                file("/c/Holiday 2019.jpg", b""),
+                subdir(
+                    "/c/sub",
+                    vec![
+                        file("/c/sub/report.pdf", b""),
+                        file("/c/sub/holliday.png", b""),
+                    ],
+                ),
+            ],
+        )
+    }
+
+    #[test]
+    fn find_similar() {
+        let dir = tree();
+        let index = NameIndex::build(&dir);
+        assert_eq!(4, index.len());
+        let paths = |ids: Vec<u32>| -> Vec<PathBuf> {
+            ids.into_iter()
+                .map(|id| index.resolve(id, &dir).unwrap().path().to_path_buf())
+                .collect()
+        };
+        let similar = index.similar("HOLIDAY", Metric::Dice, 0.5).unwrap();
+        assert_eq!(
+            vec![
+                PathBuf::from("/c/Holiday 2019.jpg"),
+                PathBuf::from("/c/sub/holliday.png")
+            ],
+            paths(similar.iter().map(|(id, _)| *id).collect())
+        );
+        assert_eq!(12.0 / 17.0, similar[1].1);
+        let similar = index.similar("HOLIDAY", Metric::Jaccard, 0.5).unwrap();
+        assert_eq!(
+            vec![3],
+            similar.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+        );
+        assert!(index.similar("HOLIDAY", Metric::Levenshtein, 0.5).is_none());
+        // po occurs twice in popo, names sharing two of its bigrams have it
+        let keys = sorted_bigrams("popo");
+        let any = 0..=usize::MAX;
+        assert_eq!(
+            vec![PathBuf::from("/c/sub/report.pdf")],
+            paths(index.candidates(&keys, &any, |_| 2))
+        );
+        assert!(index.candidates(&keys, &(0..=5), |_| 2).is_empty());
+        // as many as the bigrams of the name
+        assert!(index.candidates(&keys, &(1..=usize::MAX), |m| m).is_empty());
+        assert_eq!(4, index.candidates(&keys, &any, |_| 0).len());
+        // another tree is detected
+        let mut other = tree();
+        other.content.swap(0, 1);
+        assert!(index.resolve(3, &other).is_none());
+    }
+
+    #[test]
+    fn candidates_scale() {
+        // names of random words, as many again are numbered photos
+        let mut seed = 42u64;
+        let mut word = |len: usize| -> String {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    char::from(b'a' + (seed >> 33) as u8 % 26)
+                })
+                .collect()
+        };
+        let mut content = vec![];
+        for i in 0..10_000 {
+            let name = format!("{} {}.txt", word(3 + i % 5), word(4 + i % 7));
+            content.push(file(&format!("/c/{name}"), b""));
+            content.push(file(&format!("/c/IMG_{i:05}.jpg"), b""));
+        }
+        let dir = dir("/c", content);
+        let index = NameIndex::build(&dir);
+        // a name missing its first letter
+        let pattern = &index.entries[4000].name[1..];
+        for metric in [Metric::Dice, Metric::Jaccard] {
+            // exactly the names found by scoring every one
+            let expected: Vec<(u32, f64)> = (0..index.len() as u32)
+                .filter_map(|id| {
+                    let similarity = metric.similarity(&index.entries[id as usize].name, pattern);
+                    Some((id, similarity)).filter(|_| similarity >= 0.5)
+                })
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(Some(expected), index.similar(pattern, metric, 0.5));
+            // scoring a few of them
+            let keys = sorted_bigrams(pattern);
+            let counts = metric.bigram_counts(keys.len(), 0.5).unwrap();
+            let least = |m| metric.min_common_bigrams(keys.len(), m, 0.5).unwrap();
+            let scored = index.candidates(&keys, &counts, least).len();
+            assert!(scored * 50 < index.len(), "{metric}: {scored} scored");
+        }
+    }
+
+    #[test]
+    fn save_and_load() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let (path, db) = (tempdir.path().join("idx"), tempdir.path().join("db"));
+        assert_eq!(None, NameIndex::load(&path, &db).unwrap());
+        fs::write(&db, b"{}").unwrap();
+        let index = NameIndex::build(&tree());
+        index.save(&path, &db).unwrap();
+        assert_eq!(Some(&index), NameIndex::load(&path, &db).unwrap().as_ref());
+        // stale once the database changes
+        fs::write(&db, b"{ }").unwrap();
+        assert_eq!(None, NameIndex::load(&path, &db).unwrap());
+        let mut postings = Postings::default();
+        for id in [0, 5, 300, 70000] {
+            postings.push(id);
+        }
+        assert_eq!(vec![0, 5, 300, 70000], postings.ids().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,347 @@
+//! Observing the progress of a scan.
+//!
+//! `Collection::scan_with` and `Dir::scan_with` report what they are
+//! doing to a [`ScanObserver`]; the unit type `()` is the observer that
+//! ignores everything.
+//!
+//! Observers can also stop a scan: it is polled with
+//! [`ScanObserver::cancelled`] and fails with the error returned by
+//! [`cancelled`] once that returns true. [`Checkpointer`] saves what was
+//! hashed so far, so that a cancelled scan can be resumed.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::core::filter::{Filter, Symlinks};
+use crate::core::model::{self, is_collection_file, Checkpoint, Collection, File, ScanError};
+
+/// Default time between two checkpoints
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Minimum time between two checkpoints as a multiple of the time the
+/// last one took: every checkpoint rewrites the whole database, which
+/// would otherwise take most of the scan of a large collection
+const SAVE_TIME_RATIO: u32 = 10;
+
+/// Receives the events of a scan, all methods do nothing by default
+pub trait ScanObserver {
+    /// The scan of the tree at root, indexing the entries accepted by
+    /// filter, starts
+    fn started(&mut self, _root: &Path, _filter: &Filter) {}
+    /// A directory is about to be read
+    fn dir_entered(&mut self, _path: &Path) {}
+    /// Another chunk of n bytes of the current file was hashed
+    fn bytes_hashed(&mut self, _n: u64) {}
+    /// A file was indexed
+    fn file_hashed(&mut self, _file: &File) {}
+    /// An entry was left out of the scan
+    fn error(&mut self, _error: &ScanError) {}
+    /// The scan is over, successfully or not
+    fn finished(&mut self) {}
+    /// Polled while scanning, the scan stops as soon as it returns true
+    fn cancelled(&mut self) -> bool {
+        false
+    }
+}
+
+impl ScanObserver for () {}
+
+/// The error a scan fails with when it is cancelled
+pub fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "scan cancelled")
+}
+
+/// Whether error is the one returned by `cancelled`
+pub fn is_cancelled(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::Interrupted
+}
+
+/// Forwards events to an observer, cancelling the scan once flag is set
+/// (e.g. by a signal handler)
+pub struct Cancellable {
+    inner: Box<dyn ScanObserver>,
+    flag: Arc<AtomicBool>,
+}
+
+impl Cancellable {
+    pub fn new(inner: Box<dyn ScanObserver>, flag: Arc<AtomicBool>) -> Self {
+        Cancellable { inner, flag }
+    }
+}
+
+impl ScanObserver for Cancellable {
+    fn started(&mut self, root: &Path, filter: &Filter) {
+        self.inner.started(root, filter)
+    }
+    fn dir_entered(&mut self, path: &Path) {
+        self.inner.dir_entered(path)
+    }
+    fn bytes_hashed(&mut self, n: u64) {
+        self.inner.bytes_hashed(n)
+    }
+    fn file_hashed(&mut self, file: &File) {
+        self.inner.file_hashed(file)
+    }
+    fn error(&mut self, error: &ScanError) {
+        self.inner.error(error)
+    }
+    fn finished(&mut self) {
+        self.inner.finished()
+    }
+    fn cancelled(&mut self) -> bool {
+        self.flag.load(Ordering::Relaxed) || self.inner.cancelled()
+    }
+}
+
+/// Forwards events to an observer, periodically saving the files hashed
+/// so far as the checkpoint of a collection database.
+///
+/// The collection is written as given (e.g. with the tree of the last
+/// complete scan) plus the checkpoint, see `Collection::resume_with`.
+pub struct Checkpointer<'a> {
+    inner: &'a mut dyn ScanObserver,
+    collection: Collection,
+    interval: Duration,
+    last_save: Instant,
+    /// How long the last save took
+    save_time: Duration,
+}
+
+impl<'a> Checkpointer<'a> {
+    pub fn new(inner: &'a mut dyn ScanObserver, collection: Collection) -> Self {
+        Checkpointer {
+            inner,
+            collection,
+            interval: CHECKPOINT_INTERVAL,
+            last_save: Instant::now(),
+            save_time: Duration::ZERO,
+        }
+    }
+
+    /// Sets the (minimum) time between two checkpoints
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Saves the checkpoint now, returns the number of files in it
+    pub fn save(&mut self) -> io::Result<usize> {
+        self.last_save = Instant::now();
+        self.collection.save()?;
+        self.save_time = self.last_save.elapsed();
+        Ok(self.files())
+    }
+
+    /// Number of files hashed so far
+    pub fn files(&self) -> usize {
+        self.collection
+            .checkpoint
+            .as_ref()
+            .map_or(0, |c| c.files.len())
+    }
+}
+
+impl ScanObserver for Checkpointer<'_> {
+    fn started(&mut self, root: &Path, filter: &Filter) {
+        self.inner.started(root, filter)
+    }
+    fn dir_entered(&mut self, path: &Path) {
+        self.inner.dir_entered(path)
+    }
+    fn bytes_hashed(&mut self, n: u64) {
+        self.inner.bytes_hashed(n)
+    }
+    fn file_hashed(&mut self, file: &File) {
+        self.inner.file_hashed(file);
+        self.collection
+            .checkpoint
+            .get_or_insert_with(Checkpoint::default)
+            .files
+            .push(file.clone());
+        if self.last_save.elapsed() >= self.interval.max(self.save_time * SAVE_TIME_RATIO) {
+            // periodic checkpoints are best effort, the final save (see
+            // `save`) reports errors
+            let _ = self.save();
+        }
+    }
+    fn error(&mut self, error: &ScanError) {
+        self.inner.error(error)
+    }
+    fn finished(&mut self) {
+        self.inner.finished()
+    }
+    fn cancelled(&mut self) -> bool {
+        self.inner.cancelled()
+    }
+}
+
+/// Number of files and bytes a scan is going to process
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScanTotals {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Quickly measures the tree at root (reading metadata only) skipping
+/// what a scan with filter skips, used to estimate the remaining time
+/// of a scan. Unreadable entries are ignored.
+pub fn measure(root: &Path, filter: &Filter) -> ScanTotals {
+    let mut totals = ScanTotals::default();
+    let Ok(metadata) = fs::metadata(root) else {
+        return totals;
+    };
+    let mut pending = vec![(root.to_path_buf(), model::device(&metadata))];
+    while let Some((dir, device)) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if is_collection_file(&path) || !filter.accepts(&path) {
+                continue;
+            }
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let followed = file_type.is_symlink() && filter.symlinks == Symlinks::Follow;
+            if file_type.is_symlink() && !followed {
+                continue;
+            }
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            if metadata.is_dir() {
+                if followed && model::is_loop(&path)
+                    || filter.one_filesystem && model::device(&metadata) != device
+                {
+                    continue;
+                }
+                pending.push((path, model::device(&metadata)));
+            } else if metadata.is_file()
+                && (!filter.checks_files() || filter.accepts_file(&path, &metadata))
+            {
+                totals.files += 1;
+                totals.bytes += metadata.len();
+            }
+        }
+    }
+    totals
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::core::model::{Collection, DB_FILE_NAME};
+
+    #[derive(Default)]
+    struct Counter {
+        dirs: usize,
+        files: usize,
+        bytes: u64,
+        finished: bool,
+    }
+
+    impl ScanObserver for Counter {
+        fn dir_entered(&mut self, _path: &Path) {
+            self.dirs += 1;
+        }
+        fn bytes_hashed(&mut self, n: u64) {
+            self.bytes += n;
+        }
+        fn file_hashed(&mut self, _file: &File) {
+            self.files += 1;
+        }
+        fn finished(&mut self) {
+            self.finished = true;
+        }
+    }
+
+    /// Cancels the scan after the first file
+    #[derive(Default)]
+    struct StopAfterFirst {
+        files: usize,
+    }
+
+    impl ScanObserver for StopAfterFirst {
+        fn file_hashed(&mut self, _file: &File) {
+            self.files += 1;
+        }
+        fn cancelled(&mut self) -> bool {
+            self.files > 0
+        }
+    }
+
+    #[test]
+    fn cancel_and_resume() {
+        let tempdir = tempdir().unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(tempdir.path().join(name), name).unwrap();
+        }
+        let mut stop = StopAfterFirst::default();
+        let mut checkpointer = Checkpointer::new(&mut stop, Collection::from(tempdir.path()))
+            .with_interval(Duration::ZERO);
+        let mut collection = Collection::from(tempdir.path());
+        let error = collection.resume_with(&mut checkpointer).unwrap_err();
+        assert!(is_cancelled(&error));
+        assert_eq!(1, checkpointer.save().unwrap());
+
+        let mut stored = Collection::from(tempdir.path());
+        assert!(stored.load().unwrap());
+        assert!(stored.root_dir.is_none());
+        let mut checkpoint = stored.checkpoint.take().unwrap();
+        assert_eq!(1, checkpoint.files.len());
+        // a hash that cannot be computed proves the file is not read again
+        checkpoint.files[0].md5 = md5::Digest([0; 16]);
+        let resumed = checkpoint.files[0].path.clone();
+        stored.checkpoint = Some(checkpoint);
+
+        let mut counter = Counter::default();
+        assert!(stored.resume_with(&mut counter).unwrap().is_empty());
+        assert!(stored.checkpoint.is_none());
+        assert_eq!(3, counter.files);
+        for file in stored.root_dir.unwrap().all_files() {
+            let expected = match file.path == resumed {
+                true => md5::Digest([0; 16]),
+                false => md5::compute(fs::read(&file.path).unwrap()),
+            };
+            assert_eq!(expected, file.md5);
+        }
+    }
+
+    #[test]
+    fn observe_scan() {
+        let tempdir = tempdir().unwrap();
+        fs::create_dir(tempdir.path().join("sub")).unwrap();
+        fs::write(tempdir.path().join("a"), b"abc").unwrap();
+        fs::write(tempdir.path().join("sub").join("b"), vec![0; 100_000]).unwrap();
+        fs::write(tempdir.path().join(DB_FILE_NAME), b"{}").unwrap();
+
+        assert_eq!(
+            ScanTotals {
+                files: 2,
+                bytes: 100_003
+            },
+            measure(tempdir.path(), &Filter::default())
+        );
+        let mut filter = Filter::new(tempdir.path(), &["sub".to_string()]);
+        assert_eq!(1, measure(tempdir.path(), &filter).files);
+        filter = Filter::default();
+        filter.min_size = Some(10);
+        assert_eq!(100_000, measure(tempdir.path(), &filter).bytes);
+        let mut counter = Counter::default();
+        let mut collection = Collection::from(tempdir.path());
+        collection.scan_with(&mut counter).unwrap();
+        assert_eq!(2, counter.dirs);
+        assert_eq!(2, counter.files);
+        assert_eq!(100_003, counter.bytes);
+        assert!(counter.finished);
+    }
+}
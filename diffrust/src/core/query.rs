@@ -0,0 +1,634 @@
+//! Query expressions selecting entries by their indexed metadata.
+//!
+//! A query compares fields of the entries with values and combines the
+//! comparisons with `and`, `or`, `not` and parentheses (`not` binds
+//! tighter than `and`, which binds tighter than `or`):
+//!
+//! ```text
+//! size>100M and ext in (mkv,mp4) and mtime<2023-01-01 and not path~"/tmp/"
+//! ```
+//!
+//! | field   | values                          | operators                 |
+//! |---------|---------------------------------|---------------------------|
+//! | `name`  | text                            | `=` `!=` `~` `!~` `in`    |
+//! | `path`  | text, relative to the root with a leading `/` | same      |
+//! | `ext`   | text, without the dot           | same                      |
+//! | `mime`  | text, files only                | same                      |
+//! | `md5`   | hex digest, files only          | same                      |
+//! | `type`  | `file`, `dir` or `link`         | `=` `!=` `in`             |
+//! | `size`  | bytes, units K, M, G, T (1K = 1024), directories the size of their files | `=` `!=` `<` `<=` `>` `>=` `in` |
+//! | `mtime` | `YYYY-MM-DD` (the whole day) or RFC 3339 time | same as size |
+//!
+//! Text comparisons ignore case and `~` tests whether the field contains
+//! the value. Values with spaces or operator characters are quoted with
+//! `"` or `'`. A comparison with a field the entry does not have (e.g.
+//! the md5 of a directory) is false.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::time::SystemTime;
+
+use chrono::NaiveDate;
+
+use crate::core::filter::{parse_date, parse_size};
+use crate::core::model::{ContentType, Dir};
+
+const FIELDS: &str = "name, path, ext, mime, md5, type, size or mtime";
+
+/// A parsed query
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    expr: Expr,
+}
+
+/// A query that could not be parsed, with the position of the offending
+/// token
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    /// Byte range of the token in the query, empty at the end
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl QueryError {
+    fn new(span: Range<usize>, message: impl Into<String>) -> Self {
+        QueryError {
+            span,
+            message: message.into(),
+        }
+    }
+
+    /// The query with the offending token underlined on the next line
+    pub fn pointer(&self, query: &str) -> String {
+        let start = query[..self.span.start].chars().count();
+        let width = query[self.span.clone()].chars().count().max(1);
+        format!("{query}\n{}{}", " ".repeat(start), "^".repeat(width))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Name,
+    Path,
+    Ext,
+    Mime,
+    Md5,
+    Type,
+    Size,
+    Mtime,
+}
+
+impl Field {
+    fn parse(word: &str) -> Option<Self> {
+        Some(match word.to_lowercase().as_str() {
+            "name" => Field::Name,
+            "path" => Field::Path,
+            "ext" | "extension" => Field::Ext,
+            "mime" => Field::Mime,
+            "md5" => Field::Md5,
+            "type" => Field::Type,
+            "size" => Field::Size,
+            "mtime" | "modified" => Field::Mtime,
+            _ => return None,
+        })
+    }
+
+    fn is_ordered(&self) -> bool {
+        matches!(self, Field::Size | Field::Mtime)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+    NotContains,
+    In,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    /// Lowercase text
+    Text(String),
+    Size(u64),
+    Time(SystemTime),
+    /// A day, from its start to the start of the next one
+    Day(SystemTime, SystemTime),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare {
+        field: Field,
+        op: Op,
+        values: Vec<Value>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    /// A quoted value, never a keyword
+    Quoted(String),
+    Op(Op),
+    Open,
+    Close,
+    Comma,
+}
+
+impl Token {
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(w) if w.eq_ignore_ascii_case(keyword))
+    }
+}
+
+/// Splits the query into tokens with their byte ranges
+fn tokenize(query: &str) -> Result<Vec<(Token, Range<usize>)>, QueryError> {
+    let mut tokens = vec![];
+    let mut chars = query.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let mut end = start + c.len_utf8();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            ',' => Token::Comma,
+            '=' | '!' | '<' | '>' | '~' => {
+                let next = chars.next_if(|(_, n)| matches!(n, '=' | '~'));
+                let op = match (c, next.map(|(_, n)| n)) {
+                    ('=', Some('=')) | ('=', None) => Op::Eq,
+                    ('!', Some('=')) => Op::Ne,
+                    ('!', Some('~')) => Op::NotContains,
+                    ('<', Some('=')) => Op::Le,
+                    ('<', None) => Op::Lt,
+                    ('>', Some('=')) => Op::Ge,
+                    ('>', None) => Op::Gt,
+                    ('~', None) => Op::Contains,
+                    _ => {
+                        let end = next.map_or(end, |(i, n)| i + n.len_utf8());
+                        return Err(QueryError::new(start..end, "unknown operator"));
+                    }
+                };
+                if let Some((i, n)) = next {
+                    end = i + n.len_utf8();
+                }
+                Token::Op(op)
+            }
+            '"' | '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((i, q)) if q == c => {
+                            end = i + 1;
+                            break;
+                        }
+                        Some((_, '\\')) => {
+                            if let Some((_, escaped)) = chars.next() {
+                                text.push(escaped)
+                            }
+                        }
+                        Some((_, other)) => text.push(other),
+                        None => {
+                            return Err(QueryError::new(
+                                start..query.len(),
+                                "unterminated quoted value",
+                            ))
+                        }
+                    }
+                }
+                Token::Quoted(text)
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some((i, n)) =
+                    chars.next_if(|(_, n)| !n.is_whitespace() && !"()=!<>~,\"'".contains(*n))
+                {
+                    word.push(n);
+                    end = i + n.len_utf8();
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push((token, start..end));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token, Range<usize>)>,
+    position: usize,
+    query: &'a str,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(t, _)| t)
+    }
+
+    /// Range of the next token, the end of the query when there is none
+    fn span(&self) -> Range<usize> {
+        match self.tokens.get(self.position) {
+            Some((_, span)) => span.clone(),
+            None => self.query.len()..self.query.len(),
+        }
+    }
+
+    fn next(&mut self) -> Option<(Token, Range<usize>)> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.and()?;
+        while self.peek().is_some_and(|t| t.is_keyword("or")) {
+            self.position += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.unary()?;
+        while self.peek().is_some_and(|t| t.is_keyword("and")) {
+            self.position += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, QueryError> {
+        if self.peek().is_some_and(|t| t.is_keyword("not")) {
+            self.position += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.peek() == Some(&Token::Open) {
+            self.position += 1;
+            let expr = self.or()?;
+            return match self.next() {
+                Some((Token::Close, _)) => Ok(expr),
+                _ => Err(QueryError::new(self.previous_span(), "expected ')'")),
+            };
+        }
+        self.comparison()
+    }
+
+    /// Range of the token just consumed (or missing)
+    fn previous_span(&self) -> Range<usize> {
+        match self.tokens.get(self.position - 1) {
+            Some((_, span)) => span.clone(),
+            None => self.query.len()..self.query.len(),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, QueryError> {
+        let span = self.span();
+        let field = match self.next() {
+            Some((Token::Word(word), _)) => Field::parse(&word).ok_or_else(|| {
+                QueryError::new(span, format!("unknown field '{word}', expected {FIELDS}"))
+            })?,
+            _ => {
+                return Err(QueryError::new(
+                    span,
+                    format!("expected a field ({FIELDS})"),
+                ))
+            }
+        };
+        let span = self.span();
+        let op = match self.next() {
+            Some((Token::Op(op), _)) => op,
+            Some((t, _)) if t.is_keyword("in") => Op::In,
+            _ => {
+                return Err(QueryError::new(
+                    span,
+                    "expected an operator (=, !=, <, <=, >, >=, ~, !~ or in)",
+                ))
+            }
+        };
+        let applies = match op {
+            Op::Lt | Op::Le | Op::Gt | Op::Ge => field.is_ordered(),
+            Op::Contains | Op::NotContains => !field.is_ordered() && field != Field::Type,
+            Op::Eq | Op::Ne | Op::In => true,
+        };
+        if !applies {
+            let name = &self.query[span.clone()];
+            return Err(QueryError::new(
+                span,
+                format!("'{name}' does not apply to {field:?}").to_lowercase(),
+            ));
+        }
+        let values = match op {
+            Op::In => {
+                let span = self.span();
+                if self.next().map(|(t, _)| t) != Some(Token::Open) {
+                    return Err(QueryError::new(span, "expected '(' after in"));
+                }
+                let mut values = vec![self.value(field)?];
+                loop {
+                    let span = self.span();
+                    match self.next() {
+                        Some((Token::Comma, _)) => values.push(self.value(field)?),
+                        Some((Token::Close, _)) => break,
+                        _ => return Err(QueryError::new(span, "expected ',' or ')'")),
+                    }
+                }
+                values
+            }
+            _ => vec![self.value(field)?],
+        };
+        Ok(Expr::Compare { field, op, values })
+    }
+
+    fn value(&mut self, field: Field) -> Result<Value, QueryError> {
+        let span = self.span();
+        let text = match self.next() {
+            Some((Token::Word(text), _)) | Some((Token::Quoted(text), _)) => text,
+            _ => return Err(QueryError::new(span, "expected a value")),
+        };
+        let invalid = |message| QueryError::new(span.clone(), message);
+        Ok(match field {
+            Field::Size => Value::Size(parse_size(&text).map_err(invalid)?),
+            Field::Mtime => {
+                let start = parse_date(&text).map_err(invalid)?;
+                let next = NaiveDate::parse_from_str(text.trim(), "%Y-%m-%d")
+                    .ok()
+                    .and_then(|day| day.succ_opt());
+                match next {
+                    Some(next) => {
+                        let end = parse_date(&next.to_string()).map_err(invalid)?;
+                        Value::Day(start, end)
+                    }
+                    None => Value::Time(start),
+                }
+            }
+            Field::Type if !matches!(text.as_str(), "file" | "dir" | "link") => {
+                return Err(invalid(format!(
+                    "unknown type '{text}', expected file, dir or link"
+                )))
+            }
+            _ => Value::Text(text.to_lowercase()),
+        })
+    }
+}
+
+impl Query {
+    /// Parses a query expression
+    pub fn parse(query: &str) -> Result<Self, QueryError> {
+        let mut parser = Parser {
+            tokens: tokenize(query)?,
+            position: 0,
+            query,
+        };
+        let expr = parser.or()?;
+        if parser.peek().is_some() {
+            let span = parser.span();
+            let token = &query[span.clone()];
+            return Err(QueryError::new(
+                span,
+                format!("unexpected '{token}', expected and, or or the end of the query"),
+            ));
+        }
+        Ok(Query { expr })
+    }
+
+    /// Whether entry, in the collection at root, matches the query
+    pub fn matches(&self, entry: &ContentType, root: &Path) -> bool {
+        evaluate(&self.expr, entry, root, None)
+    }
+
+    /// All the entries of dir, at any depth, matching the query
+    pub fn find<'a>(&self, dir: &'a Dir, root: &Path) -> Vec<&'a ContentType> {
+        let mut matches = vec![];
+        self.find_into(dir, root, &mut matches);
+        matches.into_iter().flatten().collect()
+    }
+
+    /// Adds the matches in dir to matches, returns the size of dir.
+    /// Directories are matched once their size is known (computing it
+    /// for each of them would read the subtree again), at the position
+    /// kept for them before their content.
+    fn find_into<'a>(
+        &self,
+        dir: &'a Dir,
+        root: &Path,
+        matches: &mut Vec<Option<&'a ContentType>>,
+    ) -> u64 {
+        let mut total = 0;
+        for item in dir.content.iter() {
+            let size = match item {
+                ContentType::ContentDir(d) => {
+                    let at = matches.len();
+                    matches.push(None);
+                    let size = self.find_into(d, root, matches);
+                    if evaluate(&self.expr, item, root, Some(size)) {
+                        matches[at] = Some(item);
+                    }
+                    size
+                }
+                _ => {
+                    if self.matches(item, root) {
+                        matches.push(Some(item));
+                    }
+                    item.size()
+                }
+            };
+            total += size;
+        }
+        total
+    }
+}
+
+/// Whether entry matches expr, size is the size of the entry when known
+fn evaluate(expr: &Expr, entry: &ContentType, root: &Path, size: Option<u64>) -> bool {
+    match expr {
+        Expr::And(a, b) => evaluate(a, entry, root, size) && evaluate(b, entry, root, size),
+        Expr::Or(a, b) => evaluate(a, entry, root, size) || evaluate(b, entry, root, size),
+        Expr::Not(e) => !evaluate(e, entry, root, size),
+        Expr::Compare { field, op, values } => {
+            let Some(actual) = field_value(*field, entry, root, size) else {
+                return false;
+            };
+            let order = |value| compare(&actual, value);
+            match op {
+                Op::Eq | Op::In => values.iter().any(|v| order(v) == Some(Ordering::Equal)),
+                Op::Ne => order(&values[0]) != Some(Ordering::Equal),
+                Op::Lt => order(&values[0]) == Some(Ordering::Less),
+                Op::Le => matches!(order(&values[0]), Some(Ordering::Less | Ordering::Equal)),
+                Op::Gt => order(&values[0]) == Some(Ordering::Greater),
+                Op::Ge => matches!(order(&values[0]), Some(Ordering::Greater | Ordering::Equal)),
+                Op::Contains | Op::NotContains => {
+                    let contains = match (&actual, &values[0]) {
+                        (Value::Text(a), Value::Text(v)) => a.contains(v.as_str()),
+                        _ => false,
+                    };
+                    contains == (*op == Op::Contains)
+                }
+            }
+        }
+    }
+}
+
+/// Order of the value of an entry relative to a value of the query, a
+/// time within a day is equal to the day
+fn compare(actual: &Value, value: &Value) -> Option<Ordering> {
+    match (actual, value) {
+        (Value::Size(a), Value::Size(b)) => a.partial_cmp(b),
+        (Value::Time(a), Value::Time(b)) => a.partial_cmp(b),
+        (Value::Time(t), Value::Day(start, end)) => Some(match t {
+            t if t < start => Ordering::Less,
+            t if t >= end => Ordering::Greater,
+            _ => Ordering::Equal,
+        }),
+        (Value::Text(a), Value::Text(b)) => a.partial_cmp(b),
+        _ => None,
+    }
+}
+
+/// The value of field for entry, None when the entry does not have it.
+/// The size of directories is computed unless given.
+fn field_value(field: Field, entry: &ContentType, root: &Path, size: Option<u64>) -> Option<Value> {
+    let path = entry.path();
+    let text = |s: &str| Some(Value::Text(s.to_lowercase()));
+    match field {
+        Field::Name => text(&path.file_name()?.to_string_lossy()),
+        Field::Path => {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            text(&format!("/{}", relative.to_string_lossy()))
+        }
+        Field::Ext => text(&path.extension().unwrap_or_default().to_string_lossy()),
+        Field::Type => text(match entry {
+            ContentType::ContentDir(_) => "dir",
+            ContentType::ContentFile(_) => "file",
+            ContentType::ContentLink(_) => "link",
+        }),
+        Field::Size => Some(Value::Size(size.unwrap_or_else(|| entry.size()))),
+        Field::Mtime => Some(Value::Time(entry.modified())),
+        Field::Md5 | Field::Mime => {
+            let ContentType::ContentFile(file) = entry else {
+                return None;
+            };
+            match field {
+                Field::Md5 => text(&format!("{:x}", file.md5)),
+                _ => text(file.mime.as_deref()?),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::core::model::File;
+
+    fn file(path: &str, size: u64, days: u64) -> ContentType {
+        ContentType::ContentFile(File {
+            path: PathBuf::from(path),
+            size,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(days * 86400),
+            mime: Some(String::from("video/x-matroska")),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn evaluate_queries() {
+        let root = Path::new("/c");
+        let movie = file("/c/films/Big.MKV", 200 << 20, 19000);
+        let clip = file("/c/tmp/clip.mp4", 300 << 20, 19000);
+        let recent = file("/c/films/new.mkv", 200 << 20, 20000);
+        let query = Query::parse(
+            r#"size>100M and ext in (mkv,mp4) and mtime<2023-01-01 and not path~"/tmp/""#,
+        )
+        .unwrap();
+        assert!(query.matches(&movie, root));
+        assert!(!query.matches(&clip, root));
+        assert!(!query.matches(&recent, root));
+
+        let matches = |q: &str, entry: &ContentType| Query::parse(q).unwrap().matches(entry, root);
+        assert!(matches("name = big.mkv", &movie));
+        assert!(matches(
+            "type=file and (size <= 1K or mime ~ video)",
+            &movie
+        ));
+        assert!(matches("not (path ~ films or name !~ clip)", &clip));
+        assert!(matches("NAME = 'big.mkv' OR size = 0", &movie));
+        assert!(!matches(
+            "md5 = x or mime = x",
+            &ContentType::ContentDir(Dir::default())
+        ));
+
+        // dates are whole days
+        let day =
+            chrono::DateTime::<chrono::Local>::from(recent.modified() + Duration::from_secs(1))
+                .format("%Y-%m-%d")
+                .to_string();
+        assert!(matches(&format!("mtime = {day}"), &recent));
+        assert!(matches(
+            &format!("mtime <= {day} and mtime >= {day}"),
+            &recent
+        ));
+        assert!(!matches(
+            &format!("mtime != {day} or mtime > {day}"),
+            &recent
+        ));
+
+        let dir = Dir {
+            path: PathBuf::from("/"),
+            content: vec![ContentType::ContentDir(Dir {
+                path: PathBuf::from("/c"),
+                content: vec![movie, clip, recent],
+                ..Default::default()
+            })],
+            ..Default::default()
+        };
+        assert_eq!(Query::parse("ext = mkv").unwrap().find(&dir, root).len(), 2);
+        let found = Query::parse("size > 600M").unwrap().find(&dir, root);
+        assert_eq!(
+            vec![Path::new("/c")],
+            found.iter().map(|e| e.path()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |q: &str| {
+            let e = Query::parse(q).unwrap_err();
+            (q[e.span.clone()].to_string(), e.message)
+        };
+        assert_eq!(error("sise > 1").0, "sise");
+        assert!(error("sise > 1").1.starts_with("unknown field 'sise'"));
+        assert_eq!(error("size ~ 1").0, "~");
+        assert_eq!(error("size > 1Q").0, "1Q");
+        assert_eq!(error("mtime < yesterday").0, "yesterday");
+        assert_eq!(error("name = a b").0, "b");
+        assert_eq!(error("type = pipe").0, "pipe");
+        assert_eq!(error("(name = a").1, "expected ')'");
+        assert_eq!(error("name =").1, "expected a value");
+        assert_eq!(error("ext in (a b)").0, "b");
+        assert!(error("name = 'a").1.starts_with("unterminated"));
+
+        let e = Query::parse("size > 1Q").unwrap_err();
+        assert_eq!(e.pointer("size > 1Q"), "size > 1Q\n       ^^");
+    }
+}
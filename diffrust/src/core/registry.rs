@@ -0,0 +1,167 @@
+//! Registry of named collections.
+//!
+//! The registry is a JSON file in the user configuration directory
+//! (`$XDG_CONFIG_HOME/diffrust/collections.json`, `~/.config` when
+//! `XDG_CONFIG_HOME` is not set) mapping names to the roots of
+//! collections and, optionally, to databases not stored in the root.
+//! Commands accept `@name` in place of the path of a registered
+//! collection.
+//!
+//! ```json
+//! {"collections": {"photos": {"root": "/mnt/photos", "db": "/backup/photos.db"}}}
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::name;
+
+pub const REGISTRY_FILE_NAME: &str = "collections.json";
+
+/// A collection in the registry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Registered {
+    /// Root of the collection
+    #[serde(with = "name::serde_path")]
+    pub root: PathBuf,
+    /// Database of the collection, None for root/.diffrust.db
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "name::serde_path::option"
+    )]
+    pub db: Option<PathBuf>,
+}
+
+/// Named collections, sorted by name
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Registry {
+    #[serde(default)]
+    pub collections: BTreeMap<String, Registered>,
+}
+
+impl Registry {
+    /// Path of the registry in the user configuration directory
+    pub fn default_path() -> io::Result<PathBuf> {
+        let config = match (env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME")) {
+            (Some(dir), _) if !dir.is_empty() => PathBuf::from(dir),
+            (_, Some(home)) if !home.is_empty() => PathBuf::from(home).join(".config"),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no configuration directory, set XDG_CONFIG_HOME or HOME",
+                ))
+            }
+        };
+        Ok(config.join(env!("CARGO_PKG_NAME")).join(REGISTRY_FILE_NAME))
+    }
+
+    /// Reads the registry at path, empty if the file does not exist
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(serde_json::from_str(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Registry::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the registry to path, creating its directory
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    /// The collection registered with name
+    pub fn get(&self, name: &str) -> Option<&Registered> {
+        self.collections.get(name)
+    }
+
+    /// Registers a collection, returning the one previously registered
+    /// with the same name
+    pub fn add(&mut self, name: &str, collection: Registered) -> Option<Registered> {
+        self.collections.insert(name.to_string(), collection)
+    }
+
+    /// Removes the collection registered with name
+    pub fn remove(&mut self, name: &str) -> Option<Registered> {
+        self.collections.remove(name)
+    }
+
+    /// Names of the collections registered at root
+    pub fn names_of(&self, root: &Path) -> Vec<&str> {
+        self.collections
+            .iter()
+            .filter(|(_, c)| c.root == root)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+/// The name in a `@name` argument, None for other paths
+pub fn collection_name(path: &Path) -> Option<&str> {
+    path.to_str()?.strip_prefix('@')
+}
+
+/// Names are made of letters, digits, `.`, `_` and `-`, so that they
+/// can be written after `@` without quoting
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("diffrust").join(REGISTRY_FILE_NAME);
+        let mut registry = Registry::load(&path).unwrap();
+        assert!(registry.collections.is_empty());
+
+        let photos = Registered {
+            root: PathBuf::from("/mnt/photos"),
+            db: None,
+        };
+        let music = Registered {
+            root: PathBuf::from("/mnt/music"),
+            db: Some(PathBuf::from("/backup/music.db")),
+        };
+        assert!(registry.add("photos", photos.clone()).is_none());
+        assert!(registry.add("music", music.clone()).is_none());
+        assert!(registry.add("pics", photos.clone()).is_none());
+        registry.save(&path).unwrap();
+
+        let mut loaded = Registry::load(&path).unwrap();
+        assert_eq!(loaded, registry);
+        assert_eq!(loaded.get("music"), Some(&music));
+        assert_eq!(
+            loaded.names_of(Path::new("/mnt/photos")),
+            vec!["photos", "pics"]
+        );
+        assert_eq!(loaded.remove("pics"), Some(photos));
+        assert_eq!(loaded.remove("pics"), None);
+        assert!(loaded.get("pics").is_none());
+    }
+
+    #[test]
+    fn collection_names() {
+        assert_eq!(collection_name(Path::new("@photos")), Some("photos"));
+        assert_eq!(collection_name(Path::new("photos")), None);
+        assert_eq!(collection_name(Path::new("./@photos")), None);
+        assert!(is_valid_name("drive-2024_b.1"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("a b"));
+        assert!(!is_valid_name("a/b"));
+    }
+}
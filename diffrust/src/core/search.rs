@@ -0,0 +1,430 @@
+//! Matching of entries by name for find.
+//!
+//! A `Matcher` looks for a pattern in the name of an entry or, for
+//! regular expressions and fuzzy patterns, optionally in its path
+//! relative to the root, and returns the matched ranges so that they can
+//! be highlighted. Fuzzy matches are also scored, best first, either as
+//! a subsequence or by the similarity of whole names.
+
+use std::ops::Range;
+use std::path::Path;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::core::algorithm::{self, Metric};
+use crate::core::model::{ContentType, Dir};
+use crate::core::ngram::NameIndex;
+
+/// A matched part of a name or relative path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Highlight {
+    /// Byte range in the matched text
+    pub range: Range<usize>,
+    /// 0 for a whole match, n for the capture group n of a regular
+    /// expression
+    pub group: usize,
+}
+
+/// An entry found by a `Matcher`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Found<'a> {
+    pub entry: &'a ContentType,
+    /// Whether the highlights are in the path relative to the root
+    /// rather than in the name
+    pub on_path: bool,
+    pub highlights: Vec<Highlight>,
+    /// How well the entry matches, for scoring modes
+    pub score: Option<f64>,
+}
+
+impl Found<'_> {
+    /// The text the highlights refer to, the name or relative path
+    pub fn text(&self, root: &Path) -> String {
+        subject(self.entry, root, self.on_path)
+    }
+}
+
+/// Least similarity of the names found with a metric
+pub const MIN_SIMILARITY: f64 = 0.5;
+
+/// How find interprets its pattern
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FindMode {
+    #[default]
+    Substring,
+    Regex,
+    Fuzzy,
+}
+
+/// How find matches the names of entries
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    /// Substring of the name, ignoring case. An empty pattern matches
+    /// every entry.
+    Substring(String),
+    /// Regular expression searched in the name, or in the path relative
+    /// to the root (with `/` separators) when on_path is set
+    Regex { regex: Regex, on_path: bool },
+    /// Characters of the pattern in order, not necessarily adjacent,
+    /// scored by `algorithm::fuzzy_match`, or with a metric names at
+    /// least `MIN_SIMILARITY` similar to the pattern
+    Fuzzy {
+        pattern: String,
+        on_path: bool,
+        metric: Option<Metric>,
+    },
+}
+
+impl Matcher {
+    /// Matcher for a pattern given on the command line, on_path is
+    /// ignored by substrings
+    pub fn new(pattern: &str, mode: FindMode, on_path: bool) -> Result<Self, regex::Error> {
+        Ok(match mode {
+            FindMode::Substring => Matcher::Substring(pattern.to_string()),
+            FindMode::Regex => Matcher::Regex {
+                regex: Regex::new(pattern)?,
+                on_path,
+            },
+            FindMode::Fuzzy => Matcher::Fuzzy {
+                pattern: pattern.to_string(),
+                on_path,
+                metric: None,
+            },
+        })
+    }
+
+    /// Scores fuzzy matches by the similarity metric instead
+    pub fn with_metric(mut self, metric: Option<Metric>) -> Self {
+        if let Matcher::Fuzzy { metric: m, .. } = &mut self {
+            *m = metric;
+        }
+        self
+    }
+
+    /// The similarity metric of fuzzy matches, if any
+    pub fn metric(&self) -> Option<Metric> {
+        match self {
+            Matcher::Fuzzy { metric, .. } => *metric,
+            _ => None,
+        }
+    }
+
+    /// The pattern as given
+    pub fn pattern(&self) -> &str {
+        match self {
+            Matcher::Substring(pattern) | Matcher::Fuzzy { pattern, .. } => pattern,
+            Matcher::Regex { regex, .. } => regex.as_str(),
+        }
+    }
+
+    pub fn mode(&self) -> FindMode {
+        match self {
+            Matcher::Substring(_) => FindMode::Substring,
+            Matcher::Regex { .. } => FindMode::Regex,
+            Matcher::Fuzzy { .. } => FindMode::Fuzzy,
+        }
+    }
+
+    /// Whether the matches are in the relative path
+    pub fn on_path(&self) -> bool {
+        match self {
+            Matcher::Substring(_) => false,
+            Matcher::Regex { on_path, .. } | Matcher::Fuzzy { on_path, .. } => *on_path,
+        }
+    }
+
+    /// Whether entries are searched at any depth, substrings are only
+    /// searched in the content of the directory itself
+    pub fn is_recursive(&self) -> bool {
+        !matches!(self, Matcher::Substring(_))
+    }
+
+    /// The highlights of entry, in the collection at root, None when it
+    /// does not match
+    pub fn find_in(&self, entry: &ContentType, root: &Path) -> Option<Vec<Highlight>> {
+        self.scored(entry, root).map(|(highlights, _)| highlights)
+    }
+
+    /// The highlights and score of entry, None when it does not match
+    fn scored(&self, entry: &ContentType, root: &Path) -> Option<(Vec<Highlight>, Option<f64>)> {
+        let highlights: Vec<Highlight> = match self {
+            Matcher::Substring(pattern) if pattern.is_empty() => return Some((vec![], None)),
+            Matcher::Substring(pattern) => algorithm::substrings_in_name(entry.path(), pattern)
+                .into_iter()
+                .map(|range| Highlight { range, group: 0 })
+                .collect(),
+            Matcher::Regex { regex, on_path } => {
+                let text = subject(entry, root, *on_path);
+                regex
+                    .captures_iter(&text)
+                    .flat_map(|captures| {
+                        captures
+                            .iter()
+                            .enumerate()
+                            .filter_map(|(group, m)| {
+                                // empty groups have nothing to highlight
+                                let m = m.filter(|m| group == 0 || !m.is_empty())?;
+                                Some(Highlight {
+                                    range: m.range(),
+                                    group,
+                                })
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .collect()
+            }
+            Matcher::Fuzzy {
+                pattern,
+                on_path,
+                metric: Some(metric),
+            } => {
+                let text = subject(entry, root, *on_path);
+                let score = metric.similarity(&text, pattern);
+                return Some((vec![], Some(score))).filter(|_| score >= MIN_SIMILARITY);
+            }
+            Matcher::Fuzzy {
+                pattern,
+                on_path,
+                metric: None,
+            } => {
+                let text = subject(entry, root, *on_path);
+                let m = algorithm::fuzzy_match(&text, pattern)?;
+                return Some((char_ranges(&text, &m.positions), Some(m.score as f64)));
+            }
+        };
+        Some((highlights, None)).filter(|(h, _)| !h.is_empty())
+    }
+
+    /// The entries of dir matching, see `is_recursive`
+    pub fn find<'a>(&self, dir: &'a Dir, root: &Path) -> Vec<Found<'a>> {
+        let entries = match self.is_recursive() {
+            true => entries(dir),
+            false => dir.content.iter().collect(),
+        };
+        self.filter(entries, root)
+    }
+
+    /// Whether `find_indexed` applies, to names compared by a metric
+    /// bounded by common bigrams (see `Metric::min_common_bigrams`)
+    pub fn uses_index(&self) -> bool {
+        match self {
+            Matcher::Fuzzy {
+                on_path: false,
+                metric: Some(metric),
+                ..
+            } => metric.min_common_bigrams(0, 0, MIN_SIMILARITY).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Like `find`, with the names similar enough found by the index of
+    /// the names of dir (see `NameIndex::similar`). None when the index
+    /// does not apply (see `uses_index`) or was not built from dir.
+    pub fn find_indexed<'a>(&self, dir: &'a Dir, index: &NameIndex) -> Option<Vec<Found<'a>>> {
+        let metric = self.metric().filter(|_| self.uses_index())?;
+        let mut found = index
+            .similar(self.pattern(), metric, MIN_SIMILARITY)?
+            .into_iter()
+            .map(|(id, score)| {
+                Some(Found {
+                    entry: index.resolve(id, dir)?,
+                    on_path: false,
+                    highlights: vec![],
+                    score: Some(score),
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        best_first(&mut found);
+        Some(found)
+    }
+
+    /// The entries matching among the given ones, best scores first
+    /// for scoring modes
+    pub fn filter<'a>(&self, entries: Vec<&'a ContentType>, root: &Path) -> Vec<Found<'a>> {
+        let mut found: Vec<Found> = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let (highlights, score) = self.scored(entry, root)?;
+                Some(Found {
+                    entry,
+                    on_path: self.on_path(),
+                    highlights,
+                    score,
+                })
+            })
+            .collect();
+        best_first(&mut found);
+        found
+    }
+}
+
+/// Sorts found by score, best first, equal scores staying in tree order
+fn best_first(found: &mut [Found]) {
+    found.sort_by(|a, b| b.score.unwrap_or(0.0).total_cmp(&a.score.unwrap_or(0.0)));
+}
+
+/// All the entries of dir at any depth, parents before their content
+pub fn entries(dir: &Dir) -> Vec<&ContentType> {
+    let mut all = vec![];
+    for item in dir.content.iter() {
+        all.push(item);
+        if let ContentType::ContentDir(d) = item {
+            all.extend(entries(d));
+        }
+    }
+    all
+}
+
+/// Highlights of the characters at the byte offsets positions of text,
+/// adjacent ones merged
+fn char_ranges(text: &str, positions: &[usize]) -> Vec<Highlight> {
+    let mut highlights: Vec<Highlight> = vec![];
+    for &start in positions {
+        let end = start + text[start..].chars().next().map_or(0, char::len_utf8);
+        match highlights.last_mut() {
+            Some(last) if last.range.end == start => last.range.end = end,
+            _ => highlights.push(Highlight {
+                range: start..end,
+                group: 0,
+            }),
+        }
+    }
+    highlights
+}
+
+/// The name of entry, or its path relative to root
+fn subject(entry: &ContentType, root: &Path, on_path: bool) -> String {
+    let path = entry.path();
+    match on_path {
+        true => path
+            .strip_prefix(root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned(),
+        false => path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::fixtures::{dir, file, subdir};
+
+    #[test]
+    fn regex_highlights() {
+        let root = Path::new("/c");
+        let matcher = Matcher::new(r"IMG_(\d{4})\.(jpe?g|heic)", FindMode::Regex, false).unwrap();
+        let photo = file("/c/2023/IMG_0042.jpeg", b"");
+        let highlights = matcher.find_in(&photo, root).unwrap();
+        let groups: Vec<(Range<usize>, usize)> =
+            highlights.into_iter().map(|h| (h.range, h.group)).collect();
+        assert_eq!(vec![(0..13, 0), (4..8, 1), (9..13, 2)], groups);
+        assert!(matcher.find_in(&file("/c/IMG_42.jpg", b""), root).is_none());
+
+        let on_path = Matcher::new(r"^2023/", FindMode::Regex, true).unwrap();
+        assert_eq!(on_path.find_in(&photo, root).unwrap()[0].range, 0..5);
+        assert!(Matcher::new("(", FindMode::Regex, false).is_err());
+    }
+
+    #[test]
+    fn substring_matching() {
+        let root = Path::new("/");
+        let matcher = Matcher::new("ab", FindMode::Substring, false).unwrap();
+        let ranges = |path: &str| -> Option<Vec<(usize, usize)>> {
+            let highlights = matcher.find_in(&file(path, b""), root)?;
+            Some(
+                highlights
+                    .iter()
+                    .map(|h| (h.range.start, h.range.end))
+                    .collect(),
+            )
+        };
+        // exact and mixed case
+        assert_eq!(Some(vec![(0, 2)]), ranges("/tmp/a/abc.txt"));
+        assert_eq!(Some(vec![(0, 2)]), ranges("~/Abbey.jpg"));
+        assert_eq!(None, ranges("Documents/books/"));
+        // not in the parent directories, nor across path components
+        assert_eq!(None, ranges("lab/test/result.csv"));
+        assert_eq!(None, ranges("~/a/b.txt"));
+        // every match
+        assert_eq!(
+            Some(vec![(0, 2), (7, 9), (12, 14)]),
+            ranges("abracadabra.abb")
+        );
+    }
+
+    #[test]
+    fn find_entries() {
+        let root = Path::new("/c");
+        let dir = dir(
+            "/c",
+            vec![
+                file("/c/a.jpg", b""),
+                subdir("/c/sub", vec![file("/c/sub/b.JPG", b"")]),
+            ],
+        );
+        assert_eq!(3, entries(&dir).len());
+        // substrings ignore case but stay in the directory itself
+        let found = Matcher::new("jpg", FindMode::Substring, false)
+            .unwrap()
+            .find(&dir, root);
+        assert_eq!(
+            vec![Path::new("/c/a.jpg")],
+            found.iter().map(|f| f.entry.path()).collect::<Vec<_>>()
+        );
+        let found = Matcher::new("(?i)jpg$", FindMode::Regex, false)
+            .unwrap()
+            .find(&dir, root);
+        assert_eq!(2, found.len());
+        assert_eq!("b.JPG", found[1].text(root));
+        let found = Matcher::new("^sub", FindMode::Regex, true)
+            .unwrap()
+            .find(&dir, root);
+        assert_eq!(2, found.len());
+        assert_eq!(Path::new("sub/b.JPG"), Path::new(&found[1].text(root)));
+        assert_eq!(
+            Matcher::new("", FindMode::Substring, false)
+                .unwrap()
+                .find(&dir, root)
+                .len(),
+            2
+        );
+        // fuzzy matches are at any depth, best first
+        let found = Matcher::new("sbj", FindMode::Fuzzy, true)
+            .unwrap()
+            .find(&dir, root);
+        assert_eq!(1, found.len());
+        let found = Matcher::new("jg", FindMode::Fuzzy, false)
+            .unwrap()
+            .find(&dir, root);
+        assert_eq!(2, found.len());
+        assert!(found[0].score >= found[1].score);
+        assert_eq!(4..5, found[1].highlights[1].range);
+        let found = Matcher::new("A.JPEG", FindMode::Fuzzy, false)
+            .unwrap()
+            .with_metric(Some(Metric::Levenshtein))
+            .find(&dir, root);
+        // one edit for a.jpg, two for b.JPG
+        assert_eq!(2, found.len());
+        assert_eq!(Path::new("/c/a.jpg"), found[0].entry.path());
+        assert_eq!(Some(1.0 - 1.0 / 6.0), found[0].score);
+        assert!(found[1].highlights.is_empty());
+        let index = NameIndex::build(&dir);
+        let matcher = Matcher::new("x.jpg", FindMode::Fuzzy, false)
+            .unwrap()
+            .with_metric(Some(Metric::Dice));
+        // both share three bigrams out of four
+        let found = matcher.find(&dir, root);
+        assert_eq!(2, found.len());
+        assert_eq!(Some(found), matcher.find_indexed(&dir, &index));
+        // edit distances are not bounded by common bigrams
+        let matcher = matcher.with_metric(Some(Metric::Levenshtein));
+        assert_eq!(None, matcher.find_indexed(&dir, &index));
+    }
+}
@@ -0,0 +1,271 @@
+//! Statistics of an indexed tree, for capacity planning.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use crate::core::mime;
+use crate::core::model::{ContentType, Dir, File};
+use crate::core::name;
+use crate::core::usage::{self, Usage};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Upper bounds (exclusive, in seconds) of the file age histogram
+pub const AGE_BUCKETS: [u64; 5] = [DAY, 7 * DAY, 30 * DAY, 365 * DAY, 5 * 365 * DAY];
+
+/// Upper bounds (exclusive, in bytes) of the size distribution, the
+/// first bucket holds empty files only
+pub const SIZE_BUCKETS: [u64; 8] = [
+    1,
+    1 << 10,
+    16 << 10,
+    256 << 10,
+    1 << 20,
+    16 << 20,
+    256 << 20,
+    1 << 30,
+];
+
+/// Number of files and their bytes sharing a key (extension or type)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub key: String,
+    pub count: usize,
+    pub bytes: u64,
+}
+
+/// Files falling in a range of a histogram, below upper when given and
+/// at or above the upper bound of the previous bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bucket {
+    pub upper: Option<u64>,
+    pub count: usize,
+    pub bytes: u64,
+}
+
+/// Statistics report of a tree
+#[derive(Debug, PartialEq)]
+pub struct Stats {
+    pub files: usize,
+    /// Number of directories, the root included
+    pub dirs: usize,
+    pub links: usize,
+    pub bytes: u64,
+    /// Groups by lowercase extension (escaped, see `name::escape`), ""
+    /// for files without one, largest first
+    pub by_extension: Vec<Group>,
+    /// Groups by MIME type, largest first
+    pub by_mime: Vec<Group>,
+    /// Age histogram (time since the last modification)
+    pub ages: Vec<Bucket>,
+    /// Size distribution
+    pub sizes: Vec<Bucket>,
+    pub empty_files: usize,
+    pub empty_dirs: usize,
+    /// Sizes with hard links counted once and duplicates removed
+    pub usage: Usage,
+}
+
+impl Stats {
+    /// Fraction of the bytes (hard links counted once) that are copies
+    /// of other content, 0 for an empty tree
+    pub fn duplication_ratio(&self) -> f64 {
+        if self.usage.size == 0 {
+            return 0.0;
+        }
+        1.0 - self.usage.unique_size as f64 / self.usage.size as f64
+    }
+}
+
+/// Computes the statistics of dir, file ages are relative to now
+pub fn stats(dir: &Dir, now: SystemTime) -> Stats {
+    let mut builder = Builder {
+        now,
+        stats: Stats {
+            files: 0,
+            dirs: 0,
+            links: 0,
+            bytes: 0,
+            by_extension: vec![],
+            by_mime: vec![],
+            ages: buckets(&AGE_BUCKETS),
+            sizes: buckets(&SIZE_BUCKETS),
+            empty_files: 0,
+            empty_dirs: 0,
+            usage: usage::dir_usage(dir)[0].usage,
+        },
+        extensions: HashMap::new(),
+        mimes: HashMap::new(),
+    };
+    builder.add_dir(dir);
+    let mut stats = builder.stats;
+    stats.by_extension = sorted_groups(builder.extensions);
+    stats.by_mime = sorted_groups(builder.mimes);
+    stats
+}
+
+struct Builder {
+    now: SystemTime,
+    stats: Stats,
+    extensions: HashMap<String, (usize, u64)>,
+    mimes: HashMap<String, (usize, u64)>,
+}
+
+impl Builder {
+    fn add_dir(&mut self, dir: &Dir) {
+        self.stats.dirs += 1;
+        if dir.content.is_empty() {
+            self.stats.empty_dirs += 1;
+        }
+        for item in dir.content.iter() {
+            match item {
+                ContentType::ContentDir(d) => self.add_dir(d),
+                ContentType::ContentFile(f) => self.add_file(f),
+                ContentType::ContentLink(_) => self.stats.links += 1,
+            }
+        }
+    }
+
+    fn add_file(&mut self, file: &File) {
+        let stats = &mut self.stats;
+        stats.files += 1;
+        stats.bytes += file.size;
+        if file.size == 0 {
+            stats.empty_files += 1;
+        }
+        let extension = file
+            .path
+            .extension()
+            .map(|e| name::escape(e).to_lowercase())
+            .unwrap_or_default();
+        add_to(&mut self.extensions, extension, file.size);
+        // databases written before type detection only know the name
+        let mime = match &file.mime {
+            Some(m) => m.as_str(),
+            None if file.size == 0 => mime::EMPTY,
+            None => mime::guess(&file.path).unwrap_or(mime::UNKNOWN),
+        };
+        add_to(&mut self.mimes, mime.to_string(), file.size);
+        let age = self
+            .now
+            .duration_since(file.modified)
+            .unwrap_or(Duration::ZERO)
+            .as_secs();
+        add_to_bucket(&mut stats.ages, age, file.size);
+        add_to_bucket(&mut stats.sizes, file.size, file.size);
+    }
+}
+
+fn buckets(bounds: &[u64]) -> Vec<Bucket> {
+    bounds
+        .iter()
+        .map(|b| Some(*b))
+        .chain([None])
+        .map(|upper| Bucket {
+            upper,
+            count: 0,
+            bytes: 0,
+        })
+        .collect()
+}
+
+fn add_to(groups: &mut HashMap<String, (usize, u64)>, key: String, bytes: u64) {
+    let group = groups.entry(key).or_default();
+    group.0 += 1;
+    group.1 += bytes;
+}
+
+fn add_to_bucket(buckets: &mut [Bucket], value: u64, bytes: u64) {
+    // the last bucket is unbounded, so one is always found
+    if let Some(bucket) = buckets
+        .iter_mut()
+        .find(|b| b.upper.is_none_or(|u| value < u))
+    {
+        bucket.count += 1;
+        bucket.bytes += bytes;
+    }
+}
+
+fn sorted_groups(groups: HashMap<String, (usize, u64)>) -> Vec<Group> {
+    let mut groups: Vec<Group> = groups
+        .into_iter()
+        .map(|(key, (count, bytes))| Group { key, count, bytes })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then(b.count.cmp(&a.count))
+            .then(a.key.cmp(&b.key))
+    });
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn file(path: &str, content: &[u8], age: u64, mime: Option<&str>) -> ContentType {
+        ContentType::ContentFile(File {
+            path: PathBuf::from(path),
+            md5: md5::compute(content),
+            size: content.len() as u64,
+            modified: SystemTime::UNIX_EPOCH + Duration::from_secs(10 * 365 * DAY - age),
+            mime: mime.map(String::from),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn tree_stats() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(10 * 365 * DAY);
+        let root = Dir {
+            path: PathBuf::from("/r"),
+            content: vec![
+                file("/r/a.TXT", b"aaaa", 60, Some("text/plain")),
+                file("/r/b.txt", b"aaaa", 2 * DAY, Some("text/plain")),
+                file("/r/empty", b"", 400 * DAY, None),
+                file("/r/c.png", &[0; 2000], 10 * 365 * DAY, None),
+                ContentType::ContentDir(Dir {
+                    path: PathBuf::from("/r/sub"),
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        };
+        let stats = stats(&root, now);
+        assert_eq!(4, stats.files);
+        assert_eq!(2, stats.dirs);
+        assert_eq!(2008, stats.bytes);
+        assert_eq!(1, stats.empty_files);
+        assert_eq!(1, stats.empty_dirs);
+        assert_eq!(
+            vec![
+                Group {
+                    key: String::from("png"),
+                    count: 1,
+                    bytes: 2000
+                },
+                Group {
+                    key: String::from("txt"),
+                    count: 2,
+                    bytes: 8
+                },
+                Group {
+                    key: String::new(),
+                    count: 1,
+                    bytes: 0
+                },
+            ],
+            stats.by_extension
+        );
+        let mimes: Vec<&str> = stats.by_mime.iter().map(|g| g.key.as_str()).collect();
+        assert_eq!(vec!["image/png", "text/plain", mime::EMPTY], mimes);
+        let ages: Vec<usize> = stats.ages.iter().map(|b| b.count).collect();
+        assert_eq!(vec![1, 1, 0, 0, 1, 1], ages);
+        let sizes: Vec<usize> = stats.sizes.iter().map(|b| b.count).collect();
+        assert_eq!(vec![1, 2, 1, 0, 0, 0, 0, 0, 0], sizes);
+        assert!((stats.duplication_ratio() - 4.0 / 2008.0).abs() < 1e-9);
+    }
+}
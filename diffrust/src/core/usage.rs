@@ -0,0 +1,216 @@
+//! Disk usage (`du` like) aggregation over an indexed tree.
+//!
+//! Everything is computed from the index, hard links (files sharing a
+//! `FileId`) are counted once.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::core::model::{ContentType, Dir, File, FileId};
+
+/// Aggregated usage of a directory and its subdirectories
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    /// Apparent size of the files, hard links counted once
+    pub size: u64,
+    /// Number of files, hard links counted once
+    pub files: usize,
+    /// Size of the distinct contents (i.e. if duplicates were removed)
+    pub unique_size: u64,
+}
+
+/// Usage of a directory of the tree
+#[derive(Debug, PartialEq)]
+pub struct DirUsage<'a> {
+    pub dir: &'a Dir,
+    pub usage: Usage,
+}
+
+/// Key identifying a file for hard link detection, files without a
+/// `FileId` are told apart by their path
+#[derive(Hash, PartialEq, Eq)]
+enum FileKey<'a> {
+    Id(FileId),
+    Path(&'a std::path::Path),
+}
+
+fn key(file: &File) -> FileKey<'_> {
+    match file.id {
+        Some(id) => FileKey::Id(id),
+        None => FileKey::Path(&file.path),
+    }
+}
+
+/// Sets of files and contents seen in a subtree
+#[derive(Default)]
+struct Seen<'a> {
+    files: HashSet<FileKey<'a>>,
+    contents: HashSet<md5::Digest>,
+    usage: Usage,
+}
+
+impl<'a> Seen<'a> {
+    fn add(&mut self, file: &'a File) {
+        if self.files.insert(key(file)) {
+            self.usage.size += file.size;
+            self.usage.files += 1;
+            if self.contents.insert(file.md5) {
+                self.usage.unique_size += file.size;
+            }
+        }
+    }
+
+    /// Merges other into self, iterating over the smaller sets
+    fn merge(&mut self, mut other: Seen<'a>, files: &HashMap<FileKey<'a>, &'a File>) {
+        if other.files.len() > self.files.len() {
+            std::mem::swap(self, &mut other);
+        }
+        for k in other.files {
+            self.add(files[&k]);
+        }
+    }
+}
+
+/// Computes the usage of every directory of the tree, parents before
+/// their subdirectories
+pub fn dir_usage(dir: &Dir) -> Vec<DirUsage<'_>> {
+    let mut files = HashMap::new();
+    for file in dir.all_files() {
+        files.entry(key(file)).or_insert(file);
+    }
+    let mut result = vec![];
+    aggregate(dir, &files, &mut result);
+    result
+}
+
+fn aggregate<'a>(
+    dir: &'a Dir,
+    files: &HashMap<FileKey<'a>, &'a File>,
+    result: &mut Vec<DirUsage<'a>>,
+) -> Seen<'a> {
+    let index = result.len();
+    result.push(DirUsage {
+        dir,
+        usage: Usage::default(),
+    });
+    let mut seen = Seen::default();
+    for item in dir.content.iter() {
+        match item {
+            ContentType::ContentFile(f) => seen.add(f),
+            ContentType::ContentDir(d) => {
+                let child = aggregate(d, files, result);
+                seen.merge(child, files);
+            }
+            ContentType::ContentLink(_) => (),
+        }
+    }
+    result[index].usage = seen.usage;
+    seen
+}
+
+/// The n directories with the largest size, largest first
+pub fn largest_dirs<'a>(usage: &'a [DirUsage<'a>], n: usize) -> Vec<&'a DirUsage<'a>> {
+    let mut dirs: Vec<&DirUsage> = usage.iter().collect();
+    dirs.sort_by(|a, b| b.usage.size.cmp(&a.usage.size).then(a.dir.cmp(b.dir)));
+    dirs.truncate(n);
+    dirs
+}
+
+/// The n largest files, largest first, listing each hard linked file once
+pub fn largest_files(dir: &Dir, n: usize) -> Vec<&File> {
+    let mut seen = HashSet::new();
+    let mut files: Vec<&File> = dir.all_files();
+    files.sort_by(|a, b| b.size.cmp(&a.size).then(a.cmp(b)));
+    files.retain(|f| seen.insert(key(f)));
+    files.truncate(n);
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::core::model::Collection;
+
+    fn file(path: &str, content: &[u8], ino: u64) -> ContentType {
+        ContentType::ContentFile(File {
+            path: PathBuf::from(path),
+            md5: md5::compute(content),
+            size: content.len() as u64,
+            id: Some(FileId { dev: 1, ino }),
+            ..Default::default()
+        })
+    }
+
+    fn tree() -> Dir {
+        Dir {
+            path: PathBuf::from("/r"),
+            content: vec![
+                file("/r/a", b"aaaa", 1),
+                // hard link of /r/a
+                file("/r/a_link", b"aaaa", 1),
+                ContentType::ContentDir(Dir {
+                    path: PathBuf::from("/r/sub"),
+                    content: vec![
+                        file("/r/sub/copy", b"aaaa", 2),
+                        file("/r/sub/b", b"bb", 3),
+                        file("/r/sub/a_link", b"aaaa", 1),
+                    ],
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn usage_with_hard_links() {
+        let root = tree();
+        let usage = dir_usage(&root);
+        assert_eq!(2, usage.len());
+        assert_eq!(PathBuf::from("/r"), usage[0].dir.path);
+        // a (once), copy and b
+        assert_eq!(
+            Usage {
+                size: 10,
+                files: 3,
+                unique_size: 6
+            },
+            usage[0].usage
+        );
+        assert_eq!(
+            Usage {
+                size: 10,
+                files: 3,
+                unique_size: 6
+            },
+            usage[1].usage
+        );
+    }
+
+    #[test]
+    fn top_files_and_dirs() {
+        let root = tree();
+        let files = largest_files(&root, 10);
+        let paths: Vec<&str> = files.iter().map(|f| f.path.to_str().unwrap()).collect();
+        assert_eq!(vec!["/r/a", "/r/sub/copy", "/r/sub/b"], paths);
+        let usage = dir_usage(&root);
+        assert_eq!(1, largest_dirs(&usage, 1).len());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scanned_hard_links() {
+        let tempdir = tempdir().unwrap();
+        fs::write(tempdir.path().join("a"), b"abc").unwrap();
+        fs::hard_link(tempdir.path().join("a"), tempdir.path().join("b")).unwrap();
+        let mut collection = Collection::from(tempdir.path());
+        collection.scan().unwrap();
+        let usage = dir_usage(collection.root_dir.as_ref().unwrap());
+        assert_eq!(1, usage[0].usage.files);
+        assert_eq!(3, usage[0].usage.size);
+    }
+}
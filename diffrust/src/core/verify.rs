@@ -0,0 +1,111 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::core::model::{compute_md5, Dir, File};
+
+/// Outcome of checking an indexed file against the file system
+#[derive(Debug, PartialEq)]
+pub enum VerifyStatus {
+    /// Size, modification time and digest all match the index
+    Ok,
+    /// Modification time (or size) changed since the file was indexed
+    Modified,
+    /// Size and modification time match but the content digest does not
+    Corrupted,
+    /// The file is no longer present
+    Missing,
+    /// The file could not be read
+    Unreadable(io::ErrorKind),
+}
+
+/// Re-hashes every file indexed under dir and compares it with the
+/// stored digest. Results are returned in path order.
+pub fn verify_dir(dir: &Dir) -> Vec<(&File, VerifyStatus)> {
+    let mut files = dir.all_files();
+    files.sort_unstable();
+    files.into_iter().map(|f| (f, verify_file(f))).collect()
+}
+
+/// Checks a single indexed file against its current state on disk.
+///
+/// Files whose size and modification time are unchanged are re-hashed,
+/// a differing digest then means the content changed without the file
+/// system noticing (i.e. bitrot).
+pub fn verify_file(file: &File) -> VerifyStatus {
+    match check(&file.path, file) {
+        Ok(status) => status,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => VerifyStatus::Missing,
+        Err(e) => VerifyStatus::Unreadable(e.kind()),
+    }
+}
+
+fn check(path: &Path, file: &File) -> Result<VerifyStatus, io::Error> {
+    let metadata = fs::metadata(path)?;
+    if metadata.modified()? != file.modified || metadata.len() != file.size {
+        return Ok(VerifyStatus::Modified);
+    }
+    if compute_md5(path)? != file.md5 {
+        return Ok(VerifyStatus::Corrupted);
+    }
+    Ok(VerifyStatus::Ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime};
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn touch(path: &Path, time: SystemTime) {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn verify_unchanged() {
+        let tempdir = tempdir().unwrap();
+        let path = tempdir.path().join("a.txt");
+        fs::write(&path, b"abc").unwrap();
+        let file = File::from_path(&path).unwrap();
+        assert_eq!(VerifyStatus::Ok, verify_file(&file));
+    }
+
+    #[test]
+    fn verify_corrupted() {
+        let tempdir = tempdir().unwrap();
+        let path = tempdir.path().join("a.txt");
+        fs::write(&path, b"abc").unwrap();
+        let file = File::from_path(&path).unwrap();
+        // same size and mtime, different content
+        fs::write(&path, b"abd").unwrap();
+        touch(&path, file.modified);
+        assert_eq!(VerifyStatus::Corrupted, verify_file(&file));
+    }
+
+    #[test]
+    fn verify_modified() {
+        let tempdir = tempdir().unwrap();
+        let path = tempdir.path().join("a.txt");
+        fs::write(&path, b"abc").unwrap();
+        let file = File::from_path(&path).unwrap();
+        touch(&path, file.modified + Duration::from_secs(60));
+        assert_eq!(VerifyStatus::Modified, verify_file(&file));
+    }
+
+    #[test]
+    fn verify_missing() {
+        let file = File {
+            path: PathBuf::from("/this/file/does/not/exist"),
+            ..Default::default()
+        };
+        assert_eq!(VerifyStatus::Missing, verify_file(&file));
+    }
+}
@@ -0,0 +1,576 @@
+//! Keeping an indexed tree current while the file system changes.
+//!
+//! [`watch`] subscribes to the file system notifications (inotify on
+//! Linux) of the tree of a `Dir` through a [`Watch`], collects bursts
+//! of them with a [`Debouncer`] and applies them to the tree with [`apply`], hashing
+//! only the files that were created or modified. Moved files keep their
+//! hash.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use notify::event::{EventKind, ModifyKind, RenameMode};
+use notify::{RecursiveMode, Watcher};
+
+use crate::core::filter::{Filter, Symlinks};
+use crate::core::model::{is_collection_file, Attributes, ContentType, Dir, File, Link, ScanError};
+
+/// Maximum time between two checks of the stop flag
+const POLL: Duration = Duration::from_millis(100);
+/// A burst longer than this many debounce delays is applied anyway
+const MAX_BURST: u32 = 10;
+
+/// A change of a watched tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEvent {
+    Created(PathBuf),
+    Modified(PathBuf),
+    Deleted(PathBuf),
+    Moved {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// Notifications were lost (e.g. the kernel queue overflowed), the
+    /// whole tree at path is scanned again
+    Rescan(PathBuf),
+}
+
+impl WatchEvent {
+    /// Short name of the event kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WatchEvent::Created(_) => "created",
+            WatchEvent::Modified(_) => "modified",
+            WatchEvent::Deleted(_) => "deleted",
+            WatchEvent::Moved { .. } => "moved",
+            WatchEvent::Rescan(_) => "rescan",
+        }
+    }
+
+    /// Path of the affected entry, after the event
+    pub fn path(&self) -> &Path {
+        match self {
+            WatchEvent::Created(p)
+            | WatchEvent::Modified(p)
+            | WatchEvent::Deleted(p)
+            | WatchEvent::Rescan(p) => p,
+            WatchEvent::Moved { to, .. } => to,
+        }
+    }
+}
+
+/// Collects the events of a burst, reducing them to their net effect.
+///
+/// Repeated events on a path are merged (e.g. created then modified is
+/// created, created then deleted is nothing), events within a directory
+/// created in the same burst are dropped since indexing the directory
+/// covers them, and the deletion and creation notified for both ends of
+/// a rename are replaced by a single move.
+#[derive(Debug, Default)]
+pub struct Debouncer {
+    /// Events in order, None once merged away
+    pending: Vec<Option<WatchEvent>>,
+    /// Position in pending of the event on a path, for the events
+    /// following the last move or rescan, which change what a path
+    /// refers to (events are only merged with these)
+    by_path: HashMap<PathBuf, usize>,
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Debouncer {
+    /// Adds an event received at time now
+    pub fn push(&mut self, event: WatchEvent, now: Instant) {
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+        match event {
+            WatchEvent::Rescan(_) => {
+                self.pending.clear();
+                self.by_path.clear();
+                self.pending.push(Some(event));
+            }
+            WatchEvent::Moved { ref from, ref to } => {
+                self.discard(&WatchEvent::Deleted(from.clone()));
+                self.discard(&WatchEvent::Created(to.clone()));
+                self.by_path.clear();
+                self.pending.push(Some(event));
+            }
+            event => {
+                let path = event.path();
+                let covered = path
+                    .ancestors()
+                    .skip(1)
+                    .any(|dir| matches!(self.get(dir), Some(WatchEvent::Created(_))));
+                if covered && !matches!(event, WatchEvent::Deleted(_)) {
+                    return;
+                }
+                match self.by_path.get(path) {
+                    None => {
+                        self.by_path.insert(path.to_path_buf(), self.pending.len());
+                        self.pending.push(Some(event));
+                    }
+                    Some(&i) => {
+                        let old = self.pending[i].take().expect("indexed event");
+                        match merge(&old, event) {
+                            Some(merged) => self.pending[i] = Some(merged),
+                            None => {
+                                self.by_path.remove(old.path());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// The pending event on path since the last move or rescan
+    fn get(&self, path: &Path) -> Option<&WatchEvent> {
+        self.pending[*self.by_path.get(path)?].as_ref()
+    }
+
+    /// Drops event if pending since the last move or rescan
+    fn discard(&mut self, event: &WatchEvent) {
+        if self.get(event.path()) == Some(event) {
+            let i = self.by_path.remove(event.path()).expect("indexed event");
+            self.pending[i] = None;
+        }
+    }
+
+    /// Whether the pending events are to be applied: nothing happened
+    /// for delay, or the burst is going on for too long
+    pub fn ready(&self, now: Instant, delay: Duration) -> bool {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) => {
+                now.duration_since(last) >= delay || now.duration_since(first) >= delay * MAX_BURST
+            }
+            _ => false,
+        }
+    }
+
+    /// Time left until the pending events are ready, None without events
+    fn remaining(&self, now: Instant, delay: Duration) -> Option<Duration> {
+        let last = self.last?;
+        Some(delay.saturating_sub(now.duration_since(last)))
+    }
+
+    /// Removes and returns the pending events, in order
+    pub fn take(&mut self) -> Vec<WatchEvent> {
+        self.first = None;
+        self.last = None;
+        self.by_path.clear();
+        mem::take(&mut self.pending).into_iter().flatten().collect()
+    }
+}
+
+/// Net effect of new following old on the same path, None if nothing
+/// changed in the end
+fn merge(old: &WatchEvent, new: WatchEvent) -> Option<WatchEvent> {
+    match (old, new) {
+        (WatchEvent::Created(_), WatchEvent::Modified(_)) => Some(old.clone()),
+        (WatchEvent::Created(_), WatchEvent::Deleted(_)) => None,
+        (WatchEvent::Deleted(_), WatchEvent::Created(path)) => Some(WatchEvent::Modified(path)),
+        (_, new) => Some(new),
+    }
+}
+
+/// Applies event to the tree of dir, indexing what it affects unless
+/// rejected by filter. Entries that cannot be indexed are left out and
+/// returned.
+pub fn apply(dir: &mut Dir, event: &WatchEvent, filter: &Filter) -> Vec<ScanError> {
+    let mut skipped = vec![];
+    match event {
+        WatchEvent::Created(path) | WatchEvent::Modified(path) if !filter.includes(path) => (),
+        WatchEvent::Moved { from, to } if !filter.includes(to) => {
+            remove(dir, from);
+        }
+        WatchEvent::Created(path) => upsert(dir, path, &mut skipped, filter),
+        WatchEvent::Modified(path) => match find_dir(dir, path) {
+            // content changes of a directory are notified for its entries
+            Some(d) => refresh(d),
+            None => upsert(dir, path, &mut skipped, filter),
+        },
+        WatchEvent::Deleted(path) => {
+            remove(dir, path);
+        }
+        WatchEvent::Moved { from, to } => match remove(dir, from) {
+            Some(mut item) => {
+                repath(&mut item, from, to);
+                if insert(dir, item).is_some() {
+                    upsert(dir, to, &mut skipped, filter);
+                }
+            }
+            None => upsert(dir, to, &mut skipped, filter),
+        },
+        WatchEvent::Rescan(_) => {
+            *dir = Dir {
+                path: dir.path.clone(),
+                ..Default::default()
+            };
+            match dir.scan_filtered(&mut (), filter) {
+                Ok(errors) => skipped.extend(errors),
+                Err(error) => skipped.push(ScanError::new(&dir.path, error)),
+            }
+        }
+    }
+    skipped
+}
+
+/// Returns the entry at path within dir, None if not indexed
+pub fn entry<'a>(dir: &'a Dir, path: &Path) -> Option<&'a ContentType> {
+    for item in dir.content.iter() {
+        match item {
+            _ if item.path() == path => return Some(item),
+            ContentType::ContentDir(d) if path.starts_with(&d.path) => return entry(d, path),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Subscription to the notifications of a tree, delivering them in
+/// debounced bursts. Notifications are queued from creation, so that
+/// nothing is lost while the tree is being scanned.
+pub struct Watch {
+    // kept alive to keep receiving notifications
+    _watcher: notify::RecommendedWatcher,
+    rx: mpsc::Receiver<notify::Result<notify::Event>>,
+    root: PathBuf,
+    /// Absolute forms of root, as found in notifications
+    roots: [PathBuf; 2],
+    debouncer: Debouncer,
+}
+
+impl Watch {
+    /// Starts watching the tree at root, recursively
+    pub fn new(root: &Path) -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx).map_err(io::Error::other)?;
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .map_err(io::Error::other)?;
+        Ok(Watch {
+            _watcher: watcher,
+            rx,
+            root: root.to_path_buf(),
+            roots: [env::current_dir()?.join(root), fs::canonicalize(root)?],
+            debouncer: Debouncer::default(),
+        })
+    }
+
+    /// Waits for the next burst of events, None once stop is set
+    pub fn next_burst(
+        &mut self,
+        delay: Duration,
+        stop: &AtomicBool,
+    ) -> io::Result<Option<Vec<WatchEvent>>> {
+        while !stop.load(Ordering::Relaxed) {
+            let timeout = self
+                .debouncer
+                .remaining(Instant::now(), delay)
+                .map_or(POLL, |t| t.min(POLL));
+            match self.rx.recv_timeout(timeout) {
+                Ok(Ok(event)) => {
+                    for event in events(event, &self.root, &self.roots) {
+                        self.debouncer.push(event, Instant::now());
+                    }
+                }
+                Ok(Err(error)) => return Err(io::Error::other(error)),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if self.debouncer.ready(Instant::now(), delay) {
+                return Ok(Some(self.debouncer.take()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Converts a notification into events on the paths of the tree at
+/// root. Notifications that do not change the index (e.g. accesses)
+/// and the collection files are ignored.
+fn events(event: notify::Event, root: &Path, roots: &[PathBuf]) -> Vec<WatchEvent> {
+    if event.need_rescan() {
+        return vec![WatchEvent::Rescan(root.to_path_buf())];
+    }
+    let paths: Vec<Option<PathBuf>> = event
+        .paths
+        .iter()
+        .map(|p| {
+            let relative = roots.iter().find_map(|r| p.strip_prefix(r).ok())?;
+            let path = root.join(relative);
+            (!is_collection_file(&path)).then_some(path)
+        })
+        .collect();
+    let each =
+        |event: fn(PathBuf) -> WatchEvent| paths.iter().flatten().cloned().map(event).collect();
+    match event.kind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => match paths.as_slice() {
+            [Some(from), Some(to)] => vec![WatchEvent::Moved {
+                from: from.clone(),
+                to: to.clone(),
+            }],
+            [Some(from), None] => vec![WatchEvent::Deleted(from.clone())],
+            [None, Some(to)] => vec![WatchEvent::Created(to.clone())],
+            _ => vec![],
+        },
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => each(WatchEvent::Deleted),
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => each(WatchEvent::Created),
+        EventKind::Create(_) => each(WatchEvent::Created),
+        EventKind::Modify(_) => each(WatchEvent::Modified),
+        EventKind::Remove(_) => each(WatchEvent::Deleted),
+        _ => vec![],
+    }
+}
+
+/// Returns the indexed directory at path within dir
+fn find_dir<'a>(dir: &'a mut Dir, path: &Path) -> Option<&'a mut Dir> {
+    if dir.path == path {
+        return Some(dir);
+    }
+    let child = dir.content.iter_mut().find_map(|c| match c {
+        ContentType::ContentDir(d) if path.starts_with(&d.path) => Some(d),
+        _ => None,
+    })?;
+    find_dir(child, path)
+}
+
+/// Indexes the entry at path, None for unsupported types (e.g. sockets)
+fn index(
+    path: &Path,
+    skipped: &mut Vec<ScanError>,
+    filter: &Filter,
+) -> io::Result<Option<ContentType>> {
+    let metadata = fs::symlink_metadata(path)?;
+    let file_type = metadata.file_type();
+    // followed links are only indexed by (re)scans
+    let item = if file_type.is_symlink() {
+        if filter.symlinks == Symlinks::Skip {
+            return Ok(None);
+        }
+        ContentType::ContentLink(Link::from_path(path)?)
+    } else if file_type.is_file() {
+        if !filter.accepts_file(path, &metadata) {
+            return Ok(None);
+        }
+        ContentType::ContentFile(File::from_path(path)?)
+    } else if file_type.is_dir() {
+        let mut dir = Dir {
+            path: path.to_path_buf(),
+            ..Default::default()
+        };
+        skipped.extend(dir.scan_filtered(&mut (), filter)?);
+        ContentType::ContentDir(dir)
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(item))
+}
+
+/// Indexes the entry at path again, replacing it in the tree. Missing
+/// parent directories are indexed as a whole.
+fn upsert(dir: &mut Dir, path: &Path, skipped: &mut Vec<ScanError>, filter: &Filter) {
+    if path == dir.path {
+        refresh(dir);
+        return;
+    }
+    if !path.starts_with(&dir.path) {
+        return;
+    }
+    match index(path, skipped, filter) {
+        Ok(Some(item)) => {
+            if insert(dir, item).is_some() {
+                if let Some(parent) = path.parent() {
+                    upsert(dir, parent, skipped, filter);
+                }
+            }
+        }
+        // vanished before the event was applied, or not indexable
+        Ok(None) => {
+            remove(dir, path);
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            remove(dir, path);
+        }
+        Err(error) => skipped.push(ScanError::new(path, error)),
+    }
+}
+
+/// Adds item to its parent directory in the tree replacing the entry
+/// with the same path, gives item back if the parent is not indexed
+fn insert(dir: &mut Dir, item: ContentType) -> Option<ContentType> {
+    let Some(parent) = item.path().parent().and_then(|p| find_dir(dir, p)) else {
+        return Some(item);
+    };
+    match parent.content.iter().position(|c| c.path() == item.path()) {
+        Some(i) => parent.content[i] = item,
+        None => parent.content.push(item),
+    }
+    refresh(parent);
+    None
+}
+
+/// Removes the entry at path from the tree, returning it
+fn remove(dir: &mut Dir, path: &Path) -> Option<ContentType> {
+    let parent = find_dir(dir, path.parent()?)?;
+    let i = parent.content.iter().position(|c| c.path() == path)?;
+    let item = parent.content.remove(i);
+    refresh(parent);
+    Some(item)
+}
+
+/// Updates the attributes and modification time of dir, keeping the
+/// previous ones if it cannot be read
+fn refresh(dir: &mut Dir) {
+    if let Ok(metadata) = fs::metadata(&dir.path) {
+        dir.attributes = Attributes::from(&metadata);
+        dir.modified = metadata.modified().unwrap_or(dir.modified);
+    }
+}
+
+/// Moves item, and everything within it, from the path from to to
+fn repath(item: &mut ContentType, from: &Path, to: &Path) {
+    let moved = |path: &mut PathBuf| match path.strip_prefix(from) {
+        Ok(rest) if rest.as_os_str().is_empty() => *path = to.to_path_buf(),
+        Ok(rest) => *path = to.join(rest),
+        Err(_) => (),
+    };
+    match item {
+        ContentType::ContentDir(d) => {
+            moved(&mut d.path);
+            for c in d.content.iter_mut() {
+                repath(c, from, to);
+            }
+        }
+        ContentType::ContentFile(f) => moved(&mut f.path),
+        ContentType::ContentLink(l) => moved(&mut l.path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn files(dir: &Dir) -> Vec<(PathBuf, md5::Digest)> {
+        let mut files: Vec<_> = dir
+            .all_files()
+            .into_iter()
+            .map(|f| (f.path.strip_prefix(&dir.path).unwrap().to_path_buf(), f.md5))
+            .collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        files
+    }
+
+    #[test]
+    fn debounce_bursts() {
+        let now = Instant::now();
+        let delay = Duration::from_millis(100);
+        let path = |p: &str| PathBuf::from(p);
+        let mut debouncer = Debouncer::default();
+        assert!(!debouncer.ready(now, delay));
+
+        debouncer.push(WatchEvent::Created(path("/r/a")), now);
+        debouncer.push(WatchEvent::Modified(path("/r/a")), now);
+        debouncer.push(WatchEvent::Created(path("/r/tmp")), now);
+        debouncer.push(WatchEvent::Deleted(path("/r/tmp")), now);
+        debouncer.push(WatchEvent::Created(path("/r/d")), now);
+        debouncer.push(WatchEvent::Created(path("/r/d/x")), now);
+        // a rename is notified as both ends and then as a move
+        debouncer.push(WatchEvent::Deleted(path("/r/b")), now);
+        debouncer.push(WatchEvent::Created(path("/r/c")), now);
+        let moved = WatchEvent::Moved {
+            from: path("/r/b"),
+            to: path("/r/c"),
+        };
+        debouncer.push(moved.clone(), now);
+        debouncer.push(WatchEvent::Deleted(path("/r/e")), now);
+        debouncer.push(WatchEvent::Created(path("/r/e")), now);
+
+        assert!(!debouncer.ready(now + delay / 2, delay));
+        assert!(debouncer.ready(now + delay, delay));
+        assert_eq!(
+            vec![
+                WatchEvent::Created(path("/r/a")),
+                WatchEvent::Created(path("/r/d")),
+                moved,
+                WatchEvent::Modified(path("/r/e")),
+            ],
+            debouncer.take()
+        );
+        assert!(!debouncer.ready(now + delay, delay));
+    }
+
+    #[test]
+    fn apply_events() {
+        let tempdir = tempdir().unwrap();
+        let root = tempdir.path();
+        fs::create_dir(root.join("sub")).unwrap();
+        fs::write(root.join("sub").join("a"), b"a").unwrap();
+        let mut dir = Dir {
+            path: root.to_path_buf(),
+            ..Default::default()
+        };
+        dir.scan().unwrap();
+
+        fs::write(root.join("b"), b"b").unwrap();
+        fs::create_dir_all(root.join("new").join("deep")).unwrap();
+        fs::write(root.join("new").join("deep").join("c"), b"c").unwrap();
+        fs::write(root.join("sub").join("a"), b"changed").unwrap();
+        fs::rename(root.join("sub"), root.join("moved")).unwrap();
+        let events = [
+            WatchEvent::Created(root.join("b")),
+            WatchEvent::Created(root.join("new").join("deep").join("c")),
+            WatchEvent::Moved {
+                from: root.join("sub"),
+                to: root.join("moved"),
+            },
+        ];
+        for event in events.iter() {
+            assert!(apply(&mut dir, event, &Filter::default()).is_empty());
+        }
+        // moves keep the hash, the modification of a was not notified
+        assert_eq!(
+            vec![
+                (PathBuf::from("b"), md5::compute(b"b")),
+                (PathBuf::from("moved/a"), md5::compute(b"a")),
+                (PathBuf::from("new/deep/c"), md5::compute(b"c")),
+            ],
+            files(&dir)
+        );
+        assert!(entry(&dir, &root.join("new").join("deep")).is_some());
+
+        apply(
+            &mut dir,
+            &WatchEvent::Modified(root.join("moved").join("a")),
+            &Filter::default(),
+        );
+        fs::remove_file(root.join("b")).unwrap();
+        apply(
+            &mut dir,
+            &WatchEvent::Deleted(root.join("b")),
+            &Filter::default(),
+        );
+        // created and removed before the event was applied
+        apply(
+            &mut dir,
+            &WatchEvent::Created(root.join("gone")),
+            &Filter::default(),
+        );
+        assert_eq!(
+            vec![
+                (PathBuf::from("moved/a"), md5::compute(b"changed")),
+                (PathBuf::from("new/deep/c"), md5::compute(b"c")),
+            ],
+            files(&dir)
+        );
+    }
+}
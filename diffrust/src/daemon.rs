@@ -0,0 +1,891 @@
+//! Long running daemon keeping collections in memory.
+//!
+//! `diffrust <PATH> daemon` loads the collection at PATH, and any other
+//! collection it is asked about, keeps every loaded tree current with a
+//! [`Watch`] and answers queries on a Unix domain socket, so that
+//! commands do not scan again. `show`, `find`, `dups` and `diff` use a
+//! running daemon automatically (see [`Client`]).
+//!
+//! # Protocol
+//!
+//! JSON-RPC 2.0, one request or response object per line. Paths are
+//! absolute, encoded as in the collection database (a string, or
+//! `{"hex": "<bytes>"}` when not valid UTF-8). Methods are:
+//!
+//! ```text
+//! load      {"root", "db"?}       -> {"files"}
+//! show      {"root"}              -> {"tree": dir}
+//! find      {"root", "pattern", "query"?, "mode"?, "on_path"?, "metric"?}
+//!                                 -> {"matches": [content, ...]}
+//! dups      {"root"}              -> {"groups": [[file, ...], ...]}
+//! diff      {"root", "other", "metric"?}
+//!                                 -> {"changes": [{"status", "old", "new"}, ...]}
+//! status    {}                    -> {"collections": [{"root", "files", "watched", "loading"?}, ...]}
+//! shutdown  {}                    -> {}
+//! ```
+//!
+//! where `dir`, `content` and `file` are serialized as in the database,
+//! `mode` is `substring` (default), `regex` or `fuzzy`, `metric` the
+//! name of a `core::algorithm::Metric` and `status` is one of the `diff`
+//! statuses. Collections are loaded (scanned, hashing only the files
+//! changed since their database was written) by the first request on
+//! them, `load` only loads, from `db` when the database is not the one
+//! in the root; `loading` is true while a collection is
+//! being loaded. The command line client only sends requests on the
+//! collections listed by `status`. Errors carry the JSON-RPC
+//! codes (-32700 parse error, -32600 invalid request, -32601 unknown
+//! method, -32602 invalid params) or -32000 when a collection cannot be
+//! loaded.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::core::algorithm::Metric;
+use crate::core::diff::{self, Change};
+use crate::core::dups;
+use crate::core::model::{Collection, ContentType, Dir, File};
+use crate::core::name;
+use crate::core::ngram::NameIndex;
+use crate::core::query::Query;
+use crate::core::search::{self, FindMode, Matcher};
+use crate::core::watch::{self, Watch};
+use crate::error::{self, Error};
+
+/// Name of the socket in the runtime directory
+const SOCKET_NAME: &str = "diffrust.sock";
+/// Debounce delay of the watched collections
+const DEBOUNCE: Duration = Duration::from_millis(200);
+/// Maximum time between two checks of the stop flag
+const POLL: Duration = Duration::from_millis(100);
+/// Maximum time a client waits for a response
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// Path of the socket used when none is given: in `XDG_RUNTIME_DIR`
+/// when set, otherwise in a directory of the temporary directory only
+/// the user can access
+pub fn default_socket() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(SOCKET_NAME),
+        _ => {
+            let user = env::var("USER").unwrap_or_default();
+            env::temp_dir()
+                .join(format!("diffrust-{user}"))
+                .join(SOCKET_NAME)
+        }
+    }
+}
+
+/// The socket given, or the default one after checking that its
+/// directory (created if missing) belongs to the user and is not
+/// accessible to others: anybody could otherwise serve forged results
+/// or read the requests
+pub fn socket(given: Option<&Path>) -> io::Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    if let Some(socket) = given {
+        return Ok(socket.to_path_buf());
+    }
+    let socket = default_socket();
+    let dir = socket.parent().unwrap_or(Path::new("/"));
+    match fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => (),
+    }
+    // not following links, the link itself would be checked
+    let metadata = fs::symlink_metadata(dir)?;
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let user = unsafe { libc::geteuid() };
+    if !metadata.is_dir() || metadata.uid() != user || metadata.mode() & 0o077 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is not a directory private to the user",
+                name::escape_path(dir)
+            ),
+        ));
+    }
+    Ok(socket)
+}
+
+#[derive(Serialize, Deserialize)]
+struct RootParams {
+    #[serde(with = "name::serde_path")]
+    root: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LoadParams {
+    #[serde(with = "name::serde_path")]
+    root: PathBuf,
+    /// Database of the collection, None for root/.diffrust.db
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "name::serde_path::option"
+    )]
+    db: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FindParams {
+    #[serde(with = "name::serde_path")]
+    root: PathBuf,
+    pattern: String,
+    /// A query as in `core::query`, the matches are then at any depth
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    query: Option<String>,
+    /// How the pattern matches, see `core::search`
+    #[serde(default, skip_serializing_if = "is_default")]
+    mode: FindMode,
+    /// The pattern is matched on the relative path
+    #[serde(default, skip_serializing_if = "is_default")]
+    on_path: bool,
+    /// Name of the similarity metric of fuzzy matches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metric: Option<String>,
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// A collection held by the daemon, as listed by `status`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionStatus {
+    #[serde(with = "name::serde_path")]
+    pub root: PathBuf,
+    pub files: usize,
+    pub watched: bool,
+    /// Being loaded, files and watched are not known yet
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub loading: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DiffParams {
+    #[serde(with = "name::serde_path")]
+    root: PathBuf,
+    #[serde(with = "name::serde_path")]
+    other: PathBuf,
+    /// Name of the metric pairing renamed files, dice by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metric: Option<String>,
+}
+
+/// The metric named in params, the default one when none is
+fn metric(name: Option<&str>) -> Result<Option<Metric>, RpcError> {
+    name.map(Metric::from_str)
+        .transpose()
+        .map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+/// A `core::diff::Change` as exchanged on the socket
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangeRecord {
+    pub status: String,
+    pub old: Option<File>,
+    pub new: Option<File>,
+}
+
+impl ChangeRecord {
+    fn from(change: &Change) -> Self {
+        let (old, new) = match change {
+            Change::Added(n) => (None, Some(n)),
+            Change::Removed(o) => (Some(o), None),
+            Change::Modified { old, new } | Change::Renamed { from: old, to: new } => {
+                (Some(old), Some(new))
+            }
+        };
+        ChangeRecord {
+            status: change.status().to_string(),
+            old: old.map(|f| (*f).clone()),
+            new: new.map(|f| (*f).clone()),
+        }
+    }
+
+    /// Returns the change, None if the record is inconsistent
+    pub fn change(&self) -> Option<Change<'_>> {
+        match (self.status.as_str(), &self.old, &self.new) {
+            ("added", None, Some(new)) => Some(Change::Added(new)),
+            ("removed", Some(old), None) => Some(Change::Removed(old)),
+            ("modified", Some(old), Some(new)) => Some(Change::Modified { old, new }),
+            ("renamed", Some(from), Some(to)) => Some(Change::Renamed { from, to }),
+            _ => None,
+        }
+    }
+}
+
+/// A JSON-RPC error
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// A collection held by the daemon
+struct Loaded {
+    collection: Mutex<Collection>,
+    /// Index of the names of the tree, None when it changed since it was
+    /// built (locked after the collection)
+    index: Mutex<Option<NameIndex>>,
+    /// Whether a watch keeps the tree current, otherwise it is scanned
+    /// again (hashing changed files only) for every request
+    watched: AtomicBool,
+}
+
+/// A collection of the daemon, None until loaded. The slot is locked
+/// while loading, so that only the requests on that collection wait.
+type Slot = Arc<Mutex<Option<Arc<Loaded>>>>;
+
+/// The state of a daemon, shared by its connections
+pub struct Daemon {
+    collections: Mutex<HashMap<PathBuf, Slot>>,
+    stop: Arc<AtomicBool>,
+    log: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Daemon {
+    /// Creates a daemon running until stop is set, reporting what it
+    /// does to log
+    pub fn new(stop: Arc<AtomicBool>, log: Box<dyn Write + Send>) -> Arc<Self> {
+        Arc::new(Daemon {
+            collections: Mutex::new(HashMap::new()),
+            stop,
+            log: Mutex::new(log),
+        })
+    }
+
+    fn log(&self, message: &str) {
+        // logging is best effort, it must not stop the daemon
+        let _ = writeln!(lock(&self.log), "{message}");
+    }
+
+    /// Loads the collection at root with its database at db (None for
+    /// the one in root), see `collection`
+    pub fn load(self: &Arc<Self>, root: &Path, db: Option<&Path>) -> error::Result<()> {
+        self.collection(root, db)
+            .map(|_| ())
+            .map_err(|e| Error::Daemon(e.message))
+    }
+
+    /// Returns the collection at root, loading and watching it on first
+    /// use (from the database at db, None for the one in root).
+    /// Unwatched collections are scanned again.
+    fn collection(
+        self: &Arc<Self>,
+        root: &Path,
+        db: Option<&Path>,
+    ) -> Result<Arc<Loaded>, RpcError> {
+        let failed =
+            |e: io::Error| RpcError::new(SERVER_ERROR, format!("{}: {e}", name::escape_path(root)));
+        let root = fs::canonicalize(root).map_err(failed)?;
+        let slot = Arc::clone(lock(&self.collections).entry(root.clone()).or_default());
+        let mut held = lock(&slot);
+        if let Some(loaded) = held.clone() {
+            drop(held);
+            if !loaded.watched.load(Ordering::Relaxed) {
+                self.refresh(&mut lock(&loaded.collection))
+                    .map_err(failed)?;
+                *lock(&loaded.index) = None;
+            }
+            return Ok(loaded);
+        }
+        let loaded = self.open(&root, db)?;
+        *held = Some(Arc::clone(&loaded));
+        Ok(loaded)
+    }
+
+    /// Loads the collection at root and starts watching it
+    fn open(self: &Arc<Self>, root: &Path, db: Option<&Path>) -> Result<Arc<Loaded>, RpcError> {
+        let failed =
+            |e: io::Error| RpcError::new(SERVER_ERROR, format!("{}: {e}", name::escape_path(root)));
+        // subscribed first, changes during the scan are not missed
+        let watch = Watch::new(root);
+        let mut collection = crate::open_or_create_config(root)
+            .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))?;
+        collection.db = db.map(Path::to_path_buf);
+        // the database is only read, to hash changed files only
+        if let Err(e) = collection.load() {
+            self.log(&format!("{}: {e}, scanning", name::escape_path(root)));
+        }
+        // the saved index is only valid for the tree in the database
+        let index = match NameIndex::load(&collection.index_path(), &collection.db_path()) {
+            Ok(index) => index,
+            Err(e) => {
+                let path = collection.index_path();
+                self.log(&format!("{}: {e}, not used", name::escape_path(&path)));
+                None
+            }
+        };
+        self.refresh(&mut collection).map_err(failed)?;
+        // entries added or removed since the database was written
+        let index = index.filter(|index| {
+            let entries = collection
+                .root_dir
+                .as_ref()
+                .map_or(0, |d| search::entries(d).len());
+            index.len() == entries
+        });
+        self.log(&format!(
+            "Loaded {} ({} files)",
+            name::escape_path(root),
+            collection.root_dir.as_ref().map_or(0, Dir::file_count)
+        ));
+        let loaded = Arc::new(Loaded {
+            collection: Mutex::new(collection),
+            index: Mutex::new(index),
+            watched: AtomicBool::new(watch.is_ok()),
+        });
+        match watch {
+            Ok(watch) => self.spawn_watch(watch, Arc::clone(&loaded)),
+            Err(e) => self.log(&format!("{}: not watched, {e}", name::escape_path(root))),
+        }
+        Ok(loaded)
+    }
+
+    /// Scans the collection again, hashing only the changed files
+    fn refresh(&self, collection: &mut Collection) -> io::Result<()> {
+        collection.reuse_hashes();
+        for skipped in collection.resume_with(&mut ())? {
+            self.log(&format!("Skipped {skipped}"));
+        }
+        Ok(())
+    }
+
+    /// Applies the changes of the tree of loaded until the daemon stops
+    fn spawn_watch(self: &Arc<Self>, mut watch: Watch, loaded: Arc<Loaded>) {
+        let daemon = Arc::clone(self);
+        thread::spawn(move || loop {
+            match watch.next_burst(DEBOUNCE, &daemon.stop) {
+                Ok(Some(events)) => {
+                    let mut collection = lock(&loaded.collection);
+                    let filter = collection.filter.clone();
+                    let Some(dir) = collection.root_dir.as_mut() else {
+                        continue;
+                    };
+                    for event in events {
+                        for skipped in watch::apply(dir, &event, &filter) {
+                            daemon.log(&format!("Skipped {skipped}"));
+                        }
+                    }
+                    *lock(&loaded.index) = None;
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    let root = lock(&loaded.collection).root.clone();
+                    daemon.log(&format!("{}: not watched, {e}", name::escape_path(&root)));
+                    loaded.watched.store(false, Ordering::Relaxed);
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Answers a request line, None for notifications (requests without
+    /// id)
+    pub fn handle(self: &Arc<Self>, line: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return Some(error_response(Value::Null, PARSE_ERROR, &e.to_string())),
+        };
+        let id = request.get("id").cloned();
+        let Some(method) = request.get("method").and_then(Value::as_str) else {
+            let id = id.unwrap_or(Value::Null);
+            return Some(error_response(id, INVALID_REQUEST, "missing method"));
+        };
+        let params = request.get("params").cloned().unwrap_or(json!({}));
+        let result = self.call(method, params);
+        let id = id?;
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => error_response(id, e.code, &e.message),
+        })
+    }
+
+    fn call(self: &Arc<Self>, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "load" => {
+                let params: LoadParams = parse(params)?;
+                let loaded = self.collection(&params.root, params.db.as_deref())?;
+                let collection = lock(&loaded.collection);
+                Ok(json!({"files": collection.root_dir.as_ref().map_or(0, Dir::file_count)}))
+            }
+            "show" => {
+                let params: RootParams = parse(params)?;
+                let loaded = self.collection(&params.root, None)?;
+                let collection = lock(&loaded.collection);
+                Ok(json!({"tree": tree(&collection)?}))
+            }
+            "find" => {
+                let params: FindParams = parse(params)?;
+                let loaded = self.collection(&params.root, None)?;
+                let collection = lock(&loaded.collection);
+                let query = match &params.query {
+                    Some(query) => Some(
+                        Query::parse(query)
+                            .map_err(|e| RpcError::new(INVALID_PARAMS, format!("query: {e}")))?,
+                    ),
+                    None => None,
+                };
+                let matcher = Matcher::new(&params.pattern, params.mode, params.on_path)
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, format!("pattern: {e}")))?
+                    .with_metric(metric(params.metric.as_deref())?);
+                let dir = tree(&collection)?;
+                // similar names are scored among the candidates of the
+                // index, built again when the tree changed
+                let indexed = match query.is_none() && matcher.uses_index() {
+                    true => {
+                        let mut index = lock(&loaded.index);
+                        let built = index.get_or_insert_with(|| NameIndex::build(dir));
+                        matcher.find_indexed(dir, built).or_else(|| {
+                            let rebuilt = index.insert(NameIndex::build(dir));
+                            matcher.find_indexed(dir, rebuilt)
+                        })
+                    }
+                    false => None,
+                };
+                let found = match indexed {
+                    Some(found) => found,
+                    None => crate::find_matching(dir, &collection.root, &matcher, query.as_ref()),
+                };
+                let matches: Vec<&ContentType> = found.into_iter().map(|m| m.entry).collect();
+                Ok(json!({"matches": matches}))
+            }
+            "dups" => {
+                let params: RootParams = parse(params)?;
+                let loaded = self.collection(&params.root, None)?;
+                let collection = lock(&loaded.collection);
+                let groups: Vec<Vec<&File>> = dups::find_duplicates(tree(&collection)?)
+                    .into_iter()
+                    .map(|g| g.files)
+                    .collect();
+                Ok(json!({"groups": groups}))
+            }
+            "diff" => {
+                let params: DiffParams = parse(params)?;
+                let metric = metric(params.metric.as_deref())?.unwrap_or_default();
+                let loaded = self.collection(&params.root, None)?;
+                let other = self.collection(&params.other, None)?;
+                let changes: Vec<ChangeRecord> = if Arc::ptr_eq(&loaded, &other) {
+                    let collection = lock(&loaded.collection);
+                    let dir = tree(&collection)?;
+                    diff::diff_with(dir, dir, metric)
+                        .iter()
+                        .map(ChangeRecord::from)
+                        .collect()
+                } else {
+                    let (collection, other) = lock_both(&loaded, &other);
+                    let changes = diff::diff_with(tree(&collection)?, tree(&other)?, metric);
+                    changes.iter().map(ChangeRecord::from).collect()
+                };
+                Ok(json!({"changes": changes}))
+            }
+            "status" => {
+                let slots: Vec<(PathBuf, Slot)> = lock(&self.collections)
+                    .iter()
+                    .map(|(root, slot)| (root.clone(), Arc::clone(slot)))
+                    .collect();
+                let mut collections = vec![];
+                for (root, slot) in slots {
+                    let held = match slot.try_lock() {
+                        Ok(held) => held.clone(),
+                        Err(TryLockError::Poisoned(e)) => e.into_inner().clone(),
+                        Err(TryLockError::WouldBlock) => {
+                            collections.push(CollectionStatus {
+                                root,
+                                files: 0,
+                                watched: false,
+                                loading: true,
+                            });
+                            continue;
+                        }
+                    };
+                    // not loaded, the loading failed
+                    let Some(loaded) = held else {
+                        continue;
+                    };
+                    let collection = lock(&loaded.collection);
+                    collections.push(CollectionStatus {
+                        root,
+                        files: collection.root_dir.as_ref().map_or(0, Dir::file_count),
+                        watched: loaded.watched.load(Ordering::Relaxed),
+                        loading: false,
+                    });
+                }
+                Ok(json!({"collections": collections}))
+            }
+            "shutdown" => {
+                self.stop.store(true, Ordering::Relaxed);
+                Ok(json!({}))
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method '{method}'"),
+            )),
+        }
+    }
+
+    /// Serves requests on socket until the daemon is stopped
+    pub fn serve(self: &Arc<Self>, socket: &Path) -> io::Result<()> {
+        if socket.exists() {
+            if UnixStream::connect(socket).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "a daemon is already listening",
+                ));
+            }
+            // left over by a daemon that did not exit cleanly
+            fs::remove_file(socket)?;
+        }
+        let listener = UnixListener::bind(socket)?;
+        listener.set_nonblocking(true)?;
+        self.log(&format!("Listening on {}", name::escape_path(socket)));
+        while !self.stop.load(Ordering::Relaxed) {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false)?;
+                    let daemon = Arc::clone(self);
+                    thread::spawn(move || daemon.connection(stream));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL),
+                Err(e) => return Err(e),
+            }
+        }
+        let _ = fs::remove_file(socket);
+        Ok(())
+    }
+
+    /// Answers the requests of a client until it disconnects
+    fn connection(self: Arc<Self>, stream: UnixStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.handle(&line) {
+                writeln!(writer, "{response}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Connection to a running daemon
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connects to the daemon listening on socket, None if none is
+    pub fn connect(socket: &Path) -> Option<Self> {
+        let writer = UnixStream::connect(socket).ok()?;
+        // a busy daemon must not block commands forever
+        writer.set_read_timeout(Some(TIMEOUT)).ok()?;
+        writer.set_write_timeout(Some(TIMEOUT)).ok()?;
+        let reader = BufReader::new(writer.try_clone().ok()?);
+        Some(Client {
+            reader,
+            writer,
+            next_id: 1,
+        })
+    }
+
+    /// Calls method, returning its result
+    fn call(&mut self, method: &str, params: impl Serialize) -> error::Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let request = json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params});
+        writeln!(self.writer, "{request}")?;
+        let mut line = String::new();
+        let read = self
+            .reader
+            .read_line(&mut line)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                    Error::Daemon(format!("no response to {method} in {}s", TIMEOUT.as_secs()))
+                }
+                _ => Error::from(e),
+            })?;
+        if read == 0 {
+            return Err(Error::Daemon("connection closed".into()));
+        }
+        let mut response: Value =
+            serde_json::from_str(&line).map_err(|e| Error::Daemon(e.to_string()))?;
+        if let Some(error) = response.get("error") {
+            let message = error["message"].as_str().unwrap_or("unknown error");
+            return Err(Error::Daemon(message.to_string()));
+        }
+        Ok(response["result"].take())
+    }
+
+    /// Calls method, returning the field key of its result
+    fn call_for<T: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: impl Serialize,
+        key: &str,
+    ) -> error::Result<T> {
+        let mut result = self.call(method, params)?;
+        serde_json::from_value(result[key].take())
+            .map_err(|e| Error::Daemon(format!("invalid response, {e}")))
+    }
+
+    /// Loads the collection at root with its database at db (None for
+    /// the one in root), returns its number of files
+    pub fn load(&mut self, root: &Path, db: Option<&Path>) -> error::Result<usize> {
+        let params = LoadParams {
+            root: root.to_path_buf(),
+            db: db.map(Path::to_path_buf),
+        };
+        self.call_for("load", params, "files")
+    }
+
+    /// Returns the collections held by the daemon
+    pub fn status(&mut self) -> error::Result<Vec<CollectionStatus>> {
+        self.call_for("status", json!({}), "collections")
+    }
+
+    /// Whether the daemon holds the collection at root (an absolute
+    /// path), loaded
+    pub fn holds(&mut self, root: &Path) -> error::Result<bool> {
+        let status = self.status()?;
+        Ok(status.iter().any(|c| c.root == root && !c.loading))
+    }
+
+    /// Returns the tree of the collection at root
+    pub fn show(&mut self, root: &Path) -> error::Result<Dir> {
+        let params = RootParams {
+            root: root.to_path_buf(),
+        };
+        self.call_for("show", params, "tree")
+    }
+
+    /// Returns the entries of the collection at root found by matcher
+    /// and query
+    pub fn find(
+        &mut self,
+        root: &Path,
+        matcher: &Matcher,
+        query: Option<&str>,
+    ) -> error::Result<Vec<ContentType>> {
+        let params = FindParams {
+            root: root.to_path_buf(),
+            pattern: matcher.pattern().to_string(),
+            query: query.map(String::from),
+            mode: matcher.mode(),
+            metric: matcher.metric().map(|m| m.to_string()),
+            on_path: matcher.on_path(),
+        };
+        self.call_for("find", params, "matches")
+    }
+
+    /// Returns the groups of duplicate files of the collection at root
+    pub fn dups(&mut self, root: &Path) -> error::Result<Vec<Vec<File>>> {
+        let params = RootParams {
+            root: root.to_path_buf(),
+        };
+        self.call_for("dups", params, "groups")
+    }
+
+    /// Returns the changes from the collection at root to the one at
+    /// other, renames paired by metric
+    pub fn diff(
+        &mut self,
+        root: &Path,
+        other: &Path,
+        metric: Metric,
+    ) -> error::Result<Vec<ChangeRecord>> {
+        let params = DiffParams {
+            root: root.to_path_buf(),
+            other: other.to_path_buf(),
+            metric: Some(metric.to_string()).filter(|_| metric != Metric::default()),
+        };
+        self.call_for("diff", params, "changes")
+    }
+
+    /// Asks the daemon to stop
+    pub fn shutdown(&mut self) -> error::Result<()> {
+        self.call("shutdown", json!({})).map(|_| ())
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn tree(collection: &Collection) -> Result<&Dir, RpcError> {
+    collection
+        .root_dir
+        .as_ref()
+        .ok_or_else(|| RpcError::new(SERVER_ERROR, "collection not scanned"))
+}
+
+/// Locks two collections, always in the same order to avoid deadlocks
+fn lock_both<'a>(
+    a: &'a Loaded,
+    b: &'a Loaded,
+) -> (MutexGuard<'a, Collection>, MutexGuard<'a, Collection>) {
+    if (a as *const Loaded) < (b as *const Loaded) {
+        let a = lock(&a.collection);
+        (a, lock(&b.collection))
+    } else {
+        let b = lock(&b.collection);
+        (lock(&a.collection), b)
+    }
+}
+
+/// Locks mutex, ignoring poisoning: a panicking request leaves the tree
+/// consistent enough to keep serving
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn request(daemon: &Arc<Daemon>, method: &str, params: Value) -> Value {
+        let line = json!({"jsonrpc": "2.0", "id": 7, "method": method, "params": params});
+        daemon.handle(&line.to_string()).unwrap()
+    }
+
+    #[test]
+    fn answer_requests() {
+        let tempdir = tempdir().unwrap();
+        let root = fs::canonicalize(tempdir.path()).unwrap();
+        fs::write(root.join("a.txt"), b"same").unwrap();
+        fs::write(root.join("b.txt"), b"same").unwrap();
+        let daemon = Daemon::new(Arc::new(AtomicBool::new(false)), Box::new(io::sink()));
+
+        let response = request(&daemon, "dups", json!({"root": root}));
+        assert_eq!(7, response["id"]);
+        let groups: Vec<Vec<File>> =
+            serde_json::from_value(response["result"]["groups"].clone()).unwrap();
+        assert_eq!(1, groups.len());
+        assert_eq!(2, groups[0].len());
+
+        let response = request(&daemon, "find", json!({"root": root, "pattern": "a.t"}));
+        let matches: Vec<ContentType> =
+            serde_json::from_value(response["result"]["matches"].clone()).unwrap();
+        assert_eq!(
+            vec![root.join("a.txt")],
+            matches.iter().map(|m| m.path()).collect::<Vec<_>>()
+        );
+        let params = json!({"root": root, "pattern": "", "query": "name ~ b and size = 4"});
+        let response = request(&daemon, "find", params);
+        assert_eq!(1, response["result"]["matches"].as_array().unwrap().len());
+        let params = json!({"root": root, "pattern": "", "query": "size >"});
+        let response = request(&daemon, "find", params);
+        assert_eq!(INVALID_PARAMS, response["error"]["code"]);
+        let params = json!({"root": root, "pattern": "^[ab]\\.", "mode": "regex"});
+        let response = request(&daemon, "find", params);
+        assert_eq!(2, response["result"]["matches"].as_array().unwrap().len());
+        let params = json!({"root": root, "pattern": "(", "mode": "regex"});
+        let response = request(&daemon, "find", params);
+        assert_eq!(INVALID_PARAMS, response["error"]["code"]);
+        let params =
+            json!({"root": root, "pattern": "c.txt", "mode": "fuzzy", "metric": "damerau"});
+        let response = request(&daemon, "find", params);
+        assert_eq!(2, response["result"]["matches"].as_array().unwrap().len());
+        let params = json!({"root": root, "other": root, "metric": "cosine"});
+        let response = request(&daemon, "diff", params);
+        assert_eq!(INVALID_PARAMS, response["error"]["code"]);
+
+        let response = request(&daemon, "status", json!({}));
+        assert_eq!(2, response["result"]["collections"][0]["files"]);
+        assert!(response["result"]["collections"][0]["loading"].is_null());
+
+        // a registered collection with its database elsewhere
+        let (sub, db) = (root.join("sub"), root.join("sub.db"));
+        fs::create_dir(&sub).unwrap();
+        fs::write(sub.join("c.txt"), b"c").unwrap();
+        let response = request(&daemon, "load", json!({"root": sub, "db": db}));
+        assert_eq!(1, response["result"]["files"]);
+        let loaded = daemon.collection(&sub, None).unwrap();
+        assert_eq!(db, lock(&loaded.collection).db_path());
+
+        let response = request(&daemon, "nope", json!({}));
+        assert_eq!(METHOD_NOT_FOUND, response["error"]["code"]);
+        let response = request(&daemon, "show", json!({"pattern": "x"}));
+        assert_eq!(INVALID_PARAMS, response["error"]["code"]);
+        assert_eq!(PARSE_ERROR, daemon.handle("{").unwrap()["error"]["code"]);
+        // notifications get no response
+        assert!(daemon
+            .handle(r#"{"jsonrpc": "2.0", "method": "status"}"#)
+            .is_none());
+
+        request(&daemon, "shutdown", json!({}));
+        assert!(daemon.stop.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn serve_clients() {
+        let tempdir = tempdir().unwrap();
+        let root = fs::canonicalize(tempdir.path()).unwrap();
+        fs::create_dir(root.join("tree")).unwrap();
+        fs::write(root.join("tree").join("a"), b"a").unwrap();
+        let socket = root.join("test.sock");
+        let daemon = Daemon::new(Arc::new(AtomicBool::new(false)), Box::new(io::sink()));
+        let server = {
+            let daemon = Arc::clone(&daemon);
+            let socket = socket.clone();
+            thread::spawn(move || daemon.serve(&socket))
+        };
+        let mut client = loop {
+            match Client::connect(&socket) {
+                Some(client) => break client,
+                None => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        assert!(!client.holds(&root.join("tree")).unwrap());
+        assert_eq!(1, client.load(&root.join("tree"), None).unwrap());
+        assert!(client.holds(&root.join("tree")).unwrap());
+        let tree = client.show(&root.join("tree")).unwrap();
+        assert_eq!(1, tree.file_count());
+        let changes = client
+            .diff(&root.join("tree"), &root.join("tree"), Metric::Levenshtein)
+            .unwrap();
+        assert!(changes.is_empty());
+        assert!(client.show(&root.join("missing")).is_err());
+        client.shutdown().unwrap();
+        server.join().unwrap().unwrap();
+        assert!(!socket.exists());
+    }
+}
@@ -0,0 +1,164 @@
+//! Errors terminating a diffrust command.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::ConfigError;
+use crate::core::model::ScanError;
+use crate::core::mtree::SpecError;
+use crate::core::name;
+use crate::core::query::QueryError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing path failed
+    Path { path: PathBuf, source: io::Error },
+    /// An I/O error not tied to a path, e.g. printing the output
+    Io(io::Error),
+    /// The command requires a database created by scan
+    NoDatabase(PathBuf),
+    /// The mtree specification at path could not be parsed
+    Spec { path: PathBuf, source: SpecError },
+    /// Entries were skipped while scanning in strict mode
+    Incomplete(usize),
+    /// Verify found files whose content changed
+    Corrupted(usize),
+    /// Check found files not matching the manifest
+    ChecksumMismatch { failed: usize, total: usize },
+    /// Mtree check found paths not matching the specification
+    SpecMismatch(usize),
+    /// The scan was cancelled, with the number of files saved in the
+    /// checkpoint if one was written
+    Cancelled(Option<usize>),
+    /// The daemon could not answer a request
+    Daemon(String),
+    /// No collection is registered with this name (or at this path)
+    UnknownCollection(String),
+    /// The name cannot be used for a collection
+    InvalidName(String),
+    /// A collection configuration already exists at path
+    AlreadyInitialized(PathBuf),
+    /// The collection configuration at path is invalid
+    Config { path: PathBuf, source: ConfigError },
+    /// The query of find is invalid
+    Query { query: String, source: QueryError },
+    /// The regular expression of find is invalid
+    Regex(regex::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Path { path, source } => write!(f, "{}: {source}", name::escape_path(path)),
+            Error::Io(e) => write!(f, "{e}"),
+            Error::NoDatabase(db) => {
+                write!(
+                    f,
+                    "No database found at {}, run scan first",
+                    name::escape_path(db)
+                )
+            }
+            Error::Spec { path, source } => write!(f, "{}: {source}", name::escape_path(path)),
+            Error::Incomplete(n) => write!(f, "{n} path(s) could not be scanned"),
+            Error::Corrupted(n) => write!(f, "{n} corrupted file(s) found"),
+            Error::ChecksumMismatch { failed, total } => {
+                write!(f, "{failed} of {total} checksums did NOT match")
+            }
+            Error::SpecMismatch(n) => write!(f, "{n} path(s) differ from the specification"),
+            Error::Cancelled(None) => write!(f, "Scan cancelled"),
+            Error::Cancelled(Some(n)) => write!(
+                f,
+                "Scan cancelled, {n} hashed file(s) checkpointed, run scan --resume to continue"
+            ),
+            Error::Daemon(message) => write!(f, "Daemon: {message}"),
+            Error::UnknownCollection(name) => {
+                write!(f, "No registered collection {name}, see collection list")
+            }
+            Error::InvalidName(name) => write!(
+                f,
+                "Invalid collection name '{name}', use letters, digits, '.', '_' and '-'"
+            ),
+            Error::Query { query, source } => {
+                write!(f, "Invalid query, {source}")?;
+                for line in source.pointer(query).lines() {
+                    write!(f, "\n    {line}")?;
+                }
+                Ok(())
+            }
+            Error::Regex(e) => write!(f, "{e}"),
+            Error::Config { path, source } => write!(f, "{}: {source}", name::escape_path(path)),
+            Error::AlreadyInitialized(config) => write!(
+                f,
+                "{} already exists, edit it to change the collection",
+                name::escape_path(config)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Path { source, .. } => Some(source),
+            Error::Io(e) => Some(e),
+            Error::Spec { source, .. } => Some(source),
+            Error::Config { source, .. } => Some(source),
+            Error::Query { source, .. } => Some(source),
+            Error::Regex(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ScanError> for Error {
+    fn from(e: ScanError) -> Self {
+        Error::Path {
+            path: e.path,
+            source: e.error,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Attaches the path an I/O operation was working on to its error
+pub trait WithPath<T> {
+    fn with_path(self, path: &Path) -> Result<T>;
+}
+
+impl<T> WithPath<T> for io::Result<T> {
+    fn with_path(self, path: &Path) -> Result<T> {
+        self.map_err(|source| Error::Path {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let e = Err::<(), _>(io::Error::from(io::ErrorKind::NotFound))
+            .with_path(Path::new("/x/y"))
+            .unwrap_err();
+        assert!(e.to_string().starts_with("/x/y: "), "{e}");
+        assert_eq!(
+            "2 of 5 checksums did NOT match",
+            Error::ChecksumMismatch {
+                failed: 2,
+                total: 5
+            }
+            .to_string()
+        );
+    }
+}
@@ -0,0 +1,514 @@
+//! Machine readable (JSON and NDJSON) output of diffrust commands.
+//!
+//! # Schema (version 1)
+//!
+//! With `--format json` every command prints a single JSON object:
+//!
+//! ```text
+//! {
+//!   "schema_version": 1,
+//!   "command": "show" | "find" | "dups" | "diff" | "du" | "stats",
+//!   "root": "<absolute or given collection path>",
+//!   ...command specific fields
+//! }
+//! ```
+//!
+//! With `--format ndjson` the first line is the same header object with
+//! `"type": "header"` and no command specific fields, every following
+//! line is one record of the command output.
+//!
+//! An *entry* describes a `Dir`, `File` or link of the collection:
+//!
+//! ```text
+//! {
+//!   "type": "dir" | "file" | "link",
+//!   "path": "<path relative to the root, '.' for the root itself>",
+//!   "name": "<last path component>",
+//!   "mode": 420, "uid": 1000, "gid": 1000,
+//!   "modified": "<RFC 3339 UTC timestamp with nanoseconds>",
+//!   "size": 12,              (files only)
+//!   "md5": "<hex digest>",   (files only)
+//!   "target": "<target>",    (links only)
+//!   "content": [entry, ...]  (dirs, `show --format json` only)
+//! }
+//! ```
+//!
+//! Command specific fields (JSON) and records (NDJSON) are:
+//! - `show`: `"tree"`, the root entry with nested content. NDJSON
+//!   records are all entries of the tree (without `content`), parents
+//!   before children;
+//! - `find`: `"matches"`, the list of matching entries, each with
+//!   `"matched": "name" | "path"` and `"matches"`, a list of
+//!   `{"start", "end", "group"}` byte ranges of the matched name or
+//!   relative path (group 0 for whole matches, n for capture group n),
+//!   and with `--fuzzy` a `"score"`, the best matches first;
+//! - `dups`: `"groups"`, a list of
+//!   `{"type": "duplicates", "md5", "size", "files": [path, ...]}`;
+//! - `diff`: `"other_root"` and `"changes"`, a list of
+//!   `{"type": "change", "status": "added" | "removed" | "modified" |
+//!   "renamed", "old": entry | null, "new": entry | null}` where `old`
+//!   paths are relative to `root` and `new` paths to `other_root`;
+//! - `du`: `"dirs"`, a list of `{"type": "usage", "path", "size",
+//!   "files", "unique_size"}` largest first, and `"files"`, the list of
+//!   the largest file entries;
+//! - `stats`: `"stats"`, an object with the totals (`files`, `dirs`,
+//!   `links`, `bytes`, `empty_files`, `empty_dirs`, `unique_bytes`,
+//!   `duplication_ratio`) and the lists `by_extension` and `by_mime` of
+//!   `{"type": "extension" | "mime", "key", "count", "bytes"}` and
+//!   `ages` and `sizes` of `{"type": "age" | "size", "upper", "count",
+//!   "bytes"}` (`upper` in seconds or bytes, exclusive, null for the last
+//!   bucket). NDJSON records are a `{"type": "totals", ...}` record
+//!   followed by all groups and buckets.
+//!
+//! `watch` prints a stream instead, with both formats: one
+//! `{"type": "event", "schema_version", "event": "created" | "modified" |
+//! "deleted" | "moved" | "rescan", "time", "path", "from", "entry"}`
+//! record per line, where `time` is when the change was applied, `from`
+//! is only present for moves and `entry` is the indexed entry after the
+//! change (null once deleted).
+//!
+//! Names (`path`, `name`, `target`, `root`, `other_root`, `files`) are
+//! strings. File names are arbitrary bytes on Unix: a name that is not
+//! valid UTF-8 is converted lossily (invalid bytes become U+FFFD) and its
+//! exact bytes are added, hex encoded, in a sibling field with the `_hex`
+//! suffix (e.g. `"path_hex": "636166e9"`). For lists, the `_hex` list
+//! holds null for valid names.
+//!
+//! Fields are only ever added within a schema version, consumers should
+//! ignore unknown fields.
+
+use std::ffi::OsStr;
+use std::path::Path;
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Map, Value};
+
+use crate::args::OutputFormat;
+use crate::core::diff::Change;
+use crate::core::dups::DuplicateGroup;
+use crate::core::hash::to_hex;
+use crate::core::model::{Attributes, ContentType, Dir, File, Link};
+use crate::core::name;
+use crate::core::search::Found;
+use crate::core::stats::{Bucket, Group, Stats};
+use crate::core::usage::DirUsage;
+use crate::core::watch::WatchEvent;
+
+/// Version of the JSON schema, bumped on incompatible changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Renders the whole tree of dir
+pub fn show(root: &Path, dir: &Dir, format: OutputFormat) -> String {
+    let mut records = vec![];
+    flatten_dir(root, dir, &mut records);
+    let body = vec![("tree", dir_entry(root, dir, true))];
+    render("show", root, body, records, format)
+}
+
+/// Renders the entries matched by find
+pub fn find(root: &Path, matches: &[Found], format: OutputFormat) -> String {
+    let entries: Vec<Value> = matches
+        .iter()
+        .map(|m| {
+            let mut entry = entry(root, m.entry, false);
+            if let Value::Object(map) = &mut entry {
+                let ranges: Vec<Value> = m
+                    .highlights
+                    .iter()
+                    .map(|h| json!({"start": h.range.start, "end": h.range.end, "group": h.group}))
+                    .collect();
+                map.insert(
+                    "matched".into(),
+                    if m.on_path { "path" } else { "name" }.into(),
+                );
+                map.insert("matches".into(), ranges.into());
+                if let Some(score) = m.score {
+                    map.insert("score".into(), score.into());
+                }
+            }
+            entry
+        })
+        .collect();
+    let body = vec![("matches", Value::from(entries.clone()))];
+    render("find", root, body, entries, format)
+}
+
+/// Renders groups of duplicate files
+pub fn dups(root: &Path, groups: &[DuplicateGroup], format: OutputFormat) -> String {
+    let groups: Vec<Value> = groups
+        .iter()
+        .map(|g| {
+            let mut group = Map::new();
+            group.insert("type".into(), "duplicates".into());
+            group.insert("md5".into(), format!("{:x}", g.md5).into());
+            group.insert("size".into(), g.size.into());
+            let files: Vec<&OsStr> = g
+                .files
+                .iter()
+                .map(|f| relative(root, &f.path).as_os_str())
+                .collect();
+            insert_names(&mut group, "files", &files);
+            Value::Object(group)
+        })
+        .collect();
+    let body = vec![("groups", Value::from(groups.clone()))];
+    render("dups", root, body, groups, format)
+}
+
+/// Renders the changes between the collection at root and other
+pub fn diff(root: &Path, other: &Path, changes: &[Change], format: OutputFormat) -> String {
+    let changes: Vec<Value> = changes
+        .iter()
+        .map(|c| {
+            let (old, new) = match c {
+                Change::Added(n) => (Value::Null, file_entry(other, n)),
+                Change::Removed(o) => (file_entry(root, o), Value::Null),
+                Change::Modified { old, new } | Change::Renamed { from: old, to: new } => {
+                    (file_entry(root, old), file_entry(other, new))
+                }
+            };
+            json!({"type": "change", "status": c.status(), "old": old, "new": new})
+        })
+        .collect();
+    let mut body = vec![("other_root", Value::from(other.to_string_lossy()))];
+    if other.to_str().is_none() {
+        body.push(("other_root_hex", hex(other.as_os_str())));
+    }
+    body.push(("changes", Value::from(changes.clone())));
+    render("diff", root, body, changes, format)
+}
+
+/// Renders the largest directories and files
+pub fn du(root: &Path, dirs: &[&DirUsage], files: &[&File], format: OutputFormat) -> String {
+    let dirs: Vec<Value> = dirs
+        .iter()
+        .map(|d| {
+            let mut usage = Map::new();
+            usage.insert("type".into(), "usage".into());
+            insert_name(&mut usage, "path", relative(root, &d.dir.path).as_os_str());
+            usage.insert("size".into(), d.usage.size.into());
+            usage.insert("files".into(), d.usage.files.into());
+            usage.insert("unique_size".into(), d.usage.unique_size.into());
+            Value::Object(usage)
+        })
+        .collect();
+    let files: Vec<Value> = files.iter().map(|f| file_entry(root, f)).collect();
+    let body = vec![
+        ("dirs", Value::from(dirs.clone())),
+        ("files", Value::from(files.clone())),
+    ];
+    let records = dirs.into_iter().chain(files).collect();
+    render("du", root, body, records, format)
+}
+
+/// Renders the statistics report
+pub fn stats(root: &Path, stats: &Stats, format: OutputFormat) -> String {
+    let groups = |kind: &str, groups: &[Group]| -> Vec<Value> {
+        groups
+            .iter()
+            .map(|g| json!({"type": kind, "key": g.key, "count": g.count, "bytes": g.bytes}))
+            .collect()
+    };
+    let buckets = |kind: &str, buckets: &[Bucket]| -> Vec<Value> {
+        buckets
+            .iter()
+            .map(|b| json!({"type": kind, "upper": b.upper, "count": b.count, "bytes": b.bytes}))
+            .collect()
+    };
+    let totals = json!({
+        "type": "totals",
+        "files": stats.files,
+        "dirs": stats.dirs,
+        "links": stats.links,
+        "bytes": stats.bytes,
+        "empty_files": stats.empty_files,
+        "empty_dirs": stats.empty_dirs,
+        "unique_bytes": stats.usage.unique_size,
+        "duplication_ratio": stats.duplication_ratio(),
+    });
+    let by_extension = groups("extension", &stats.by_extension);
+    let by_mime = groups("mime", &stats.by_mime);
+    let ages = buckets("age", &stats.ages);
+    let sizes = buckets("size", &stats.sizes);
+    let mut object = totals.clone();
+    object["by_extension"] = Value::from(by_extension.clone());
+    object["by_mime"] = Value::from(by_mime.clone());
+    object["ages"] = Value::from(ages.clone());
+    object["sizes"] = Value::from(sizes.clone());
+    if let Some(o) = object.as_object_mut() {
+        o.remove("type");
+    }
+    let records = [vec![totals], by_extension, by_mime, ages, sizes].concat();
+    render("stats", root, vec![("stats", object)], records, format)
+}
+
+/// Renders a change applied by watch as a single line record
+pub fn watch_event(root: &Path, event: &WatchEvent, entry: Option<&ContentType>) -> String {
+    let mut record = Map::new();
+    record.insert("type".into(), "event".into());
+    record.insert("schema_version".into(), SCHEMA_VERSION.into());
+    record.insert("event".into(), event.kind().into());
+    record.insert("time".into(), timestamp(SystemTime::now()).into());
+    insert_name(
+        &mut record,
+        "path",
+        relative(root, event.path()).as_os_str(),
+    );
+    if let WatchEvent::Moved { from, .. } = event {
+        insert_name(&mut record, "from", relative(root, from).as_os_str());
+    }
+    let entry = entry.map_or(Value::Null, |e| self::entry(root, e, false));
+    record.insert("entry".into(), entry);
+    Value::Object(record).to_string()
+}
+
+fn render(
+    command: &str,
+    root: &Path,
+    body: Vec<(&str, Value)>,
+    records: Vec<Value>,
+    format: OutputFormat,
+) -> String {
+    let mut header = Map::new();
+    header.insert("schema_version".into(), SCHEMA_VERSION.into());
+    header.insert("command".into(), command.into());
+    insert_name(&mut header, "root", root.as_os_str());
+    match format {
+        OutputFormat::Ndjson => {
+            let mut first = Map::new();
+            first.insert("type".into(), "header".into());
+            first.extend(header);
+            let mut lines = vec![Value::Object(first).to_string()];
+            lines.extend(records.iter().map(Value::to_string));
+            lines.join("\n") + "\n"
+        }
+        _ => {
+            for (key, value) in body {
+                header.insert(key.into(), value);
+            }
+            serde_json::to_string_pretty(&Value::Object(header)).unwrap_or_default() + "\n"
+        }
+    }
+}
+
+fn flatten_dir(root: &Path, dir: &Dir, records: &mut Vec<Value>) {
+    records.push(dir_entry(root, dir, false));
+    let mut content: Vec<&ContentType> = dir.content.iter().collect();
+    content.sort_by_key(|c| c.path());
+    for c in content {
+        match c {
+            ContentType::ContentDir(d) => flatten_dir(root, d, records),
+            _ => records.push(entry(root, c, false)),
+        }
+    }
+}
+
+fn entry(root: &Path, content: &ContentType, nested: bool) -> Value {
+    match content {
+        ContentType::ContentDir(d) => dir_entry(root, d, nested),
+        ContentType::ContentFile(f) => file_entry(root, f),
+        ContentType::ContentLink(l) => link_entry(root, l),
+    }
+}
+
+fn dir_entry(root: &Path, dir: &Dir, nested: bool) -> Value {
+    let mut value = common("dir", root, &dir.path, &dir.attributes, dir.modified);
+    if nested {
+        let mut content: Vec<&ContentType> = dir.content.iter().collect();
+        content.sort_by_key(|c| c.path());
+        let content: Vec<Value> = content.iter().map(|c| entry(root, c, true)).collect();
+        value.insert("content".into(), content.into());
+    }
+    Value::Object(value)
+}
+
+fn file_entry(root: &Path, file: &File) -> Value {
+    let mut value = common("file", root, &file.path, &file.attributes, file.modified);
+    value.insert("size".into(), file.size.into());
+    value.insert("md5".into(), format!("{:x}", file.md5).into());
+    Value::Object(value)
+}
+
+fn link_entry(root: &Path, link: &Link) -> Value {
+    let mut value = common("link", root, &link.path, &link.attributes, link.modified);
+    insert_name(&mut value, "target", link.target.as_os_str());
+    Value::Object(value)
+}
+
+fn common(
+    kind: &str,
+    root: &Path,
+    path: &Path,
+    attributes: &Attributes,
+    modified: SystemTime,
+) -> Map<String, Value> {
+    let mut value = Map::new();
+    value.insert("type".into(), kind.into());
+    insert_name(&mut value, "path", relative(root, path).as_os_str());
+    insert_name(&mut value, "name", path.file_name().unwrap_or_default());
+    value.insert("mode".into(), attributes.mode.into());
+    value.insert("uid".into(), attributes.uid.into());
+    value.insert("gid".into(), attributes.gid.into());
+    value.insert("modified".into(), timestamp(modified).into());
+    value
+}
+
+fn relative<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    match path.strip_prefix(root) {
+        Ok(p) if p.as_os_str().is_empty() => Path::new("."),
+        Ok(p) => p,
+        Err(_) => path,
+    }
+}
+
+/// Inserts name under key as a string. Names that are not valid UTF-8
+/// are converted lossily and inserted, hex encoded, under `<key>_hex`.
+pub(crate) fn insert_name(map: &mut Map<String, Value>, key: &str, name: &OsStr) {
+    map.insert(key.into(), name.to_string_lossy().into());
+    if name.to_str().is_none() {
+        map.insert(format!("{key}_hex"), hex(name));
+    }
+}
+
+/// Inserts the list of names under key, like `insert_name`. `<key>_hex`
+/// holds null for valid UTF-8 names.
+fn insert_names(map: &mut Map<String, Value>, key: &str, names: &[&OsStr]) {
+    let lossy: Vec<Value> = names.iter().map(|n| n.to_string_lossy().into()).collect();
+    map.insert(key.into(), lossy.into());
+    if names.iter().any(|n| n.to_str().is_none()) {
+        let hex: Vec<Value> = names
+            .iter()
+            .map(|n| match n.to_str() {
+                Some(_) => Value::Null,
+                None => hex(n),
+            })
+            .collect();
+        map.insert(format!("{key}_hex"), hex.into());
+    }
+}
+
+fn hex(name: &OsStr) -> Value {
+    to_hex(name::to_bytes(name)).into()
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::core::search::{FindMode, Matcher};
+
+    fn tree() -> Dir {
+        Dir {
+            path: PathBuf::from("/r"),
+            content: vec![
+                ContentType::ContentFile(File {
+                    path: PathBuf::from("/r/b.txt"),
+                    md5: md5::compute(b"abc"),
+                    size: 3,
+                    ..Default::default()
+                }),
+                ContentType::ContentDir(Dir {
+                    path: PathBuf::from("/r/a"),
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn show_json_nested() {
+        let v: Value =
+            serde_json::from_str(&show(Path::new("/r"), &tree(), OutputFormat::Json)).unwrap();
+        assert_eq!(SCHEMA_VERSION, v["schema_version"]);
+        assert_eq!("show", v["command"]);
+        assert_eq!(".", v["tree"]["path"]);
+        assert_eq!("a", v["tree"]["content"][0]["path"]);
+        let file = &v["tree"]["content"][1];
+        assert_eq!("file", file["type"]);
+        assert_eq!("b.txt", file["path"]);
+        assert_eq!("900150983cd24fb0d6963f7d28e17f72", file["md5"]);
+        assert_eq!(3, file["size"]);
+        assert_eq!("1970-01-01T00:00:00.000000000Z", file["modified"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_hex() {
+        let latin1 = Path::new("/r").join(name::from_bytes(b"caf\xe9".to_vec()));
+        let file = File {
+            path: latin1,
+            ..Default::default()
+        };
+        let v: Value =
+            serde_json::from_str(&find(Path::new("/r"), &[], OutputFormat::Json)).unwrap();
+        assert!(v.get("root_hex").is_none());
+        let entry = file_entry(Path::new("/r"), &file);
+        assert_eq!("caf\u{fffd}", entry["path"]);
+        assert_eq!("636166e9", entry["path_hex"]);
+        assert_eq!("636166e9", entry["name_hex"]);
+    }
+
+    #[test]
+    fn find_match_ranges() {
+        let tree = tree();
+        let matcher = Matcher::new(r"(\w)\.txt", FindMode::Regex, false).unwrap();
+        let matches = matcher.find(&tree, Path::new("/r"));
+        let v: Value =
+            serde_json::from_str(&find(Path::new("/r"), &matches, OutputFormat::Json)).unwrap();
+        let found = &v["matches"][0];
+        assert_eq!("b.txt", found["path"]);
+        assert_eq!("name", found["matched"]);
+        assert_eq!(
+            json!({"start": 0, "end": 5, "group": 0}),
+            found["matches"][0]
+        );
+        assert_eq!(
+            json!({"start": 0, "end": 1, "group": 1}),
+            found["matches"][1]
+        );
+    }
+
+    #[test]
+    fn show_ndjson_lines() {
+        let output = show(Path::new("/r"), &tree(), OutputFormat::Ndjson);
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(4, lines.len());
+        assert_eq!("header", lines[0]["type"]);
+        assert_eq!(SCHEMA_VERSION, lines[0]["schema_version"]);
+        assert_eq!(".", lines[1]["path"]);
+        assert!(lines[1].get("content").is_none());
+        assert_eq!("a", lines[2]["path"]);
+        assert_eq!("b.txt", lines[3]["path"]);
+    }
+
+    #[test]
+    fn diff_json_changes() {
+        let old = tree();
+        let new = Dir {
+            path: PathBuf::from("/n"),
+            ..Default::default()
+        };
+        let changes = crate::core::diff::diff(&old, &new);
+        let output = diff(
+            Path::new("/r"),
+            Path::new("/n"),
+            &changes,
+            OutputFormat::Json,
+        );
+        let v: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!("/n", v["other_root"]);
+        assert_eq!("removed", v["changes"][0]["status"]);
+        assert_eq!("b.txt", v["changes"][0]["old"]["path"]);
+        assert!(v["changes"][0]["new"].is_null());
+    }
+}
@@ -1,222 +1,984 @@
 use core::algorithm;
 use std::fs;
-use std::ops::Range;
-use std::path::PathBuf;
-use std::{collections::HashSet, error::Error};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
 
-use chrono::{DateTime, Local};
-use colored::Colorize;
-use md5::Digest;
-use serde_json::Value;
-
-use algorithm::dice_coefficient;
-use args::Config;
-use core::model::{self, Collection, ContentType, Dir};
+use algorithm::Metric;
+use args::{CollectionAction, Commands, Config};
+use config::CollectionConfig;
+use core::dups;
+use core::hash::HashAlgorithm;
+use core::manifest::{CheckStatus, Manifest, ManifestFormat};
+use core::model::{self, Collection, Dir, ScanError};
+use core::mtree;
+use core::name;
+use core::ngram::NameIndex;
+use core::progress::{self, Cancellable, Checkpointer, ScanObserver};
+use core::query::Query;
+use core::registry::{self, Registered, Registry};
+use core::search::{FindMode, Found, Matcher};
+use core::stats;
+use core::usage;
+use core::verify::{self, VerifyStatus};
+use core::watch;
+use error::{Error, WithPath};
+use render::{Renderer, ShowOptions};
 
 pub mod args;
+pub mod config;
 pub mod core;
-
-mod fsutil {
-    use std::fs;
-    use std::path::Path;
-    use std::time::SystemTime;
-
-    pub fn get_last_modified_time(path: &Path) -> Result<SystemTime, std::io::Error> {
-        fs::metadata(path)?.modified()
-    }
-}
+#[cfg(unix)]
+pub mod daemon;
+pub mod error;
+pub mod json;
+pub mod render;
+pub mod shell;
+pub mod tui;
 
 enum Command {
-    Show,
-    Find,
+    Show(ShowOptions),
+    Find {
+        pattern: String,
+        mode: FindMode,
+        metric: Option<Metric>,
+        on_path: bool,
+        query: Option<String>,
+    },
+    Scan {
+        resume: bool,
+    },
+    Dups,
+    Diff {
+        other: PathBuf,
+        metric: Metric,
+    },
+    Verify,
+    Du {
+        top: usize,
+    },
+    Stats,
+    Manifest {
+        output: PathBuf,
+        algorithm: Option<HashAlgorithm>,
+        format: ManifestFormat,
+    },
+    Check {
+        manifest: PathBuf,
+    },
+    Mtree {
+        output: PathBuf,
+        algorithms: Vec<HashAlgorithm>,
+    },
+    MtreeCheck {
+        spec: PathBuf,
+    },
+    Watch {
+        debounce: Duration,
+        log: Option<PathBuf>,
+    },
+    Daemon {
+        stop: bool,
+    },
+    Tui {
+        other: Option<PathBuf>,
+    },
+    Shell {
+        db: Option<PathBuf>,
+    },
+    AddCollection {
+        name: Option<String>,
+        db: Option<PathBuf>,
+    },
+    RemoveCollection,
+    ListCollections,
+    Init {
+        name: Option<String>,
+        hash: HashAlgorithm,
+        ignore: Vec<String>,
+    },
     // Nop,
 }
 
 impl Command {
     fn from(config: &Config) -> Self {
-        if let Some(_) = &config.find {
-            return Command::Find;
+        match &config.command {
+            Some(Commands::Show {
+                tree,
+                depth,
+                sort,
+                reverse,
+                mixed,
+            }) => {
+                return Command::Show(ShowOptions {
+                    tree: *tree || depth.is_some(),
+                    depth: *depth,
+                    sort: *sort,
+                    reverse: *reverse,
+                    dirs_first: !mixed,
+                })
+            }
+            Some(Commands::Scan { resume }) => return Command::Scan { resume: *resume },
+            Some(Commands::Dups) => return Command::Dups,
+            Some(Commands::Diff { other, metric }) => {
+                return Command::Diff {
+                    other: other.clone(),
+                    metric: *metric,
+                }
+            }
+            Some(Commands::Verify) => return Command::Verify,
+            Some(Commands::Du { top }) => return Command::Du { top: *top },
+            Some(Commands::Stats) => return Command::Stats,
+            Some(Commands::Manifest {
+                output,
+                algorithm,
+                style,
+            }) => {
+                return Command::Manifest {
+                    output: output.clone(),
+                    algorithm: *algorithm,
+                    format: *style,
+                }
+            }
+            Some(Commands::Check { manifest }) => {
+                return Command::Check {
+                    manifest: manifest.clone(),
+                }
+            }
+            Some(Commands::Mtree { output, algorithms }) => {
+                return Command::Mtree {
+                    output: output.clone(),
+                    algorithms: algorithms.clone(),
+                }
+            }
+            Some(Commands::MtreeCheck { spec }) => {
+                return Command::MtreeCheck { spec: spec.clone() }
+            }
+            Some(Commands::Watch { debounce, log }) => {
+                return Command::Watch {
+                    debounce: Duration::from_millis(*debounce),
+                    log: log.clone(),
+                }
+            }
+            Some(Commands::Daemon { stop }) => return Command::Daemon { stop: *stop },
+            Some(Commands::Shell { db }) => return Command::Shell { db: db.clone() },
+            Some(Commands::Collection { action }) => {
+                return match action {
+                    CollectionAction::Add { name, db } => Command::AddCollection {
+                        name: name.clone(),
+                        db: db.clone(),
+                    },
+                    CollectionAction::Remove => Command::RemoveCollection,
+                    CollectionAction::List => Command::ListCollections,
+                }
+            }
+            Some(Commands::Tui { other }) => {
+                return Command::Tui {
+                    other: other.clone(),
+                }
+            }
+            Some(Commands::Init { name, hash, ignore }) => {
+                return Command::Init {
+                    name: name.clone(),
+                    hash: *hash,
+                    ignore: ignore.clone(),
+                }
+            }
+            None => (),
+        }
+        if config.find.is_some() || config.query.is_some() {
+            return Command::Find {
+                pattern: config.find.clone().unwrap_or_default(),
+                mode: match (config.regex, config.fuzzy) {
+                    (true, _) => FindMode::Regex,
+                    (_, true) => FindMode::Fuzzy,
+                    _ => FindMode::Substring,
+                },
+                metric: config.metric,
+                on_path: config.match_path,
+                query: config.query.clone(),
+            };
         }
-        Command::Show
+        Command::Show(ShowOptions::default())
     }
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    print_welcome();
+pub fn run(mut config: Config) -> error::Result<()> {
     let command = Command::from(&config);
-    let mut collection: model::Collection = open_or_create_config(&config.path);
-    // TODO: replace with a `print_collection_info`` function
-    match collection.name.as_str() {
-        "" => println!(
-            "Unnamed collection opened at {}",
-            &config.path.to_str().unwrap_or("")
-        ),
-        _ => println!(
-            "Opened {} collection at {}",
-            collection.name,
-            &config.path.to_str().unwrap_or("")
+    if let Command::Init { name, hash, ignore } = &command {
+        let mut renderer = render::from_args(&config.output);
+        renderer.print_welcome()?;
+        let config_path = init(&config.path, name.as_deref(), *hash, ignore)?;
+        return renderer
+            .print_message(&format!(
+                "Initialized collection in {}",
+                name::escape_path(&config_path)
+            ))
+            .map_err(Error::from);
+    }
+    // invalid queries and patterns are reported before scanning
+    let query = match &command {
+        Command::Find { query: Some(q), .. } => Some(parse_query(q)?),
+        _ => None,
+    };
+    let matcher = match &command {
+        Command::Find {
+            pattern,
+            mode,
+            metric,
+            on_path,
+            ..
+        } => Some(
+            Matcher::new(pattern, *mode, *on_path)
+                .map_err(Error::Regex)?
+                .with_metric(*metric),
         ),
+        _ => None,
+    };
+    let mut collection: model::Collection = open_collection(&config.path)?;
+    config.filters.apply(&mut collection.filter);
+    if config.output.format.is_none() {
+        let root = collection.enclosing.as_ref().unwrap_or(&collection.root);
+        config.output.format = read_config(root)?.format;
+    }
+    let mut renderer = render::from_args(&config.output);
+    renderer.print_welcome()?;
+    let mut scanner = Scanner::from(&config);
+    if matches!(
+        command,
+        Command::Scan { .. }
+            | Command::Watch { .. }
+            | Command::Daemon { .. }
+            | Command::AddCollection { .. }
+    ) {
+        // these keep the index of the whole collection up to date
+        collection.widen();
+    }
+    renderer.print_collection_info(&collection)?;
+    // the daemon serves whole collections only, as configured
+    if !config.no_daemon
+        && !config.strict
+        && collection.enclosing.is_none()
+        && config.filters == args::FilterArgs::default()
+        && run_on_daemon(
+            &config,
+            &collection.root,
+            &command,
+            matcher.as_ref(),
+            renderer.as_mut(),
+        )?
+    {
+        return Ok(());
     }
-    collection.scan()?;
     // TODO: If all commands require unwrapping root_dir, then do it once
     // propagating (returning) an error when something goes wrong
     match command {
-        Command::Show => {
+        Command::Show(options) => {
+            scanner.scan(&mut collection, renderer.as_mut())?;
             if let Some(c) = collection.root_dir {
-                print_content(&c);
+                renderer.print_content(&collection.root, &c, &options)?;
             };
         }
-        Command::Find => {
+        Command::Find { .. } => {
+            // similar names are scored among the candidates of the index
+            // saved by scan, without scanning again
+            let index = match &matcher {
+                Some(m) if m.uses_index() && query.is_none() && !config.strict => {
+                    load_indexed(&mut collection, &config)?
+                }
+                _ => None,
+            };
+            if index.is_none() {
+                scanner.scan(&mut collection, renderer.as_mut())?;
+            }
+            if let (Some(c), Some(matcher)) = (collection.root_dir, matcher) {
+                let indexed = index.and_then(|i| matcher.find_indexed(&c, &i));
+                let matches = match indexed {
+                    Some(matches) => matches,
+                    None => find_matching(&c, &collection.root, &matcher, query.as_ref()),
+                };
+                renderer.print_find_matches(&collection.root, &matches)?;
+            }
+        }
+        Command::Dups => {
+            scanner.scan(&mut collection, renderer.as_mut())?;
             if let Some(c) = collection.root_dir {
-                // unwrap should be safe, if clap is used properly
-                let pattern = &config.find.unwrap();
-                let matches = find(&c, &pattern);
-                print_find_matches(matches, &pattern);
+                let groups = dups::find_duplicates(&c);
+                renderer.print_duplicates(&collection.root, &groups)?;
+            }
+        }
+        Command::Diff { other, metric } => {
+            scanner.scan(&mut collection, renderer.as_mut())?;
+            let mut other_collection = open_collection(&other)?;
+            config.filters.apply(&mut other_collection.filter);
+            scanner.scan(&mut other_collection, renderer.as_mut())?;
+            if let (Some(c), Some(o)) = (collection.root_dir, other_collection.root_dir) {
+                let changes = core::diff::diff_with(&c, &o, metric);
+                renderer.print_changes(&collection.root, &other_collection.root, &changes)?;
+            }
+        }
+        Command::Scan { resume } => {
+            scanner.scan_to_db(&mut collection, renderer.as_mut(), resume)?;
+            collection.save().with_path(&collection.db_path())?;
+            if let Some(dir) = &collection.root_dir {
+                let index = collection.index_path();
+                NameIndex::build(dir)
+                    .save(&index, &collection.db_path())
+                    .with_path(&index)?;
+            }
+            renderer.print_message(&format!(
+                "Collection saved to {}",
+                name::escape_path(&collection.db_path())
+            ))?;
+        }
+        Command::Verify => {
+            if !collection.load().with_path(&collection.db_path())? {
+                return Err(Error::NoDatabase(collection.db_path()));
+            }
+            if let Some(c) = collection.root_dir {
+                let report = verify::verify_dir(&c);
+                renderer.print_verify_report(&report)?;
+                let corrupted = report
+                    .iter()
+                    .filter(|(_, s)| *s == VerifyStatus::Corrupted)
+                    .count();
+                if corrupted > 0 {
+                    return Err(Error::Corrupted(corrupted));
+                }
+            }
+        }
+        Command::Du { top } => {
+            scanner.load_or_scan(&mut collection, renderer.as_mut())?;
+            if let Some(c) = collection.root_dir {
+                let usage = usage::dir_usage(&c);
+                let dirs = usage::largest_dirs(&usage, top);
+                let files = usage::largest_files(&c, top);
+                renderer.print_usage(&collection.root, &dirs, &files)?;
+            }
+        }
+        Command::Stats => {
+            scanner.load_or_scan(&mut collection, renderer.as_mut())?;
+            if let Some(c) = collection.root_dir {
+                let stats = stats::stats(&c, SystemTime::now());
+                renderer.print_stats(&collection.root, &stats)?;
+            }
+        }
+        Command::Manifest {
+            output,
+            algorithm,
+            format,
+        } => {
+            scanner.load_or_scan(&mut collection, renderer.as_mut())?;
+            let algorithm = algorithm.or(collection.hash).unwrap_or(HashAlgorithm::Md5);
+            if let Some(c) = collection.root_dir {
+                let manifest = Manifest::from_dir(&c, &collection.root, algorithm)?;
+                fs::write(&output, manifest.render(format)).with_path(&output)?;
+                renderer.print_message(&format!(
+                    "{} {} checksums written to {}",
+                    manifest.entries.len(),
+                    algorithm,
+                    name::escape_path(&output)
+                ))?;
+            }
+        }
+        Command::Check { manifest } => {
+            let manifest = Manifest::parse(&fs::read(&manifest).with_path(&manifest)?);
+            let report = manifest.check(&collection.root);
+            renderer.print_check_report(&report, manifest.malformed.len())?;
+            let failures = report.iter().filter(|(_, s)| *s != CheckStatus::Ok).count();
+            if failures > 0 {
+                return Err(Error::ChecksumMismatch {
+                    failed: failures,
+                    total: report.len(),
+                });
+            }
+        }
+        Command::Mtree {
+            output,
+            mut algorithms,
+        } => {
+            // the stored times may be older than the tree (the root at
+            // least, changed by saving the database), only the changed
+            // files are hashed again
+            scanner.refresh(&mut collection, renderer.as_mut())?;
+            if algorithms.is_empty() {
+                algorithms.push(collection.hash.unwrap_or(HashAlgorithm::Md5));
+            }
+            if let Some(c) = collection.root_dir {
+                let spec = mtree::write_spec(&c, &collection.root, &algorithms)?;
+                fs::write(&output, spec).with_path(&output)?;
+                renderer.print_message(&format!(
+                    "Specification written to {}",
+                    name::escape_path(&output)
+                ))?;
+            }
+        }
+        Command::MtreeCheck { spec } => {
+            let entries = mtree::parse_spec(&fs::read_to_string(&spec).with_path(&spec)?).map_err(
+                |source| Error::Spec {
+                    path: spec.clone(),
+                    source,
+                },
+            )?;
+            let report = mtree::compare(&collection.root, &entries);
+            renderer.print_mtree_report(&report)?;
+            if !report.is_empty() {
+                return Err(Error::SpecMismatch(report.len()));
+            }
+        }
+        Command::Watch { debounce, log } => {
+            // subscribed first, changes during the scan are not missed
+            let mut watch = watch::Watch::new(&collection.root).with_path(&collection.root)?;
+            let stored = scanner.refresh(&mut collection, renderer.as_mut())?;
+            let mut log = match log {
+                Some(path) => {
+                    let file = fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .with_path(&path)?;
+                    Some(render::with_writer(
+                        &config.output,
+                        render::Uncolored::new(file),
+                    ))
+                }
+                None => None,
+            };
+            renderer.print_message(&format!(
+                "Watching {}, press Ctrl-C to stop",
+                name::escape_path(&collection.root)
+            ))?;
+            let root = collection.root.clone();
+            let filter = collection.filter.clone();
+            let stop = interrupted();
+            if let Some(dir) = collection.root_dir.as_mut() {
+                while let Some(events) = watch.next_burst(debounce, &stop).with_path(&root)? {
+                    for event in events {
+                        let skipped = watch::apply(dir, &event, &filter);
+                        if !skipped.is_empty() {
+                            renderer.print_scan_errors(&skipped)?;
+                        }
+                        // changes of ignored entries are not reported
+                        if !filter.includes(event.path()) {
+                            continue;
+                        }
+                        let out = log.as_deref_mut().unwrap_or(renderer.as_mut());
+                        out.print_watch_event(&root, &event, watch::entry(dir, event.path()))?;
+                    }
+                }
+            }
+            if stored {
+                collection.save().with_path(&collection.db_path())?;
+                renderer.print_message(&format!(
+                    "Collection saved to {}",
+                    name::escape_path(&collection.db_path())
+                ))?;
+            }
+        }
+        Command::Daemon { stop } => serve_daemon(
+            &config,
+            &collection.root,
+            collection.db.as_deref(),
+            stop,
+            renderer.as_mut(),
+        )?,
+        Command::Tui { other } => {
+            scanner.load_or_scan(&mut collection, renderer.as_mut())?;
+            let other_collection = match other {
+                Some(other) => {
+                    let mut other_collection = open_collection(&other)?;
+                    config.filters.apply(&mut other_collection.filter);
+                    scanner.load_or_scan(&mut other_collection, renderer.as_mut())?;
+                    Some(other_collection)
+                }
+                None => None,
+            };
+            if let Some(c) = &collection.root_dir {
+                let app = match other_collection.as_ref().and_then(|o| o.root_dir.as_ref()) {
+                    Some(o) => tui::App::side_by_side(c, o),
+                    None => tui::App::new(c),
+                };
+                tui::run(&mut app.ascii(config.output.ascii))?;
+            }
+        }
+        Command::AddCollection { name, db } => {
+            let root = fs::canonicalize(&collection.root).with_path(&collection.root)?;
+            let name = match name {
+                Some(name) => name,
+                None if !collection.name.is_empty() => collection.name.clone(),
+                None => root
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            };
+            if !registry::is_valid_name(&name) {
+                return Err(Error::InvalidName(name));
+            }
+            let db = match db {
+                Some(db) => Some(std::path::absolute(&db).with_path(&db)?),
+                None => collection.db.clone(),
+            };
+            let (path, mut registry) = load_registry()?;
+            let previous = registry.add(&name, Registered { root, db });
+            registry.save(&path).with_path(&path)?;
+            renderer.print_message(&format!(
+                "Collection @{name} {} at {}",
+                if previous.is_some() {
+                    "updated"
+                } else {
+                    "registered"
+                },
+                name::escape_path(&registry.collections[&name].root)
+            ))?;
+        }
+        Command::RemoveCollection => {
+            let (path, mut registry) = load_registry()?;
+            let names: Vec<String> = match registry::collection_name(&config.path) {
+                Some(name) => vec![name.to_string()],
+                None => {
+                    let root = fs::canonicalize(&config.path).with_path(&config.path)?;
+                    registry
+                        .names_of(&root)
+                        .into_iter()
+                        .map(String::from)
+                        .collect()
+                }
+            };
+            if names.is_empty() {
+                return Err(Error::UnknownCollection(name::escape_path(&config.path)));
+            }
+            for name in names.iter() {
+                registry.remove(name);
+            }
+            registry.save(&path).with_path(&path)?;
+            for name in names {
+                renderer.print_message(&format!("Collection @{name} removed"))?;
+            }
+        }
+        Command::ListCollections => {
+            let (_, registry) = load_registry()?;
+            renderer.print_collections(&registry)?;
+        }
+        Command::Shell { db } => {
+            // only the database is read, the collection may be offline
+            collection.db = db.or(collection.db);
+            if !collection.load().with_path(&collection.db_path())? {
+                return Err(Error::NoDatabase(collection.db_path()));
+            }
+            if let Some(c) = &collection.root_dir {
+                shell::run(c, renderer.as_mut())?;
             }
         }
+        Command::Init { .. } => unreachable!("handled before opening the collection"),
     }
 
     Ok(())
 }
 
-/// Attempts to parse content as a configuration storing result into collection
-fn parse_config(content: &str, collection: &mut Collection) -> serde_json::Result<()> {
-    let v: Value = serde_json::from_str(content)?;
-    if let Some(name) = v.get("name").and_then(|n| n.as_str()) {
-        collection.name = name.to_string();
-    }
-    Ok(())
+/// Scans collections as requested on the command line
+struct Scanner {
+    /// Fail when an entry cannot be scanned
+    strict: bool,
+    observer: Box<dyn ScanObserver>,
 }
 
-pub fn open_or_create_config(root: &PathBuf) -> Collection {
-    let config = root.join(".diffrust.conf");
+impl Scanner {
+    fn from(config: &Config) -> Self {
+        Scanner {
+            strict: config.strict,
+            observer: Box::new(Cancellable::new(
+                render::progress_from_args(&config.output),
+                interrupted(),
+            )),
+        }
+    }
 
-    let mut collection = Collection::new();
-    collection.root = root.clone(); // # TODO: should this be a "method" or a "property"?
-    if config.is_file() {
-        if let Ok(content) = fs::read_to_string(&config) {
-            let _ = parse_config(&content, &mut collection);
-            collection.db = Some(config);
+    /// Scans the collection, printing the entries that could not be
+    /// indexed. In strict mode any such entry fails the command.
+    fn scan(
+        &mut self,
+        collection: &mut Collection,
+        renderer: &mut dyn Renderer,
+    ) -> error::Result<()> {
+        let result = collection.scan_with(self.observer.as_mut());
+        let skipped = match result {
+            Err(e) if progress::is_cancelled(&e) => return Err(Error::Cancelled(None)),
+            result => result.with_path(&collection.root)?,
+        };
+        self.report(skipped, renderer)
+    }
+
+    /// Scans the collection hashing only the files that changed since
+    /// its database was written (everything without database). Returns
+    /// whether a database was found.
+    fn refresh(
+        &mut self,
+        collection: &mut Collection,
+        renderer: &mut dyn Renderer,
+    ) -> error::Result<bool> {
+        let stored = collection.load().with_path(&collection.db_path())?;
+        collection.reuse_hashes();
+        let result = collection.resume_with(self.observer.as_mut());
+        let skipped = match result {
+            Err(e) if progress::is_cancelled(&e) => return Err(Error::Cancelled(None)),
+            result => result.with_path(&collection.root)?,
+        };
+        self.report(skipped, renderer)?;
+        Ok(stored)
+    }
+
+    /// Scans the collection like `scan`, periodically checkpointing the
+    /// hashed files into its database. When resume is set the files in
+    /// the checkpoint left by a cancelled scan are not hashed again.
+    fn scan_to_db(
+        &mut self,
+        collection: &mut Collection,
+        renderer: &mut dyn Renderer,
+        resume: bool,
+    ) -> error::Result<()> {
+        let db = collection.db_path();
+        // the checkpoints keep the tree of the last complete scan, a
+        // database that cannot be read is simply replaced
+        let mut stored = open_or_create_config(&collection.root)?;
+        stored.db = collection.db.clone();
+        let loaded = stored.load();
+        if resume {
+            loaded.with_path(&db)?;
+            collection.checkpoint = stored.checkpoint.take();
+            if collection.checkpoint.is_none() {
+                renderer.print_message("No checkpoint found, scanning everything")?;
+            }
         }
+        let mut checkpointer = Checkpointer::new(self.observer.as_mut(), stored);
+        let skipped = match collection.resume_with(&mut checkpointer) {
+            Err(e) if progress::is_cancelled(&e) => {
+                let files = checkpointer.save().with_path(&db)?;
+                return Err(Error::Cancelled(Some(files)));
+            }
+            result => result.with_path(&collection.root)?,
+        };
+        self.report(skipped, renderer)
+    }
+
+    fn report(&self, skipped: Vec<ScanError>, renderer: &mut dyn Renderer) -> error::Result<()> {
+        if !skipped.is_empty() {
+            renderer.print_scan_errors(&skipped)?;
+            if self.strict {
+                return Err(Error::Incomplete(skipped.len()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the stored collection tree, scanning the file system only
+    /// when no database is available
+    fn load_or_scan(
+        &mut self,
+        collection: &mut Collection,
+        renderer: &mut dyn Renderer,
+    ) -> error::Result<()> {
+        if !collection.load().with_path(&collection.db_path())? {
+            self.scan(collection, renderer)?;
+        }
+        Ok(())
     }
-    collection
 }
 
-fn print_welcome() {
-    println!(
-        "\nWelcome to {} compare 🔄 file system directories 📁",
-        "DIFFRUST".cyan().bold()
-    );
+/// Runs command on a running daemon, returns false when the command is
+/// not served by the daemon or none is running
+#[cfg(unix)]
+fn run_on_daemon(
+    config: &Config,
+    root: &Path,
+    command: &Command,
+    matcher: Option<&Matcher>,
+    renderer: &mut dyn Renderer,
+) -> error::Result<bool> {
+    if !matches!(
+        command,
+        Command::Show(_) | Command::Find { .. } | Command::Dups | Command::Diff { .. }
+    ) {
+        return Ok(false);
+    }
+    // a default socket that others could have made is not used
+    let Ok(socket) = daemon::socket(config.socket.as_deref()) else {
+        return Ok(false);
+    };
+    let Some(mut client) = daemon::Client::connect(&socket) else {
+        return Ok(false);
+    };
+    // the daemon knows collections by absolute path, results are
+    // printed relative to it. Only collections it already holds are
+    // asked for, others would be loaded and watched for good.
+    let root = fs::canonicalize(root).with_path(root)?;
+    if !client.holds(&root)? {
+        return Ok(false);
+    }
+    match command {
+        Command::Show(options) => {
+            let tree = client.show(&root)?;
+            renderer.print_content(&root, &tree, options)?;
+        }
+        Command::Find { query, .. } => {
+            let Some(matcher) = matcher else {
+                return Ok(false);
+            };
+            let matches = client.find(&root, matcher, query.as_deref())?;
+            // the daemon returns the entries, highlights are found again
+            let matches = matcher.filter(matches.iter().collect(), &root);
+            renderer.print_find_matches(&root, &matches)?;
+        }
+        Command::Dups => {
+            let groups = client.dups(&root)?;
+            let groups: Vec<dups::DuplicateGroup> = groups
+                .iter()
+                .filter(|files| !files.is_empty())
+                .map(|files| dups::DuplicateGroup {
+                    md5: files[0].md5,
+                    size: files[0].size,
+                    files: files.iter().collect(),
+                })
+                .collect();
+            renderer.print_duplicates(&root, &groups)?;
+        }
+        Command::Diff { other, metric } => {
+            let other = open_collection(other)?.root;
+            let other = fs::canonicalize(&other).with_path(&other)?;
+            if !client.holds(&other)? {
+                return Ok(false);
+            }
+            let records = client.diff(&root, &other, *metric)?;
+            let changes: Vec<core::diff::Change> = records
+                .iter()
+                .filter_map(daemon::ChangeRecord::change)
+                .collect();
+            renderer.print_changes(&root, &other, &changes)?;
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
 }
 
-fn print_content(dir: &Dir) {
-    let dirs = dir.sorted_dirs();
-    let files = dir.sorted_files();
+#[cfg(not(unix))]
+fn run_on_daemon(
+    _config: &Config,
+    _root: &Path,
+    _command: &Command,
+    _matcher: Option<&Matcher>,
+    _renderer: &mut dyn Renderer,
+) -> error::Result<bool> {
+    Ok(false)
+}
 
-    // print directories first
-    println!();
-    for item in dirs.iter() {
-        println!(" 📁 {:}", item.path.file_name().unwrap().to_str().unwrap());
+/// Serves the collection at root, with its database at db (and any
+/// other requested one) until interrupted, or stops the running daemon
+#[cfg(unix)]
+fn serve_daemon(
+    config: &Config,
+    root: &Path,
+    db: Option<&Path>,
+    stop: bool,
+    renderer: &mut dyn Renderer,
+) -> error::Result<()> {
+    let socket =
+        daemon::socket(config.socket.as_deref()).map_err(|e| Error::Daemon(e.to_string()))?;
+    if stop {
+        let mut client = daemon::Client::connect(&socket)
+            .ok_or_else(|| Error::Daemon(format!("no daemon on {}", name::escape_path(&socket))))?;
+        client.shutdown()?;
+        return renderer
+            .print_message("Daemon stopped")
+            .map_err(Error::from);
     }
-
-    // print files next counting unique md5's
-    let mut md5_set: HashSet<&Digest> = HashSet::new();
-    for item in files.iter() {
-        md5_set.insert(&item.md5);
-        println!(
-            " 🗄  {:} ({:?})",
-            item.path.file_name().unwrap().to_str().unwrap(),
-            &item.md5
-        );
+    // another collection for the running daemon
+    if let Some(mut client) = daemon::Client::connect(&socket) {
+        let root = fs::canonicalize(root).with_path(root)?;
+        let files = client.load(&root, db)?;
+        return renderer
+            .print_message(&format!(
+                "Collection loaded by the running daemon ({files} files)"
+            ))
+            .map_err(Error::from);
     }
-    println!("\n{} total directories", dirs.len());
-    println!("{} total files ({} unique)\n", files.len(), md5_set.len());
+    let daemon = daemon::Daemon::new(interrupted(), Box::new(std::io::stderr()));
+    daemon.load(root, db)?;
+    renderer.print_message("Serving, press Ctrl-C to stop")?;
+    daemon.serve(&socket).with_path(&socket)
+}
 
-    // finally, prints last modification time for dir
-    let last_modified_time = fsutil::get_last_modified_time(dir.path.as_path()).unwrap();
-    let local_time: DateTime<Local> = last_modified_time.into();
-    println!("Last modified: {:}", local_time.format("%Y-%m-%d %H:%M:%S"));
+#[cfg(not(unix))]
+fn serve_daemon(
+    _config: &Config,
+    _root: &Path,
+    _db: Option<&Path>,
+    _stop: bool,
+    _renderer: &mut dyn Renderer,
+) -> error::Result<()> {
+    Err(Error::Daemon("not supported on this platform".into()))
 }
 
-fn print_find_matches(matches: Vec<&ContentType>, pattern: &str) {
-    // Ideally this function would work like this
-    // - iterate over the input
-    // - for each input, extract matches
-    // - format each extracted match
-    // - print formatted match of the input
-    // Notice that this would make the second parameter superfluous
-    for m in matches {
-        match m {
-            ContentType::ContentDir(d) => {
-                let path = d.path.as_path();
-                let name = path.file_name().unwrap().to_str().unwrap();
-                println!(" 📁 {}", name.to_string());
-            }
-            ContentType::ContentFile(f) => {
-                let path = f.path.as_path();
-                let name = path
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .replace(pattern, pattern.to_string().red().to_string().as_str());
-                println!(" 🗄  {}", name.to_string());
+/// Returns the flag set when the user interrupts the process (SIGINT),
+/// a second interrupt terminates the process immediately
+fn interrupted() -> Arc<AtomicBool> {
+    static FLAG: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+    FLAG.get_or_init(|| {
+        let flag = Arc::new(AtomicBool::new(false));
+        let handler_flag = flag.clone();
+        // without a handler interrupts keep their default behaviour
+        let _ = ctrlc::set_handler(move || {
+            if handler_flag.swap(true, Ordering::Relaxed) {
+                std::process::exit(130);
             }
-            _ => (),
+        });
+        flag
+    })
+    .clone()
+}
+
+/// Reads the configuration of the collection at root, the default one
+/// when it has none
+fn read_config(root: &Path) -> error::Result<CollectionConfig> {
+    let path = root.join(model::CONFIG_FILE_NAME);
+    if !path.is_file() {
+        return Ok(CollectionConfig::default());
+    }
+    let content = fs::read_to_string(&path).with_path(&path)?;
+    CollectionConfig::parse(&content).map_err(|source| Error::Config { path, source })
+}
+
+/// Writes the configuration of a new collection at root, returning its
+/// path. An existing configuration is never overwritten.
+fn init(
+    root: &Path,
+    name: Option<&str>,
+    hash: HashAlgorithm,
+    ignore: &[String],
+) -> error::Result<PathBuf> {
+    let config = root.join(model::CONFIG_FILE_NAME);
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => fs::canonicalize(root)
+            .with_path(root)?
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    let content = CollectionConfig {
+        name: Some(name),
+        hash: Some(hash),
+        ignore: ignore.to_vec(),
+        ..Default::default()
+    };
+    let mut file = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&config)
+    {
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            return Err(Error::AlreadyInitialized(config))
         }
+        file => file.with_path(&config)?,
+    };
+    std::io::Write::write_all(&mut file, content.to_json().as_bytes()).with_path(&config)?;
+    Ok(config)
+}
+
+/// The nearest directory enclosing path (path itself included) with a
+/// collection configuration, like git finds `.git`
+fn find_enclosing(path: &Path) -> Option<PathBuf> {
+    let path = fs::canonicalize(path).ok()?;
+    path.ancestors()
+        .find(|dir| dir.join(model::CONFIG_FILE_NAME).is_file())
+        .map(Path::to_path_buf)
+}
+
+/// Opens the collection at path, or the registered collection for
+/// `@name` (with its database). A path inside a collection opens the
+/// subtree of the nearest enclosing collection.
+pub fn open_collection(path: &Path) -> error::Result<Collection> {
+    let Some(name) = registry::collection_name(path) else {
+        if path.join(model::CONFIG_FILE_NAME).is_file() {
+            return open_or_create_config(path);
+        }
+        return match find_enclosing(path) {
+            Some(enclosing) => {
+                let mut collection = open_or_create_config(&enclosing)?;
+                collection.root = fs::canonicalize(path).with_path(path)?;
+                collection.enclosing = Some(enclosing);
+                Ok(collection)
+            }
+            None => open_or_create_config(path),
+        };
+    };
+    let (_, registry) = load_registry()?;
+    let registered = registry
+        .get(name)
+        .ok_or_else(|| Error::UnknownCollection(format!("@{name}")))?;
+    let mut collection = open_or_create_config(&registered.root)?;
+    collection.db = registered.db.clone();
+    if collection.name.is_empty() {
+        collection.name = name.to_string();
     }
+    Ok(collection)
 }
 
-fn find<'a>(dir: &'a Dir, pattern: &str) -> Vec<&'a ContentType> {
-    dir.content
-        .iter()
-        .filter(|f| match f {
-            ContentType::ContentDir(d) => algorithm::substrings_in_name(&d.path, pattern).len() > 0,
-            ContentType::ContentFile(f) => {
-                algorithm::substrings_in_name(&f.path, pattern).len() > 0
-            }
-            _ => false,
-        })
-        .collect()
+/// Reads the registry of named collections, returning its path too
+fn load_registry() -> error::Result<(PathBuf, Registry)> {
+    let path = Registry::default_path()?;
+    let registry = Registry::load(&path).with_path(&path)?;
+    Ok((path, registry))
 }
 
-/// Returns a vector of ranges, entry i contains the range where the
-/// pattern matched the input i. If no such match is found the position
-/// contains None.
-fn _substring_match_content(
-    content: &Vec<ContentType>,
-    _pattern: &str,
-) -> Vec<Option<Range<usize>>> {
-    content
-        .iter()
-        .map(|c| match c {
-            ContentType::ContentDir(_) => None,
-            ContentType::ContentFile(_) => None,
-            _ => None,
-        })
-        .collect()
+pub fn open_or_create_config(root: &Path) -> error::Result<Collection> {
+    let mut collection = Collection::new();
+    collection.root = root.to_path_buf(); // # TODO: should this be a "method" or a "property"?
+    read_config(root)?.apply(&mut collection);
+    Ok(collection)
 }
 
-/// Returns a vector of (score, index) pairs indicating that input at
-/// indicated index obtained the corresponding fuzzy matching score
-/// (Dice coefficient is used) against the provided pattern.
-fn _fuzzy_match_content(content: &Vec<ContentType>, pattern: &str) -> Vec<(f64, usize)> {
-    content
-        .iter()
-        .enumerate()
-        .map(|(i, val)| {
-            let text = match &val {
-                ContentType::ContentFile(f) => f.path.to_str().unwrap(),
-                ContentType::ContentDir(d) => d.path.to_str().unwrap(),
-                _ => "",
-            };
-            (dice_coefficient(text, pattern), i)
-        })
-        .collect()
+/// Parses the query of find
+fn parse_query(query: &str) -> error::Result<Query> {
+    Query::parse(query).map_err(|source| Error::Query {
+        query: query.to_string(),
+        source,
+    })
+}
+
+/// Finds the entries found by matcher and the query: in the content of
+/// dir for a substring alone, at any depth with a query or another mode
+pub(crate) fn find_matching<'a>(
+    dir: &'a Dir,
+    root: &Path,
+    matcher: &Matcher,
+    query: Option<&Query>,
+) -> Vec<Found<'a>> {
+    match query {
+        Some(query) => matcher.filter(query.find(dir, root), root),
+        None => matcher.find(dir, root),
+    }
+}
+
+/// Loads the tree of collection and the name index saved by scan, None
+/// when there is no up to date index or it does not cover the entries
+/// to search: a subdirectory, or other filters than the configured ones.
+/// Reading the whole database takes longer than the search itself on
+/// large collections, which only a daemon answers without reading it.
+fn load_indexed(collection: &mut Collection, config: &Config) -> error::Result<Option<NameIndex>> {
+    if collection.enclosing.is_some() || config.filters != args::FilterArgs::default() {
+        return Ok(None);
+    }
+    let (path, db) = (collection.index_path(), collection.db_path());
+    let Some(index) = NameIndex::load(&path, &db).with_path(&path)? else {
+        return Ok(None);
+    };
+    Ok(collection.load().with_path(&db)?.then_some(index))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::PathBuf;
+
     use super::*;
 
     #[test]
@@ -224,79 +986,67 @@ mod tests {
         let mut config = Config {
             path: PathBuf::from("/tmp"),
             find: Some(String::from("*document*.txt")),
+            regex: false,
+            fuzzy: false,
+            metric: None,
+            match_path: false,
+            query: None,
+            strict: false,
+            socket: None,
+            no_daemon: false,
+            output: args::OutputArgs::default(),
+            filters: args::FilterArgs::default(),
+            command: None,
         };
         let command = Command::from(&config);
-        assert!(matches!(command, Command::Find));
+        assert!(matches!(command, Command::Find { .. }));
         config.find = None;
         let command = Command::from(&config);
-        assert!(matches!(command, Command::Show));
+        assert!(matches!(command, Command::Show(_)));
+        config.command = Some(Commands::Verify);
+        let command = Command::from(&config);
+        assert!(matches!(command, Command::Verify));
+    }
+
+    #[test]
+    fn parse_manifest_options() {
+        use clap::Parser;
+        let config = Config::try_parse_from([
+            "diffrust", "/tmp", "manifest", "out", "--style", "bsd", "--format", "json",
+        ])
+        .unwrap();
+        assert_eq!(Some(args::OutputFormat::Json), config.output.format);
+        let command = Command::from(&config);
+        assert!(matches!(
+            command,
+            Command::Manifest {
+                format: ManifestFormat::Bsd,
+                ..
+            }
+        ));
     }
 
     #[test]
-    fn substring_matching() {
-        let content = vec![
-            ContentType::ContentFile(model::File {
-                path: PathBuf::from("/tmp/a/abc.txt"),
-                md5: md5::compute(b"abc"),
-            }),
-            ContentType::ContentDir(model::Dir {
-                path: PathBuf::from("Documents/books/"),
-                content: vec![],
-            }),
-            ContentType::ContentFile(model::File {
-                path: PathBuf::from("~/Abbey.jpg"),
-                md5: md5::compute(b"123"),
-            }),
-            ContentType::ContentFile(model::File {
-                path: PathBuf::from("lab/test/result.csv"),
-                md5: md5::compute(b"lab"),
-            }),
-            ContentType::ContentFile(model::File {
-                path: PathBuf::from("~/a/b.txt"),
-                md5: md5::compute(b"version=0.1\n"),
-            }),
-            ContentType::ContentDir(model::Dir {
-                path: PathBuf::from("abracadabra.abb"),
-                content: vec![],
-            }),
-        ];
-        let pattern = "ab";
-        let matched = _substring_match_content(&content, pattern);
-        // check number of matched items
-        assert_eq!(
-            matched.len(),
-            content.len(),
-            "Returned length don't match input length"
-        );
-        // check an exact-case match
-        assert_eq!(
-            matched[0],
-            Some(Range { start: 6, end: 9 }),
-            "Match not found"
-        );
-        // check a mismatch
-        assert!(matches!(matched[1], None), "Found wrong matching");
-        // check a mixed-case match
-        assert_eq!(
-            matched[2],
-            Some(Range { start: 2, end: 3 }),
-            "Match with mixed case not found",
-        );
-        // check match not in name part of the path
-        assert!(
-            matches!(matched[3], None),
-            "Found match in prefix not in name"
-        );
-        // check match that crosses path parts
-        assert!(
-            matches!(matched[4], None),
-            "Found wrong match crossing path parts"
-        );
-        // Check multiple matches (expect the first)
-        assert_eq!(
-            matched[5],
-            Some(Range { start: 0, end: 1 }),
-            "Found wrong match in multiple"
-        )
+    fn open_enclosing_collection() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(tempdir.path()).unwrap();
+        fs::create_dir_all(root.join("a").join("b")).unwrap();
+        let ignore = vec![String::from("*.tmp")];
+        let config = init(&root, Some("photos"), HashAlgorithm::Sha256, &ignore).unwrap();
+        assert!(matches!(
+            init(&root, None, HashAlgorithm::Md5, &[]),
+            Err(Error::AlreadyInitialized(path)) if path == config
+        ));
+
+        let collection = open_collection(&root.join("a").join("b")).unwrap();
+        assert_eq!(collection.name, "photos");
+        assert_eq!(collection.hash, Some(HashAlgorithm::Sha256));
+        assert_eq!(collection.root, root.join("a").join("b"));
+        assert_eq!(collection.enclosing, Some(root.clone()));
+        assert!(!collection.filter.accepts(&root.join("a").join("x.tmp")));
+
+        let collection = open_collection(&root).unwrap();
+        assert_eq!(collection.root, root);
+        assert!(collection.enclosing.is_none());
     }
 }
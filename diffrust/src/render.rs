@@ -0,0 +1,349 @@
+//! Output of command results.
+//!
+//! Commands hand their results to a [`Renderer`], which decides how to
+//! present them: [`TextRenderer`] prints human readable text (optionally
+//! colored, with or without emoji) and [`JsonRenderer`] prints the JSON
+//! documents described in the `json` module.
+
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::args::{ColorChoice, OutputArgs, OutputFormat, ProgressMode};
+use crate::core::diff::Change;
+use crate::core::dups::DuplicateGroup;
+use crate::core::manifest::{CheckStatus, ManifestEntry};
+use crate::core::model::{Collection, ContentType, Dir, File, ScanError, SortKey};
+use crate::core::mtree::MtreeStatus;
+use crate::core::progress::ScanObserver;
+use crate::core::registry::Registry;
+use crate::core::search::Found;
+use crate::core::stats::Stats;
+use crate::core::usage::DirUsage;
+use crate::core::verify::VerifyStatus;
+use crate::core::watch::WatchEvent;
+use crate::json;
+
+pub mod progress;
+pub mod table;
+pub mod text;
+
+pub use progress::{ProgressBar, ProgressStream};
+pub use text::TextRenderer;
+
+/// How much a text renderer prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Verbosity {
+    /// Results and problems only, without banner and summaries
+    Quiet,
+    /// One plain line per result, without banner and summaries
+    Brief,
+    /// Everything, with decorations
+    #[default]
+    Normal,
+}
+
+/// How `show` lays out the content of a directory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShowOptions {
+    /// Render the whole hierarchy instead of the root content only
+    pub tree: bool,
+    /// Maximum depth of the tree, 1 being the content of the root
+    pub depth: Option<usize>,
+    pub sort: SortKey,
+    pub reverse: bool,
+    /// List directories before files and links
+    pub dirs_first: bool,
+}
+
+impl Default for ShowOptions {
+    fn default() -> Self {
+        ShowOptions {
+            tree: false,
+            depth: None,
+            sort: SortKey::default(),
+            reverse: false,
+            dirs_first: true,
+        }
+    }
+}
+
+impl ShowOptions {
+    /// Content of dir in the order given by these options
+    pub fn sorted<'a>(&self, dir: &'a Dir) -> Vec<&'a ContentType> {
+        let mut content = dir.sorted_content(self.sort, self.dirs_first);
+        if self.reverse {
+            content.reverse();
+            if self.dirs_first {
+                // keep directories first, reversing within each group
+                content.sort_by_key(|c| !matches!(c, ContentType::ContentDir(_)));
+            }
+        }
+        content
+    }
+}
+
+/// Presentation of the results of every command
+pub trait Renderer {
+    fn print_welcome(&mut self) -> io::Result<()>;
+    fn print_collection_info(&mut self, collection: &Collection) -> io::Result<()>;
+    /// Prints an informational message (e.g. where a file was written)
+    fn print_message(&mut self, message: &str) -> io::Result<()>;
+    fn print_content(&mut self, root: &Path, dir: &Dir, options: &ShowOptions) -> io::Result<()>;
+    /// Lists the entries found, highlighting the matched parts
+    fn print_find_matches(&mut self, root: &Path, matches: &[Found]) -> io::Result<()>;
+    fn print_duplicates(&mut self, root: &Path, groups: &[DuplicateGroup]) -> io::Result<()>;
+    fn print_changes(&mut self, root: &Path, other: &Path, changes: &[Change]) -> io::Result<()>;
+    fn print_verify_report(&mut self, report: &[(&File, VerifyStatus)]) -> io::Result<()>;
+    fn print_check_report(
+        &mut self,
+        report: &[(&ManifestEntry, CheckStatus)],
+        malformed: usize,
+    ) -> io::Result<()>;
+    fn print_mtree_report(&mut self, report: &[(PathBuf, MtreeStatus)]) -> io::Result<()>;
+    fn print_usage(&mut self, root: &Path, dirs: &[&DirUsage], files: &[&File]) -> io::Result<()>;
+    fn print_stats(&mut self, root: &Path, stats: &Stats) -> io::Result<()>;
+    /// Lists the named collections of the registry
+    fn print_collections(&mut self, registry: &Registry) -> io::Result<()>;
+    /// Reports the entries left out of a scan
+    fn print_scan_errors(&mut self, skipped: &[ScanError]) -> io::Result<()>;
+    /// Reports a change applied by watch, entry is the indexed entry
+    /// after the change (None if it was deleted)
+    fn print_watch_event(
+        &mut self,
+        root: &Path,
+        event: &WatchEvent,
+        entry: Option<&ContentType>,
+    ) -> io::Result<()>;
+}
+
+/// Builds the renderer selected by the command line options, writing
+/// to standard output
+pub fn from_args(args: &OutputArgs) -> Box<dyn Renderer> {
+    colored::control::set_override(use_color(args.color, io::stdout().is_terminal()));
+    with_writer(args, io::stdout())
+}
+
+/// Builds the renderer selected by the command line options, writing
+/// to out. Colors are decided by `from_args`.
+pub fn with_writer<W: Write + 'static>(args: &OutputArgs, out: W) -> Box<dyn Renderer> {
+    let verbosity = if args.quiet {
+        Verbosity::Quiet
+    } else if args.brief {
+        Verbosity::Brief
+    } else {
+        Verbosity::Normal
+    };
+    let text = TextRenderer::new(out, verbosity)
+        .ascii(args.ascii)
+        .exact_sizes(args.bytes);
+    match args.format.unwrap_or_default() {
+        OutputFormat::Text => Box::new(text),
+        format => Box::new(JsonRenderer::new(text, format)),
+    }
+}
+
+/// Builds the observer reporting scan progress on standard error
+pub fn progress_from_args(args: &OutputArgs) -> Box<dyn ScanObserver> {
+    let bar = match args.progress {
+        ProgressMode::Auto => io::stderr().is_terminal() && !args.quiet && !args.brief,
+        ProgressMode::Bar => true,
+        ProgressMode::Json => return Box::new(ProgressStream::new(io::stderr())),
+        ProgressMode::None => false,
+    };
+    match bar {
+        true => Box::new(ProgressBar::new(io::stderr(), args.ascii)),
+        false => Box::new(()),
+    }
+}
+
+/// Decides whether to color output. In auto mode colors are used only
+/// on terminals and when `NO_COLOR` is not set (see no-color.org).
+pub fn use_color(choice: ColorChoice, is_terminal: bool) -> bool {
+    match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            is_terminal && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        }
+    }
+}
+
+/// Writes to the inner writer without the ANSI escape sequences of
+/// colors, for output that is never colored (e.g. a log file) whatever
+/// `from_args` decided for standard output
+pub struct Uncolored<W: Write> {
+    inner: W,
+    /// Bytes of an escape sequence read so far (a sequence may be split
+    /// across writes)
+    escape: usize,
+}
+
+impl<W: Write> Uncolored<W> {
+    pub fn new(inner: W) -> Self {
+        Uncolored { inner, escape: 0 }
+    }
+}
+
+impl<W: Write> Write for Uncolored<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut plain = Vec::with_capacity(buf.len());
+        for &b in buf {
+            self.escape = match (self.escape, b) {
+                (0, 0x1b) => 1,
+                (0, _) => {
+                    plain.push(b);
+                    0
+                }
+                (1, b'[') => 2,
+                // the final byte of a control sequence
+                (1, _) | (2.., 0x40..=0x7e) => 0,
+                (n, _) => n + 1,
+            };
+        }
+        self.inner.write_all(&plain)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Formats a size with binary units (e.g. `1.5 KiB`)
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{value:.1} {}", UNITS[unit]),
+    }
+}
+
+/// Prints JSON documents for the commands covered by the JSON schema,
+/// other commands fall back to plain text.
+pub struct JsonRenderer<W: Write> {
+    text: TextRenderer<W>,
+    format: OutputFormat,
+}
+
+impl<W: Write> JsonRenderer<W> {
+    pub fn new(text: TextRenderer<W>, format: OutputFormat) -> Self {
+        JsonRenderer { text, format }
+    }
+}
+
+impl<W: Write> Renderer for JsonRenderer<W> {
+    fn print_welcome(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn print_collection_info(&mut self, _collection: &Collection) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn print_message(&mut self, _message: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn print_content(&mut self, root: &Path, dir: &Dir, _options: &ShowOptions) -> io::Result<()> {
+        write!(self.text.out, "{}", json::show(root, dir, self.format))
+    }
+
+    fn print_find_matches(&mut self, root: &Path, matches: &[Found]) -> io::Result<()> {
+        write!(self.text.out, "{}", json::find(root, matches, self.format))
+    }
+
+    fn print_duplicates(&mut self, root: &Path, groups: &[DuplicateGroup]) -> io::Result<()> {
+        write!(self.text.out, "{}", json::dups(root, groups, self.format))
+    }
+
+    fn print_changes(&mut self, root: &Path, other: &Path, changes: &[Change]) -> io::Result<()> {
+        write!(
+            self.text.out,
+            "{}",
+            json::diff(root, other, changes, self.format)
+        )
+    }
+
+    fn print_verify_report(&mut self, report: &[(&File, VerifyStatus)]) -> io::Result<()> {
+        self.text.print_verify_report(report)
+    }
+
+    fn print_check_report(
+        &mut self,
+        report: &[(&ManifestEntry, CheckStatus)],
+        malformed: usize,
+    ) -> io::Result<()> {
+        self.text.print_check_report(report, malformed)
+    }
+
+    fn print_mtree_report(&mut self, report: &[(PathBuf, MtreeStatus)]) -> io::Result<()> {
+        self.text.print_mtree_report(report)
+    }
+
+    fn print_usage(&mut self, root: &Path, dirs: &[&DirUsage], files: &[&File]) -> io::Result<()> {
+        write!(
+            self.text.out,
+            "{}",
+            json::du(root, dirs, files, self.format)
+        )
+    }
+
+    fn print_stats(&mut self, root: &Path, stats: &Stats) -> io::Result<()> {
+        write!(self.text.out, "{}", json::stats(root, stats, self.format))
+    }
+
+    fn print_collections(&mut self, registry: &Registry) -> io::Result<()> {
+        self.text.print_collections(registry)
+    }
+
+    fn print_scan_errors(&mut self, skipped: &[ScanError]) -> io::Result<()> {
+        // keeps standard output a valid document
+        TextRenderer::new(io::stderr(), Verbosity::Quiet).print_scan_errors(skipped)
+    }
+
+    fn print_watch_event(
+        &mut self,
+        root: &Path,
+        event: &WatchEvent,
+        entry: Option<&ContentType>,
+    ) -> io::Result<()> {
+        // events are a stream, one record per line whatever the format
+        writeln!(self.text.out, "{}", json::watch_event(root, event, entry))?;
+        self.text.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!("0 B", human_size(0));
+        assert_eq!("1023 B", human_size(1023));
+        assert_eq!("1.0 KiB", human_size(1024));
+        assert_eq!("1.5 MiB", human_size(3 * 512 * 1024));
+        assert_eq!("2.0 GiB", human_size(2 << 30));
+    }
+
+    #[test]
+    fn color_choice() {
+        assert!(use_color(ColorChoice::Always, false));
+        assert!(!use_color(ColorChoice::Never, true));
+        assert!(!use_color(ColorChoice::Auto, false));
+    }
+
+    #[test]
+    fn uncolored_output() {
+        let mut out = Uncolored::new(vec![]);
+        out.write_all(b"\x1b[1;3").unwrap();
+        out.write_all(b"1mFAILED\x1b[0m a\x1b[32m+\x1b[0m").unwrap();
+        assert_eq!(b"FAILED a+".to_vec(), out.inner);
+    }
+}
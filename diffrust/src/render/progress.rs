@@ -0,0 +1,325 @@
+//! Scan progress: a terminal progress bar and a machine readable stream.
+//!
+//! The stream (`--progress json`) writes one JSON object per line to
+//! standard error:
+//!
+//! ```text
+//! {"type": "start", "root", "files", "bytes"}      totals to be scanned
+//! {"type": "progress", "dirs", "files", "bytes", "total_files",
+//!  "total_bytes", "elapsed", "rate", "eta", "current"}
+//! {"type": "error", "path", "message"}            an entry was skipped
+//! {"type": "finish", "dirs", "files", "bytes", "errors", "elapsed"}
+//! ```
+//!
+//! `elapsed` and `eta` are seconds (`eta` is null until it can be
+//! estimated), `rate` is in bytes per second and `current` is the
+//! directory being scanned. Progress records are emitted at most every
+//! 100 milliseconds, every record carries `"schema_version": 1`. Paths
+//! follow the conventions of the `json` module (`_hex` fields).
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde_json::{Map, Value};
+
+use super::human_size;
+use crate::core::filter::Filter;
+use crate::core::model::{File, ScanError};
+use crate::core::progress::{self, ScanObserver, ScanTotals};
+use crate::json::{insert_name, SCHEMA_VERSION};
+
+/// Minimum time between two updates
+const INTERVAL: Duration = Duration::from_millis(100);
+/// Width of the bar, in characters
+const BAR_WIDTH: usize = 30;
+
+/// Counters shared by the progress observers
+struct Progress {
+    totals: ScanTotals,
+    dirs: usize,
+    files: usize,
+    bytes: u64,
+    errors: usize,
+    current: PathBuf,
+    start: Instant,
+    last_update: Option<Instant>,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Progress {
+            totals: ScanTotals::default(),
+            dirs: 0,
+            files: 0,
+            bytes: 0,
+            errors: 0,
+            current: PathBuf::new(),
+            start: Instant::now(),
+            last_update: None,
+        }
+    }
+
+    fn start(&mut self, root: &Path, filter: &Filter) {
+        self.totals = progress::measure(root, filter);
+        self.start = Instant::now();
+    }
+
+    /// Whether enough time passed since the last update
+    fn due(&mut self) -> bool {
+        let now = Instant::now();
+        if self
+            .last_update
+            .is_some_and(|last| now.duration_since(last) < INTERVAL)
+        {
+            return false;
+        }
+        self.last_update = Some(now);
+        true
+    }
+
+    /// Bytes hashed per second
+    fn rate(&self) -> f64 {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.bytes as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    /// Estimated seconds to completion
+    fn eta(&self) -> Option<u64> {
+        let rate = self.rate();
+        if rate <= 0.0 || self.bytes > self.totals.bytes {
+            return None;
+        }
+        Some(((self.totals.bytes - self.bytes) as f64 / rate).ceil() as u64)
+    }
+}
+
+/// Draws a progress bar on a terminal, with throughput and ETA
+pub struct ProgressBar<W: Write> {
+    out: W,
+    ascii: bool,
+    progress: Progress,
+}
+
+impl<W: Write> ProgressBar<W> {
+    pub fn new(out: W, ascii: bool) -> Self {
+        ProgressBar {
+            out,
+            ascii,
+            progress: Progress::new(),
+        }
+    }
+
+    fn line(&self) -> String {
+        let p = &self.progress;
+        let fraction = match p.totals.bytes {
+            0 => 0.0,
+            total => (p.bytes as f64 / total as f64).min(1.0),
+        };
+        let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+        let (full, empty) = if self.ascii {
+            ("#", "-")
+        } else {
+            ("█", "░")
+        };
+        let eta = p.eta().map(format_duration).unwrap_or_else(|| "?".into());
+        format!(
+            "{:3.0}% {}{} {}/{} {}/s ETA {} {}/{} files",
+            fraction * 100.0,
+            full.repeat(filled),
+            empty.repeat(BAR_WIDTH - filled),
+            human_size(p.bytes),
+            human_size(p.totals.bytes),
+            human_size(p.rate() as u64),
+            eta,
+            p.files,
+            p.totals.files
+        )
+    }
+
+    fn draw(&mut self) {
+        // progress is best effort, a broken terminal must not stop a scan
+        let line = self.line();
+        let _ = write!(self.out, "\r\x1b[2K{line}");
+        let _ = self.out.flush();
+    }
+}
+
+impl<W: Write> ScanObserver for ProgressBar<W> {
+    fn started(&mut self, root: &Path, filter: &Filter) {
+        self.progress.start(root, filter);
+        self.draw();
+    }
+
+    fn dir_entered(&mut self, _path: &Path) {
+        self.progress.dirs += 1;
+    }
+
+    fn bytes_hashed(&mut self, n: u64) {
+        self.progress.bytes += n;
+        if self.progress.due() {
+            self.draw();
+        }
+    }
+
+    fn file_hashed(&mut self, _file: &File) {
+        self.progress.files += 1;
+        if self.progress.due() {
+            self.draw();
+        }
+    }
+
+    fn error(&mut self, _error: &ScanError) {
+        self.progress.errors += 1;
+    }
+
+    fn finished(&mut self) {
+        let _ = write!(self.out, "\r\x1b[2K");
+        let _ = self.out.flush();
+    }
+}
+
+/// Writes the progress as a stream of JSON records, see module docs
+pub struct ProgressStream<W: Write> {
+    out: W,
+    progress: Progress,
+}
+
+impl<W: Write> ProgressStream<W> {
+    pub fn new(out: W) -> Self {
+        ProgressStream {
+            out,
+            progress: Progress::new(),
+        }
+    }
+
+    fn emit(&mut self, kind: &str, fields: Vec<(&str, Value)>, names: &[(&str, &Path)]) {
+        let mut record = Map::new();
+        record.insert("type".into(), kind.into());
+        record.insert("schema_version".into(), SCHEMA_VERSION.into());
+        for (key, value) in fields {
+            record.insert(key.into(), value);
+        }
+        for (key, path) in names {
+            insert_name(&mut record, key, path.as_os_str());
+        }
+        let _ = writeln!(self.out, "{}", Value::Object(record));
+        let _ = self.out.flush();
+    }
+
+    fn emit_progress(&mut self) {
+        let p = &self.progress;
+        let fields = vec![
+            ("dirs", p.dirs.into()),
+            ("files", p.files.into()),
+            ("bytes", p.bytes.into()),
+            ("total_files", p.totals.files.into()),
+            ("total_bytes", p.totals.bytes.into()),
+            ("elapsed", p.start.elapsed().as_secs_f64().into()),
+            ("rate", p.rate().into()),
+            ("eta", p.eta().into()),
+        ];
+        let current = p.current.clone();
+        self.emit("progress", fields, &[("current", &current)]);
+    }
+}
+
+impl<W: Write> ScanObserver for ProgressStream<W> {
+    fn started(&mut self, root: &Path, filter: &Filter) {
+        self.progress.start(root, filter);
+        let totals = self.progress.totals;
+        let fields = vec![
+            ("files", totals.files.into()),
+            ("bytes", totals.bytes.into()),
+        ];
+        self.emit("start", fields, &[("root", root)]);
+    }
+
+    fn dir_entered(&mut self, path: &Path) {
+        self.progress.dirs += 1;
+        self.progress.current = path.to_path_buf();
+    }
+
+    fn bytes_hashed(&mut self, n: u64) {
+        self.progress.bytes += n;
+        if self.progress.due() {
+            self.emit_progress();
+        }
+    }
+
+    fn file_hashed(&mut self, _file: &File) {
+        self.progress.files += 1;
+        if self.progress.due() {
+            self.emit_progress();
+        }
+    }
+
+    fn error(&mut self, error: &ScanError) {
+        self.progress.errors += 1;
+        let fields = vec![("message", error.error.to_string().into())];
+        self.emit("error", fields, &[("path", &error.path)]);
+    }
+
+    fn finished(&mut self) {
+        let p = &self.progress;
+        let fields = vec![
+            ("dirs", p.dirs.into()),
+            ("files", p.files.into()),
+            ("bytes", p.bytes.into()),
+            ("errors", p.errors.into()),
+            ("elapsed", p.start.elapsed().as_secs_f64().into()),
+        ];
+        self.emit("finish", fields, &[]);
+    }
+}
+
+/// Formats seconds as `M:SS` or `H:MM:SS`
+fn format_duration(seconds: u64) -> String {
+    let (h, m, s) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if h > 0 {
+        format!("{h}:{m:02}:{s:02}")
+    } else {
+        format!("{m}:{s:02}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::core::model::Collection;
+
+    #[test]
+    fn durations() {
+        assert_eq!("0:07", format_duration(7));
+        assert_eq!("2:05", format_duration(125));
+        assert_eq!("1:00:01", format_duration(3601));
+    }
+
+    #[test]
+    fn stream_records() {
+        let tempdir = tempdir().unwrap();
+        fs::write(tempdir.path().join("a"), b"abc").unwrap();
+        let mut stream = ProgressStream::new(Vec::new());
+        let mut collection = Collection::from(tempdir.path());
+        collection.scan_with(&mut stream).unwrap();
+        let records: Vec<Value> = String::from_utf8(stream.out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!("start", records[0]["type"]);
+        assert_eq!(3, records[0]["bytes"]);
+        let last = records.last().unwrap();
+        assert_eq!("finish", last["type"]);
+        assert_eq!(1, last["files"]);
+        assert_eq!(0, last["errors"]);
+    }
+}
@@ -0,0 +1,156 @@
+use colored::{Color, Colorize};
+use unicode_width::UnicodeWidthStr;
+
+/// Alignment of a table column
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+}
+
+/// A table cell, colors are applied after padding so that escape codes
+/// do not break the alignment
+#[derive(Debug, Default)]
+pub struct Cell {
+    /// Uncolored text printed before the cell text (e.g. tree branches)
+    prefix: String,
+    text: String,
+    color: Option<Color>,
+    bold: bool,
+}
+
+impl Cell {
+    pub fn new(text: impl Into<String>) -> Self {
+        Cell {
+            text: text.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn color(mut self, color: Color) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    pub fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn width(&self) -> usize {
+        self.prefix.width() + self.text.width()
+    }
+
+    fn paint(&self, text: &str) -> String {
+        let mut s = match self.color {
+            Some(c) => text.color(c),
+            None => text.normal(),
+        };
+        if self.bold {
+            s = s.bold();
+        }
+        format!("{}{}", self.prefix, s)
+    }
+}
+
+/// Column aligned rows of text
+#[derive(Debug)]
+pub struct Table {
+    align: Vec<Align>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    /// Creates a table whose columns are aligned as given
+    pub fn new(align: &[Align]) -> Self {
+        Table {
+            align: align.to_vec(),
+            rows: vec![],
+        }
+    }
+
+    pub fn push(&mut self, row: Vec<Cell>) {
+        self.rows.push(row);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Renders the rows, columns are separated by two spaces and a left
+    /// aligned last column is not padded
+    pub fn render(&self) -> Vec<String> {
+        let columns = self.rows.iter().map(Vec::len).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|i| {
+                self.rows
+                    .iter()
+                    .filter_map(|r| r.get(i))
+                    .map(Cell::width)
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        self.rows
+            .iter()
+            .map(|row| {
+                let cells: Vec<String> = row
+                    .iter()
+                    .enumerate()
+                    .map(|(i, cell)| {
+                        let fill = " ".repeat(widths[i] - cell.width());
+                        let align = self.align.get(i).copied().unwrap_or(Align::Left);
+                        match align {
+                            Align::Right => format!("{fill}{}", cell.paint(&cell.text)),
+                            Align::Left if i + 1 == row.len() => cell.paint(&cell.text),
+                            Align::Left => format!("{}{fill}", cell.paint(&cell.text)),
+                        }
+                    })
+                    .collect();
+                cells.join("  ")
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_aligned() {
+        colored::control::set_override(false);
+        let mut table = Table::new(&[Align::Left, Align::Right, Align::Left]);
+        table.push(vec![Cell::new("a"), Cell::new("1 B"), Cell::new("x")]);
+        table.push(vec![
+            Cell::new("long name").color(Color::Red),
+            Cell::new("10 KiB"),
+            Cell::new("y"),
+        ]);
+        assert_eq!(
+            vec!["a             1 B  x", "long name  10 KiB  y"],
+            table.render()
+        );
+    }
+
+    #[test]
+    fn prefix_counts_in_width() {
+        let mut table = Table::new(&[Align::Left, Align::Left]);
+        table.push(vec![Cell::new("a").prefix("├── "), Cell::new("x")]);
+        table.push(vec![Cell::new("abc"), Cell::new("y")]);
+        assert_eq!(vec!["├── a  x", "abc    y"], table.render());
+    }
+
+    #[test]
+    fn wide_characters() {
+        let mut table = Table::new(&[Align::Left, Align::Left]);
+        table.push(vec![Cell::new("日本"), Cell::new("a")]);
+        table.push(vec![Cell::new("abcde"), Cell::new("b")]);
+        assert_eq!(vec!["日本   a", "abcde  b"], table.render());
+    }
+}
@@ -0,0 +1,981 @@
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use colored::{Color, Colorize};
+
+use super::table::{Align, Cell, Table};
+use super::{human_size, Renderer, ShowOptions, Verbosity};
+use crate::core::diff::Change;
+use crate::core::dups::DuplicateGroup;
+use crate::core::manifest::{CheckStatus, ManifestEntry};
+use crate::core::model::{Collection, ContentType, Dir, File, ScanError, DB_FILE_NAME};
+use crate::core::mtree::MtreeStatus;
+use crate::core::name;
+use crate::core::registry::Registry;
+use crate::core::search::{Found, Highlight};
+use crate::core::stats::{Bucket, Group, Stats};
+use crate::core::usage::DirUsage;
+use crate::core::verify::VerifyStatus;
+use crate::core::watch::WatchEvent;
+
+/// Kinds of entries that get an icon
+enum Icon {
+    Dir,
+    File,
+    Link,
+}
+
+/// Number of groups listed by the stats report, the remaining ones are
+/// summarized in a single line
+const MAX_GROUPS: usize = 10;
+
+/// Prints human readable text
+pub struct TextRenderer<W: Write> {
+    pub(crate) out: W,
+    verbosity: Verbosity,
+    ascii: bool,
+    exact_sizes: bool,
+}
+
+impl<W: Write> TextRenderer<W> {
+    pub fn new(out: W, verbosity: Verbosity) -> Self {
+        TextRenderer {
+            out,
+            verbosity,
+            ascii: false,
+            exact_sizes: false,
+        }
+    }
+
+    /// Only uses ASCII characters, replacing emoji and arrows
+    pub fn ascii(mut self, ascii: bool) -> Self {
+        self.ascii = ascii;
+        self
+    }
+
+    /// Prints sizes in bytes rather than in human readable units
+    pub fn exact_sizes(mut self, exact: bool) -> Self {
+        self.exact_sizes = exact;
+        self
+    }
+
+    /// Returns the writer, mostly useful for testing
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn normal(&self) -> bool {
+        self.verbosity == Verbosity::Normal
+    }
+
+    fn icon(&self, icon: Icon) -> &'static str {
+        match (icon, self.ascii) {
+            (Icon::Dir, false) => "📁",
+            (Icon::File, false) => "🗄 ",
+            (Icon::Link, false) => "🔗",
+            (Icon::Dir, true) => "d",
+            (Icon::File, true) => "f",
+            (Icon::Link, true) => "l",
+        }
+    }
+
+    fn arrow(&self) -> &'static str {
+        if self.ascii {
+            "->"
+        } else {
+            "→"
+        }
+    }
+
+    fn size(&self, bytes: u64) -> String {
+        if self.exact_sizes {
+            bytes.to_string()
+        } else {
+            human_size(bytes)
+        }
+    }
+
+    /// Prints the paths of the content of dir, relative to root, down to
+    /// the depth allowed by options
+    fn print_paths(
+        &mut self,
+        root: &Path,
+        dir: &Dir,
+        options: &ShowOptions,
+        depth: usize,
+    ) -> io::Result<()> {
+        for item in options.sorted(dir) {
+            match item {
+                ContentType::ContentDir(d) => {
+                    let path = if options.tree {
+                        relative(root, &d.path)
+                    } else {
+                        name_of(&d.path)
+                    };
+                    writeln!(self.out, "{path}/")?;
+                    if options.tree && options.depth.is_none_or(|max| depth < max) {
+                        self.print_paths(root, d, options, depth + 1)?;
+                    }
+                }
+                _ if options.tree => writeln!(self.out, "{}", relative(root, item.path()))?,
+                _ => writeln!(self.out, "{}", name_of(item.path()))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the rows of the content of dir, with tree branches
+    fn tree_rows(
+        &self,
+        dir: &Dir,
+        options: &ShowOptions,
+        prefix: &str,
+        depth: usize,
+        table: &mut Table,
+    ) {
+        let content = options.sorted(dir);
+        for (i, item) in content.iter().enumerate() {
+            let last = i + 1 == content.len();
+            let (branch, indent) = match (self.ascii, last) {
+                (false, false) => ("├── ", "│   "),
+                (false, true) => ("└── ", "    "),
+                (true, false) => ("|-- ", "|   "),
+                (true, true) => ("`-- ", "    "),
+            };
+            table.push(self.content_row(item, &format!("{prefix}{branch}")));
+            if let ContentType::ContentDir(d) = item {
+                if options.depth.is_none_or(|max| depth < max) {
+                    self.tree_rows(d, options, &format!("{prefix}{indent}"), depth + 1, table);
+                }
+            }
+        }
+    }
+
+    /// A table row describing content: name, file count, size,
+    /// modification time and digest
+    fn content_row(&self, item: &ContentType, prefix: &str) -> Vec<Cell> {
+        match item {
+            ContentType::ContentDir(d) => {
+                let name = Cell::new(name_of(&d.path))
+                    .color(Color::Blue)
+                    .bold()
+                    .prefix(format!("{prefix}{} ", self.icon(Icon::Dir)));
+                self.dir_row(d, name)
+            }
+            ContentType::ContentFile(f) => vec![
+                Cell::new(name_of(&f.path)).prefix(format!("{prefix}{} ", self.icon(Icon::File))),
+                Cell::new(""),
+                Cell::new(self.size(f.size)),
+                Cell::new(local_time(f.modified)),
+                Cell::new(format!("{:x}", f.md5)).color(Color::BrightBlack),
+            ],
+            ContentType::ContentLink(l) => vec![
+                Cell::new(format!(
+                    "{} {} {}",
+                    name_of(&l.path),
+                    self.arrow(),
+                    name::escape_path(&l.target)
+                ))
+                .color(Color::Cyan)
+                .prefix(format!("{prefix}{} ", self.icon(Icon::Link))),
+                Cell::new(""),
+                Cell::new(""),
+                Cell::new(local_time(l.modified)),
+            ],
+        }
+    }
+
+    fn dir_row(&self, dir: &Dir, name: Cell) -> Vec<Cell> {
+        let count = dir.file_count();
+        let files = if count == 1 { "file" } else { "files" };
+        vec![
+            name,
+            Cell::new(format!("{count} {files}")),
+            Cell::new(self.size(dir.total_size())),
+            Cell::new(local_time(dir.modified)),
+        ]
+    }
+
+    fn write_table(&mut self, table: &Table) -> io::Result<()> {
+        for line in table.render() {
+            writeln!(self.out, " {}", line)?;
+        }
+        Ok(())
+    }
+
+    fn print_groups(&mut self, title: &str, groups: &[Group], empty_key: &str) -> io::Result<()> {
+        writeln!(self.out, "\n{}", title.bold())?;
+        let mut table = Table::new(&[Align::Right, Align::Right, Align::Left]);
+        for g in groups.iter().take(MAX_GROUPS) {
+            let key = if g.key.is_empty() { empty_key } else { &g.key };
+            table.push(vec![
+                Cell::new(g.count.to_string()),
+                Cell::new(self.size(g.bytes)),
+                Cell::new(key),
+            ]);
+        }
+        if groups.len() > MAX_GROUPS {
+            let rest = &groups[MAX_GROUPS..];
+            table.push(vec![
+                Cell::new(rest.iter().map(|g| g.count).sum::<usize>().to_string()),
+                Cell::new(self.size(rest.iter().map(|g| g.bytes).sum())),
+                Cell::new(format!("({} more)", rest.len())).color(Color::BrightBlack),
+            ]);
+        }
+        self.write_table(&table)
+    }
+
+    fn print_buckets(
+        &mut self,
+        title: &str,
+        labels: Vec<String>,
+        buckets: &[Bucket],
+    ) -> io::Result<()> {
+        writeln!(self.out, "\n{}", title.bold())?;
+        let mut table = Table::new(&[Align::Left, Align::Right, Align::Right]);
+        for (label, b) in labels.into_iter().zip(buckets) {
+            table.push(vec![
+                Cell::new(label),
+                Cell::new(b.count.to_string()),
+                Cell::new(self.size(b.bytes)),
+            ]);
+        }
+        self.write_table(&table)
+    }
+
+    fn size_label(&self, bucket: &Bucket, buckets: &[Bucket]) -> String {
+        match bucket.upper {
+            Some(1) => String::from("empty"),
+            Some(upper) => format!("< {}", self.size(upper)),
+            None => format!(">= {}", self.size(last_bound(buckets))),
+        }
+    }
+}
+
+fn name_of(path: &Path) -> String {
+    path.file_name().map(name::escape).unwrap_or_default()
+}
+
+fn relative(root: &Path, path: &Path) -> String {
+    match path.strip_prefix(root) {
+        Ok(p) if p.as_os_str().is_empty() => String::from("."),
+        Ok(p) => name::escape_path(p),
+        Err(_) => name::escape_path(path),
+    }
+}
+
+/// Colors of the highlighted matches, red for whole matches and then
+/// one per capture group
+const GROUP_COLORS: [Color; 6] = [
+    Color::Red,
+    Color::Yellow,
+    Color::Green,
+    Color::Cyan,
+    Color::Magenta,
+    Color::Blue,
+];
+
+/// Colors the highlighted parts of text
+fn highlight(text: &str, highlights: &[Highlight]) -> String {
+    segments(text, highlights)
+        .into_iter()
+        .map(|(range, group)| match group {
+            Some(g) => text[range]
+                .color(GROUP_COLORS[g % GROUP_COLORS.len()])
+                .to_string(),
+            None => text[range].to_string(),
+        })
+        .collect()
+}
+
+/// Splits text at the bounds of the highlights, each part with the
+/// group of the innermost highlight covering it
+fn segments(text: &str, highlights: &[Highlight]) -> Vec<(Range<usize>, Option<usize>)> {
+    let mut bounds: Vec<usize> = highlights
+        .iter()
+        .flat_map(|h| [h.range.start, h.range.end])
+        .filter(|&i| i < text.len() && text.is_char_boundary(i))
+        .collect();
+    bounds.extend([0, text.len()]);
+    bounds.sort_unstable();
+    bounds.dedup();
+    bounds
+        .windows(2)
+        .map(|w| {
+            let group = highlights
+                .iter()
+                .filter(|h| h.range.start <= w[0] && w[1] <= h.range.end)
+                .min_by_key(|h| (h.range.len(), usize::MAX - h.group))
+                .map(|h| h.group);
+            (w[0]..w[1], group)
+        })
+        .collect()
+}
+
+fn local_time(time: SystemTime) -> String {
+    let local: DateTime<Local> = time.into();
+    local.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+impl<W: Write> Renderer for TextRenderer<W> {
+    fn print_welcome(&mut self) -> io::Result<()> {
+        if !self.normal() {
+            return Ok(());
+        }
+        let (sync, dir) = if self.ascii {
+            ("", "")
+        } else {
+            (" 🔄", " 📁")
+        };
+        writeln!(
+            self.out,
+            "\nWelcome to {} compare{sync} file system directories{dir}",
+            "DIFFRUST".cyan().bold()
+        )
+    }
+
+    fn print_collection_info(&mut self, collection: &Collection) -> io::Result<()> {
+        if !self.normal() {
+            return Ok(());
+        }
+        match collection.name.as_str() {
+            "" => writeln!(
+                self.out,
+                "Unnamed collection opened at {}",
+                name::escape_path(&collection.root)
+            ),
+            name => writeln!(
+                self.out,
+                "Opened {} collection at {}",
+                name,
+                name::escape_path(&collection.root)
+            ),
+        }
+    }
+
+    fn print_message(&mut self, message: &str) -> io::Result<()> {
+        if self.verbosity == Verbosity::Quiet {
+            return Ok(());
+        }
+        writeln!(self.out, "{message}")
+    }
+
+    fn print_content(&mut self, root: &Path, dir: &Dir, options: &ShowOptions) -> io::Result<()> {
+        if !self.normal() {
+            return self.print_paths(root, dir, options, 1);
+        }
+        let mut table = Table::new(&[
+            Align::Left,
+            Align::Right,
+            Align::Right,
+            Align::Left,
+            Align::Left,
+        ]);
+        if options.tree {
+            let root_name = Cell::new(name::escape_path(&dir.path))
+                .color(Color::Blue)
+                .bold();
+            table.push(self.dir_row(dir, root_name));
+            self.tree_rows(dir, options, "", 1, &mut table);
+        } else {
+            for item in options.sorted(dir) {
+                table.push(self.content_row(item, ""));
+            }
+        }
+        writeln!(self.out)?;
+        self.write_table(&table)?;
+
+        // summary of the listed content counting unique md5's
+        let (dirs, files) = match options.tree {
+            true => (dir.dir_count(), dir.all_files()),
+            false => (dir.sorted_dirs().len(), dir.sorted_files()),
+        };
+        let md5_set: HashSet<&md5::Digest> = files.iter().map(|f| &f.md5).collect();
+        writeln!(self.out, "\n{} total directories", dirs)?;
+        writeln!(
+            self.out,
+            "{} total files ({} unique)\n",
+            files.len(),
+            md5_set.len()
+        )?;
+        writeln!(self.out, "Last modified: {}", local_time(dir.modified))
+    }
+
+    fn print_find_matches(&mut self, root: &Path, matches: &[Found]) -> io::Result<()> {
+        for m in matches {
+            let (icon, path) = match m.entry {
+                ContentType::ContentDir(d) => (Icon::Dir, &d.path),
+                ContentType::ContentFile(f) => (Icon::File, &f.path),
+                ContentType::ContentLink(l) => (Icon::Link, &l.path),
+            };
+            if !self.normal() {
+                writeln!(self.out, "{}", relative(root, path))?;
+                continue;
+            }
+            let shown = match m.on_path {
+                true => relative(root, path),
+                false => name_of(path),
+            };
+            // highlights are ranges of the unescaped text
+            let text = m.text(root);
+            let shown = match shown == text {
+                true => highlight(&text, &m.highlights),
+                false => shown,
+            };
+            writeln!(self.out, " {} {}", self.icon(icon), shown)?;
+        }
+        Ok(())
+    }
+
+    fn print_duplicates(&mut self, root: &Path, groups: &[DuplicateGroup]) -> io::Result<()> {
+        if !self.normal() {
+            for (i, group) in groups.iter().enumerate() {
+                if i > 0 {
+                    writeln!(self.out)?;
+                }
+                for file in group.files.iter() {
+                    writeln!(self.out, "{}", relative(root, &file.path))?;
+                }
+            }
+            return Ok(());
+        }
+        writeln!(self.out)?;
+        let times = if self.ascii { "x" } else { "×" };
+        let mut wasted = 0;
+        for group in groups.iter() {
+            wasted += group.wasted();
+            writeln!(
+                self.out,
+                "{:x} ({} {times} {})",
+                group.md5,
+                group.files.len(),
+                self.size(group.size)
+            )?;
+            for file in group.files.iter() {
+                let icon = self.icon(Icon::File);
+                writeln!(self.out, "\t{icon} {}", relative(root, &file.path))?;
+            }
+        }
+        writeln!(
+            self.out,
+            "\n{} duplicate groups ({} wasted)\n",
+            groups.len(),
+            self.size(wasted)
+        )
+    }
+
+    fn print_changes(&mut self, root: &Path, other: &Path, changes: &[Change]) -> io::Result<()> {
+        if self.normal() {
+            writeln!(self.out)?;
+        }
+        let arrow = self.arrow();
+        for change in changes.iter() {
+            match change {
+                Change::Added(f) => {
+                    writeln!(self.out, " {} {}", "+".green(), relative(other, &f.path))?
+                }
+                Change::Removed(f) => {
+                    writeln!(self.out, " {} {}", "-".red(), relative(root, &f.path))?
+                }
+                Change::Modified { new: f, .. } => {
+                    writeln!(self.out, " {} {}", "~".yellow(), relative(other, &f.path))?
+                }
+                Change::Renamed { from, to } => writeln!(
+                    self.out,
+                    " {} {} {arrow} {}",
+                    ">".cyan(),
+                    relative(root, &from.path),
+                    relative(other, &to.path)
+                )?,
+            }
+        }
+        if self.normal() {
+            writeln!(self.out, "\n{} changes\n", changes.len())?;
+        }
+        Ok(())
+    }
+
+    fn print_verify_report(&mut self, report: &[(&File, VerifyStatus)]) -> io::Result<()> {
+        let mut table = Table::new(&[Align::Right, Align::Left]);
+        let mut ok = 0;
+        for (file, status) in report.iter() {
+            let label = match status {
+                VerifyStatus::Ok => {
+                    ok += 1;
+                    continue;
+                }
+                VerifyStatus::Modified => Cell::new("modified").color(Color::Yellow),
+                VerifyStatus::Corrupted => Cell::new("corrupted").color(Color::Red).bold(),
+                VerifyStatus::Missing => Cell::new("missing").color(Color::Yellow),
+                VerifyStatus::Unreadable(kind) => {
+                    Cell::new(format!("unreadable ({kind})")).color(Color::Red)
+                }
+            };
+            table.push(vec![label, Cell::new(name::escape_path(&file.path))]);
+        }
+        if self.normal() {
+            writeln!(self.out)?;
+        }
+        self.write_table(&table)?;
+        if self.normal() {
+            writeln!(self.out, "\n{} files verified, {} ok\n", report.len(), ok)?;
+        }
+        Ok(())
+    }
+
+    fn print_check_report(
+        &mut self,
+        report: &[(&ManifestEntry, CheckStatus)],
+        malformed: usize,
+    ) -> io::Result<()> {
+        if self.normal() {
+            writeln!(self.out)?;
+        }
+        for (entry, status) in report.iter() {
+            let label = match status {
+                CheckStatus::Ok if self.verbosity == Verbosity::Quiet => continue,
+                CheckStatus::Ok => "OK".green(),
+                CheckStatus::Failed => "FAILED".red().bold(),
+                CheckStatus::Missing => "MISSING".yellow(),
+            };
+            writeln!(self.out, "{}: {}", name::escape_path(&entry.path), label)?;
+        }
+        if malformed > 0 {
+            writeln!(
+                self.out,
+                "\nWARNING: {malformed} line(s) improperly formatted"
+            )?;
+        }
+        if self.normal() {
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+
+    fn print_mtree_report(&mut self, report: &[(PathBuf, MtreeStatus)]) -> io::Result<()> {
+        if self.normal() {
+            writeln!(self.out)?;
+        }
+        for (path, status) in report.iter() {
+            // the root as written in specifications
+            let path = match path.as_os_str().is_empty() {
+                true => Path::new("."),
+                false => path.as_path(),
+            };
+            match status {
+                MtreeStatus::Missing => writeln!(
+                    self.out,
+                    "{}: {}",
+                    name::escape_path(path),
+                    "missing".yellow()
+                )?,
+                MtreeStatus::Extra => {
+                    writeln!(self.out, "{}: {}", name::escape_path(path), "extra".cyan())?
+                }
+                MtreeStatus::Differs(diffs) => {
+                    writeln!(self.out, "{}:", name::escape_path(path))?;
+                    for d in diffs {
+                        writeln!(
+                            self.out,
+                            "\t{} (expected {}, found {})",
+                            d.keyword.red(),
+                            d.expected,
+                            d.actual
+                        )?;
+                    }
+                }
+            }
+        }
+        if self.normal() {
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+
+    fn print_usage(&mut self, root: &Path, dirs: &[&DirUsage], files: &[&File]) -> io::Result<()> {
+        if !self.normal() {
+            // du like output, directories then files
+            for d in dirs.iter() {
+                let path = relative(root, &d.dir.path);
+                writeln!(self.out, "{}\t{path}", self.size(d.usage.size))?;
+            }
+            for f in files.iter() {
+                let path = relative(root, &f.path);
+                writeln!(self.out, "{}\t{path}", self.size(f.size))?;
+            }
+            return Ok(());
+        }
+        writeln!(self.out, "\n{}", "Largest directories".bold())?;
+        let mut table = Table::new(&[Align::Right, Align::Right, Align::Right, Align::Left]);
+        table.push(vec![
+            Cell::new("size"),
+            Cell::new("files"),
+            Cell::new("unique"),
+            Cell::new("path"),
+        ]);
+        for d in dirs.iter() {
+            let icon = self.icon(Icon::Dir);
+            table.push(vec![
+                Cell::new(self.size(d.usage.size)),
+                Cell::new(d.usage.files.to_string()),
+                Cell::new(self.size(d.usage.unique_size)),
+                Cell::new(relative(root, &d.dir.path))
+                    .color(Color::Blue)
+                    .prefix(format!("{icon} ")),
+            ]);
+        }
+        self.write_table(&table)?;
+        writeln!(self.out, "\n{}", "Largest files".bold())?;
+        let mut table = Table::new(&[Align::Right, Align::Left]);
+        for f in files.iter() {
+            let icon = self.icon(Icon::File);
+            table.push(vec![
+                Cell::new(self.size(f.size)),
+                Cell::new(relative(root, &f.path)).prefix(format!("{icon} ")),
+            ]);
+        }
+        self.write_table(&table)?;
+        writeln!(self.out)
+    }
+
+    fn print_collections(&mut self, registry: &Registry) -> io::Result<()> {
+        if !self.normal() {
+            for (name, c) in registry.collections.iter() {
+                writeln!(self.out, "{name}\t{}", name::escape_path(&c.root))?;
+            }
+            return Ok(());
+        }
+        if registry.collections.is_empty() {
+            return writeln!(
+                self.out,
+                "\nNo collections registered, see collection add\n"
+            );
+        }
+        writeln!(self.out)?;
+        let mut table = Table::new(&[Align::Left, Align::Left, Align::Left, Align::Left]);
+        for (name, c) in registry.collections.iter() {
+            let db = c.db.clone().unwrap_or_else(|| c.root.join(DB_FILE_NAME));
+            // drives of archives are often unplugged
+            let state = if !c.root.is_dir() {
+                Cell::new("offline").color(Color::Yellow)
+            } else if !db.is_file() {
+                Cell::new("not scanned").color(Color::Yellow)
+            } else {
+                Cell::new("")
+            };
+            table.push(vec![
+                Cell::new(format!("@{name}")).bold(),
+                Cell::new(name::escape_path(&c.root)).color(Color::Blue),
+                Cell::new(c.db.as_deref().map(name::escape_path).unwrap_or_default()),
+                state,
+            ]);
+        }
+        self.write_table(&table)?;
+        writeln!(self.out, "\n{} collection(s)\n", registry.collections.len())
+    }
+
+    fn print_scan_errors(&mut self, skipped: &[ScanError]) -> io::Result<()> {
+        if self.normal() {
+            let title = format!("{} path(s) skipped while scanning", skipped.len());
+            writeln!(self.out, "\n{}", title.yellow())?;
+        }
+        for e in skipped.iter() {
+            let error = e.error.to_string();
+            writeln!(self.out, " {}: {}", name::escape_path(&e.path), error.red())?;
+        }
+        Ok(())
+    }
+
+    fn print_watch_event(
+        &mut self,
+        root: &Path,
+        event: &WatchEvent,
+        _entry: Option<&ContentType>,
+    ) -> io::Result<()> {
+        let path = relative(root, event.path());
+        if !self.normal() {
+            match event {
+                WatchEvent::Moved { from, .. } => {
+                    writeln!(self.out, "moved\t{}\t{path}", relative(root, from))?
+                }
+                _ => writeln!(self.out, "{}\t{path}", event.kind())?,
+            }
+            return self.out.flush();
+        }
+        let time = local_time(SystemTime::now());
+        let arrow = self.arrow();
+        match event {
+            WatchEvent::Created(_) => writeln!(self.out, "{time} {} {path}", "+".green())?,
+            WatchEvent::Modified(_) => writeln!(self.out, "{time} {} {path}", "~".yellow())?,
+            WatchEvent::Deleted(_) => writeln!(self.out, "{time} {} {path}", "-".red())?,
+            WatchEvent::Moved { from, .. } => writeln!(
+                self.out,
+                "{time} {} {} {arrow} {path}",
+                ">".cyan(),
+                relative(root, from)
+            )?,
+            WatchEvent::Rescan(_) => writeln!(
+                self.out,
+                "{time} {} {path} {}",
+                "!".yellow(),
+                "(notifications lost, rescanned)".yellow()
+            )?,
+        }
+        self.out.flush()
+    }
+
+    fn print_stats(&mut self, _root: &Path, stats: &Stats) -> io::Result<()> {
+        let ratio = format!("{:.1}%", stats.duplication_ratio() * 100.0);
+        if !self.normal() {
+            let totals = [
+                ("files", stats.files.to_string()),
+                ("dirs", stats.dirs.to_string()),
+                ("links", stats.links.to_string()),
+                ("bytes", self.size(stats.bytes)),
+                ("unique", self.size(stats.usage.unique_size)),
+                ("empty_files", stats.empty_files.to_string()),
+                ("empty_dirs", stats.empty_dirs.to_string()),
+                ("duplication", ratio),
+            ];
+            for (key, value) in totals {
+                writeln!(self.out, "{key}\t{value}")?;
+            }
+            return Ok(());
+        }
+        writeln!(self.out, "\n{}", "Collection statistics".bold())?;
+        writeln!(
+            self.out,
+            " {} files ({}), {} directories, {} links",
+            stats.files,
+            self.size(stats.bytes),
+            stats.dirs,
+            stats.links
+        )?;
+        writeln!(
+            self.out,
+            " {} empty files, {} empty directories",
+            stats.empty_files, stats.empty_dirs
+        )?;
+        writeln!(
+            self.out,
+            " {} unique of {} ({} duplicated)",
+            self.size(stats.usage.unique_size),
+            self.size(stats.usage.size),
+            ratio
+        )?;
+        self.print_groups("By extension", &stats.by_extension, "(none)")?;
+        self.print_groups("By type", &stats.by_mime, "")?;
+        let ages = stats.ages.iter().map(|b| age_label(b, &stats.ages));
+        self.print_buckets("By age", ages.collect(), &stats.ages)?;
+        let sizes = stats.sizes.iter().map(|b| self.size_label(b, &stats.sizes));
+        self.print_buckets("By size", sizes.collect(), &stats.sizes)?;
+        writeln!(self.out)
+    }
+}
+
+fn age_label(bucket: &Bucket, buckets: &[Bucket]) -> String {
+    match bucket.upper {
+        Some(upper) => format!("< {}", days(upper)),
+        None => format!(">= {}", days(last_bound(buckets))),
+    }
+}
+
+/// Upper bound of the last bounded bucket
+fn last_bound(buckets: &[Bucket]) -> u64 {
+    buckets.iter().filter_map(|b| b.upper).max().unwrap_or(0)
+}
+
+/// Formats a duration in seconds as days, weeks or years
+fn days(seconds: u64) -> String {
+    let days = seconds / (24 * 60 * 60);
+    let (n, unit) = match days {
+        d if d >= 365 && d % 365 == 0 => (d / 365, "year"),
+        d if d > 1 && d < 30 && d % 7 == 0 => (d / 7, "week"),
+        d => (d, "day"),
+    };
+    if n == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{n} {unit}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::mtree::KeywordDiff;
+
+    fn tree() -> Dir {
+        Dir {
+            path: PathBuf::from("/r"),
+            content: vec![
+                ContentType::ContentFile(File {
+                    path: PathBuf::from("/r/b.txt"),
+                    md5: md5::compute(b"abc"),
+                    size: 2048,
+                    ..Default::default()
+                }),
+                ContentType::ContentDir(Dir {
+                    path: PathBuf::from("/r/a"),
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn render(verbosity: Verbosity, ascii: bool, options: ShowOptions) -> String {
+        colored::control::set_override(false);
+        let mut renderer = TextRenderer::new(Vec::new(), verbosity).ascii(ascii);
+        renderer
+            .print_content(Path::new("/r"), &tree(), &options)
+            .unwrap();
+        String::from_utf8(renderer.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn content_ascii() {
+        let output = render(Verbosity::Normal, true, ShowOptions::default());
+        assert!(output.is_ascii(), "Non ASCII output in ASCII mode");
+        assert!(output.contains(" d a      0 files      0 B"), "{output}");
+        assert!(output.contains(" f b.txt           2.0 KiB"), "{output}");
+        assert!(output.contains("1 total files (1 unique)"));
+    }
+
+    #[test]
+    fn highlight_segments() {
+        let h = |range, group| Highlight { range, group };
+        // IMG_(\d{4})\.(jpe?g) on IMG_0042.jpg, then a plain suffix
+        let highlights = [h(0..12, 0), h(4..8, 1), h(9..12, 2)];
+        assert_eq!(
+            vec![
+                (0..4, Some(0)),
+                (4..8, Some(1)),
+                (8..9, Some(0)),
+                (9..12, Some(2)),
+                (12..15, None)
+            ],
+            segments("IMG_0042.jpg.gz", &highlights)
+        );
+        assert_eq!(vec![(0..3, None)], segments("abc", &[]));
+        // ranges inside a character are ignored
+        assert_eq!(vec![(0..2, None)], segments("é", &[h(1..2, 0)]));
+    }
+
+    #[test]
+    fn content_brief() {
+        let options = ShowOptions::default();
+        assert_eq!("a/\nb.txt\n", render(Verbosity::Brief, false, options));
+    }
+
+    #[test]
+    fn content_tree() {
+        let mut root = tree();
+        if let ContentType::ContentDir(a) = &mut root.content[1] {
+            a.content.push(ContentType::ContentFile(File {
+                path: PathBuf::from("/r/a/c.txt"),
+                ..Default::default()
+            }));
+        }
+        colored::control::set_override(false);
+        let mut renderer = TextRenderer::new(Vec::new(), Verbosity::Normal).ascii(true);
+        let options = ShowOptions {
+            tree: true,
+            ..Default::default()
+        };
+        renderer
+            .print_content(Path::new("/r"), &root, &options)
+            .unwrap();
+        let output = String::from_utf8(renderer.into_inner()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        let words = |line: &str| {
+            line.split_whitespace()
+                .take(4)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!("/r 2 files 2.0", words(lines[1]), "{output}");
+        assert_eq!("|-- d a 1", words(lines[2]), "{output}");
+        assert!(lines[2].contains(" 1 file "), "{output}");
+        assert!(lines[3].starts_with(" |   `-- f c.txt"), "{output}");
+        assert!(lines[4].starts_with(" `-- f b.txt"), "{output}");
+        assert!(output.contains("1 total directories\n2 total files"));
+
+        let options = ShowOptions {
+            tree: true,
+            depth: Some(1),
+            ..Default::default()
+        };
+        let brief = render(Verbosity::Brief, false, options);
+        assert_eq!("a/\nb.txt\n", brief);
+    }
+
+    #[test]
+    fn quiet_hides_banner_and_ok_lines() {
+        let mut renderer = TextRenderer::new(Vec::new(), Verbosity::Quiet);
+        renderer.print_welcome().unwrap();
+        renderer.print_message("saved").unwrap();
+        let entry = ManifestEntry::parse("900150983cd24fb0d6963f7d28e17f72  a").unwrap();
+        renderer
+            .print_check_report(&[(&entry, CheckStatus::Ok)], 0)
+            .unwrap();
+        assert!(renderer.into_inner().is_empty());
+    }
+
+    #[test]
+    fn mtree_report_root() {
+        colored::control::set_override(false);
+        let mut renderer = TextRenderer::new(Vec::new(), Verbosity::Quiet);
+        let time = KeywordDiff {
+            keyword: "time".to_string(),
+            expected: "1.0".to_string(),
+            actual: "2.0".to_string(),
+        };
+        let report = [
+            (PathBuf::new(), MtreeStatus::Differs(vec![time])),
+            (PathBuf::from("a.txt"), MtreeStatus::Missing),
+        ];
+        renderer.print_mtree_report(&report).unwrap();
+        let output = String::from_utf8(renderer.into_inner()).unwrap();
+        assert_eq!(
+            ".:\n\ttime (expected 1.0, found 2.0)\na.txt: missing\n",
+            output
+        );
+    }
+
+    #[test]
+    fn bucket_labels() {
+        let day = 24 * 60 * 60;
+        assert_eq!("1 day", days(day));
+        assert_eq!("1 week", days(7 * day));
+        assert_eq!("30 days", days(30 * day));
+        assert_eq!("5 years", days(5 * 365 * day));
+        let buckets = [
+            Bucket {
+                upper: Some(1),
+                count: 0,
+                bytes: 0,
+            },
+            Bucket {
+                upper: Some(1024),
+                count: 0,
+                bytes: 0,
+            },
+            Bucket {
+                upper: None,
+                count: 0,
+                bytes: 0,
+            },
+        ];
+        let renderer = TextRenderer::new(Vec::new(), Verbosity::Normal).exact_sizes(true);
+        let labels: Vec<String> = buckets
+            .iter()
+            .map(|b| renderer.size_label(b, &buckets))
+            .collect();
+        assert_eq!(vec!["empty", "< 1024", ">= 1024"], labels);
+    }
+}
@@ -0,0 +1,575 @@
+//! Interactive shell exploring the indexed tree of a collection.
+//!
+//! The shell works on the tree loaded from the database and never reads
+//! the collection itself, so an archive can be explored after its drive
+//! was unplugged. Paths are relative to the current directory of the
+//! shell, or to the collection root when they start with `/`, and the
+//! names of the indexed entries are completed with `Tab`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::core::algorithm;
+use crate::core::diff;
+use crate::core::dups;
+use crate::core::model::{self, ContentType, Dir, File};
+use crate::core::name;
+use crate::core::search::{self, Matcher};
+use crate::core::watch;
+use crate::render::{human_size, Renderer, ShowOptions};
+
+/// Commands of the shell with their usage
+const COMMANDS: [(&str, &str); 10] = [
+    ("cd", "cd [DIR]           change directory (to the root without DIR)"),
+    ("ls", "ls [DIR]           list a directory"),
+    ("pwd", "pwd                print the current directory"),
+    ("find", "find PATTERN       find entries by name below the current directory"),
+    ("dups", "dups [DIR]         list files with the same content"),
+    ("stat", "stat PATH          show the indexed metadata of an entry"),
+    (
+        "hash",
+        "hash PATH          print the digests of a file or of the files in a directory,\n                   for a local file not in the tree list its indexed copies",
+    ),
+    ("diff", "diff DIR OTHER     compare two directories"),
+    ("help", "help               show this help"),
+    ("exit", "exit               leave the shell (also quit or Ctrl-D)"),
+];
+
+/// A shell command that could not be run
+#[derive(Debug)]
+pub enum ShellError {
+    /// The command line is not valid (unknown command, missing entry...)
+    Invalid(String),
+    /// Printing the result failed
+    Io(io::Error),
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShellError::Invalid(message) => write!(f, "{message}"),
+            ShellError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<io::Error> for ShellError {
+    fn from(e: io::Error) -> Self {
+        ShellError::Io(e)
+    }
+}
+
+fn invalid<T>(message: String) -> Result<T, ShellError> {
+    Err(ShellError::Invalid(message))
+}
+
+/// State of the shell: the indexed tree and the current directory
+pub struct Shell<'a> {
+    root: &'a Dir,
+    /// Current directory, relative to the root
+    cwd: PathBuf,
+    by_md5: HashMap<md5::Digest, Vec<&'a File>>,
+}
+
+impl<'a> Shell<'a> {
+    pub fn new(root: &'a Dir) -> Self {
+        let mut by_md5: HashMap<md5::Digest, Vec<&File>> = HashMap::new();
+        for file in root.all_files() {
+            by_md5.entry(file.md5).or_default().push(file);
+        }
+        Shell {
+            root,
+            cwd: PathBuf::new(),
+            by_md5,
+        }
+    }
+
+    /// Prompt showing the root name and current directory
+    pub fn prompt(&self) -> String {
+        let root = self
+            .root
+            .path
+            .file_name()
+            .map(name::escape)
+            .unwrap_or_else(|| name::escape_path(&self.root.path));
+        format!("{root}:/{}> ", name::escape_path(&self.cwd))
+    }
+
+    /// Completer of the names in the current directory
+    pub fn names(&self) -> Names<'a> {
+        Names {
+            root: self.root,
+            cwd: self.cwd.clone(),
+        }
+    }
+
+    /// Runs a command line, the results are printed with renderer and
+    /// the other output on out. Returns false when the shell should exit.
+    pub fn execute(
+        &mut self,
+        line: &str,
+        renderer: &mut dyn Renderer,
+        out: &mut dyn Write,
+    ) -> Result<bool, ShellError> {
+        let words = split_words(line)?;
+        let Some((command, args)) = words.split_first() else {
+            return Ok(true);
+        };
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match (command.as_str(), args.as_slice()) {
+            ("exit" | "quit", []) => return Ok(false),
+            ("help", []) => {
+                for (_, usage) in COMMANDS {
+                    writeln!(out, "{usage}")?;
+                }
+            }
+            ("pwd", []) => writeln!(out, "/{}", name::escape_path(&self.cwd))?,
+            ("cd", []) => self.cwd = PathBuf::new(),
+            ("cd", [dir]) => {
+                self.dir(dir)?;
+                self.cwd = resolve(&self.cwd, dir);
+            }
+            ("ls", []) => {
+                renderer.print_content(&self.root.path, self.dir(".")?, &ShowOptions::default())?
+            }
+            ("ls", [dir]) => {
+                renderer.print_content(&self.root.path, self.dir(dir)?, &ShowOptions::default())?
+            }
+            ("find", [pattern]) => {
+                let entries = search::entries(self.dir(".")?);
+                let matches =
+                    Matcher::Substring(pattern.to_string()).filter(entries, &self.root.path);
+                renderer.print_find_matches(&self.root.path, &matches)?;
+            }
+            ("dups", []) => {
+                let groups = dups::find_duplicates(self.dir(".")?);
+                renderer.print_duplicates(&self.root.path, &groups)?;
+            }
+            ("dups", [dir]) => {
+                let groups = dups::find_duplicates(self.dir(dir)?);
+                renderer.print_duplicates(&self.root.path, &groups)?;
+            }
+            ("stat", [path]) => self.stat(path, out)?,
+            ("hash", [path]) => self.hash(path, out)?,
+            ("diff", [dir, other]) => {
+                let (dir, other) = (self.dir(dir)?, self.dir(other)?);
+                let changes = diff::diff(dir, other);
+                renderer.print_changes(&dir.path, &other.path, &changes)?;
+            }
+            (command, _) => match COMMANDS.iter().find(|(c, _)| *c == command) {
+                Some((_, usage)) => return invalid(format!("usage: {usage}")),
+                None => return invalid(format!("{command}: unknown command, try help")),
+            },
+        }
+        Ok(true)
+    }
+
+    /// Entry at a path argument, None for the root
+    fn entry(&self, arg: &str) -> Result<Option<&'a ContentType>, ShellError> {
+        let path = resolve(&self.cwd, arg);
+        if path.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        match watch::entry(self.root, &self.root.path.join(&path)) {
+            Some(entry) => Ok(Some(entry)),
+            None => invalid(format!("{arg}: no such entry")),
+        }
+    }
+
+    /// Directory at a path argument
+    fn dir(&self, arg: &str) -> Result<&'a Dir, ShellError> {
+        match self.entry(arg)? {
+            None => Ok(self.root),
+            Some(ContentType::ContentDir(d)) => Ok(d),
+            Some(_) => invalid(format!("{arg}: not a directory")),
+        }
+    }
+
+    fn relative(&self, path: &Path) -> String {
+        match path.strip_prefix(&self.root.path) {
+            Ok(p) if p.as_os_str().is_empty() => String::from("/"),
+            Ok(p) => name::escape_path(p),
+            Err(_) => name::escape_path(path),
+        }
+    }
+
+    fn stat(&self, arg: &str, out: &mut dyn Write) -> Result<(), ShellError> {
+        let entry = self.entry(arg)?;
+        let (path, kind, attributes, modified) = match entry {
+            None => (
+                &self.root.path,
+                "directory",
+                &self.root.attributes,
+                self.root.modified,
+            ),
+            Some(ContentType::ContentDir(d)) => (&d.path, "directory", &d.attributes, d.modified),
+            Some(ContentType::ContentFile(f)) => (&f.path, "file", &f.attributes, f.modified),
+            Some(ContentType::ContentLink(l)) => (&l.path, "link", &l.attributes, l.modified),
+        };
+        writeln!(out, "Path      {}", self.relative(path))?;
+        writeln!(out, "Type      {kind}")?;
+        match entry {
+            None => self.stat_dir(self.root, out)?,
+            Some(ContentType::ContentDir(d)) => self.stat_dir(d, out)?,
+            Some(ContentType::ContentFile(f)) => {
+                writeln!(out, "Size      {} ({} bytes)", human_size(f.size), f.size)?
+            }
+            Some(ContentType::ContentLink(l)) => {
+                writeln!(out, "Target    {}", name::escape_path(&l.target))?
+            }
+        }
+        let modified: chrono::DateTime<chrono::Local> = modified.into();
+        writeln!(out, "Modified  {}", modified.format("%Y-%m-%d %H:%M:%S"))?;
+        writeln!(out, "Mode      {:o}", attributes.mode & 0o7777)?;
+        writeln!(out, "Owner     {}:{}", attributes.uid, attributes.gid)?;
+        if let Some(ContentType::ContentFile(f)) = entry {
+            writeln!(out, "MIME      {}", f.mime.as_deref().unwrap_or("-"))?;
+            writeln!(out, "MD5       {:x}", f.md5)?;
+            let copies: Vec<String> = self.by_md5[&f.md5]
+                .iter()
+                .filter(|c| c.path != f.path)
+                .map(|c| self.relative(&c.path))
+                .collect();
+            match copies.is_empty() {
+                true => writeln!(out, "Copies    none")?,
+                false => writeln!(out, "Copies    {}", copies.join(", "))?,
+            }
+        }
+        Ok(())
+    }
+
+    fn stat_dir(&self, dir: &Dir, out: &mut dyn Write) -> io::Result<()> {
+        let size = dir.total_size();
+        writeln!(out, "Size      {} ({size} bytes)", human_size(size))?;
+        writeln!(
+            out,
+            "Content   {} file(s), {} dir(s)",
+            dir.file_count(),
+            dir.dir_count()
+        )
+    }
+
+    fn hash(&self, arg: &str, out: &mut dyn Write) -> Result<(), ShellError> {
+        let files = match self.entry(arg) {
+            Ok(None) => self.root.all_files(),
+            Ok(Some(ContentType::ContentDir(d))) => d.all_files(),
+            Ok(Some(ContentType::ContentFile(f))) => vec![f],
+            Ok(Some(ContentType::ContentLink(_))) => return invalid(format!("{arg}: is a link")),
+            // not indexed, look for a local file with the same content
+            Err(e) => match Path::new(arg).is_file() {
+                true => return self.hash_local(Path::new(arg), out),
+                false => return Err(e),
+            },
+        };
+        for file in files {
+            writeln!(out, "{:x}  {}", file.md5, self.relative(&file.path))?;
+        }
+        Ok(())
+    }
+
+    fn hash_local(&self, path: &Path, out: &mut dyn Write) -> Result<(), ShellError> {
+        let md5 = model::compute_md5(path)?;
+        writeln!(out, "{md5:x}  {}", name::escape_path(path))?;
+        match self.by_md5.get(&md5) {
+            Some(copies) => {
+                for copy in copies {
+                    writeln!(out, "  indexed as {}", self.relative(&copy.path))?;
+                }
+            }
+            None => writeln!(out, "  not indexed")?,
+        }
+        Ok(())
+    }
+}
+
+/// Normalized path, relative to the root, of a path argument
+fn resolve(cwd: &Path, arg: &str) -> PathBuf {
+    let mut path = match arg.starts_with('/') {
+        true => PathBuf::new(),
+        false => cwd.to_path_buf(),
+    };
+    for component in Path::new(arg).components() {
+        match component {
+            Component::ParentDir => {
+                path.pop();
+            }
+            Component::Normal(name) => path.push(name),
+            _ => (),
+        }
+    }
+    path
+}
+
+/// Splits a command line into words, honouring quotes and backslash
+/// escapes (e.g. for names with spaces)
+fn split_words(line: &str) -> Result<Vec<String>, ShellError> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None | Some('"'), '\\') => {
+                if let Some(escaped) = chars.next() {
+                    word.get_or_insert_with(String::new).push(escaped);
+                }
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (_, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return invalid(String::from("unterminated quote"));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Escapes the characters that split_words would interpret
+fn escape_word(word: &str) -> String {
+    let mut escaped = String::new();
+    for c in word.chars() {
+        if c.is_whitespace() || matches!(c, '\\' | '\'' | '"') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Completes command names and the names of the indexed entries
+pub struct Names<'a> {
+    root: &'a Dir,
+    cwd: PathBuf,
+}
+
+impl Names<'_> {
+    /// Start of the word before pos and its completions
+    pub fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<Pair>) {
+        let line = &line[..pos];
+        let mut start = 0;
+        let mut escaped = false;
+        for (i, c) in line.char_indices() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                c if c.is_whitespace() => start = i + c.len_utf8(),
+                _ => (),
+            }
+        }
+        let word = &line[start..];
+        if line[..start].trim().is_empty() {
+            let commands = COMMANDS
+                .iter()
+                .filter(|(c, _)| c.starts_with(word))
+                .map(|(c, _)| Pair {
+                    display: c.to_string(),
+                    replacement: format!("{c} "),
+                })
+                .collect();
+            return (start, commands);
+        }
+        let (dir, prefix) = match word.rfind('/') {
+            Some(i) => word.split_at(i + 1),
+            None => ("", word),
+        };
+        let unescaped = |s: &str| split_words(s).map(|w| w.concat()).unwrap_or_default();
+        let (dir_arg, prefix) = (unescaped(dir), unescaped(prefix));
+        let path = resolve(&self.cwd, &dir_arg);
+        let content = match path.as_os_str().is_empty() {
+            true => Some(&self.root.content),
+            false => match watch::entry(self.root, &self.root.path.join(&path)) {
+                Some(ContentType::ContentDir(d)) => Some(&d.content),
+                _ => None,
+            },
+        };
+        let mut names: Vec<Pair> = content
+            .into_iter()
+            .flatten()
+            .filter_map(|item| {
+                let name = item.path().file_name()?.to_string_lossy().into_owned();
+                if !name.starts_with(&prefix) {
+                    return None;
+                }
+                let suffix = match item {
+                    ContentType::ContentDir(_) => "/",
+                    _ => " ",
+                };
+                Some(Pair {
+                    replacement: format!("{dir}{}{suffix}", escape_word(&name)),
+                    display: name,
+                })
+            })
+            .collect();
+        names.sort_by(|a, b| algorithm::natural_cmp(&a.display, &b.display));
+        (start, names)
+    }
+}
+
+impl Completer for Names<'_> {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+impl Hinter for Names<'_> {
+    type Hint = String;
+}
+
+impl Highlighter for Names<'_> {}
+
+impl Validator for Names<'_> {}
+
+impl Helper for Names<'_> {}
+
+/// Reads and runs commands until exit or end of input
+pub fn run(root: &Dir, renderer: &mut dyn Renderer) -> io::Result<()> {
+    let mut shell = Shell::new(root);
+    let mut editor: Editor<Names, DefaultHistory> = Editor::new().map_err(io::Error::other)?;
+    loop {
+        editor.set_helper(Some(shell.names()));
+        let line = match editor.readline(&shell.prompt()) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        let _ = editor.add_history_entry(line.as_str());
+        match shell.execute(&line, renderer, &mut io::stdout()) {
+            Ok(true) => (),
+            Ok(false) => return Ok(()),
+            Err(ShellError::Invalid(message)) => eprintln!("{message}"),
+            Err(ShellError::Io(e)) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::model::fixtures::{dir, file, subdir};
+    use crate::render::text::TextRenderer;
+    use crate::render::Verbosity;
+
+    /// Runs a command returning the renderer and shell output
+    fn run(shell: &mut Shell, line: &str) -> Result<String, ShellError> {
+        let mut renderer = TextRenderer::new(vec![], Verbosity::Brief);
+        let mut out = vec![];
+        assert!(shell.execute(line, &mut renderer, &mut out)?);
+        out.extend(renderer.into_inner());
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn navigate_tree() {
+        let root = dir(
+            "/archive",
+            vec![
+                subdir(
+                    "/archive/old photos",
+                    vec![file("/archive/old photos/a.jpg", b"a")],
+                ),
+                subdir(
+                    "/archive/new",
+                    vec![
+                        file("/archive/new/a.jpg", b"a"),
+                        file("/archive/new/b.jpg", b"b"),
+                    ],
+                ),
+            ],
+        );
+        colored::control::set_override(false);
+        let mut shell = Shell::new(&root);
+        assert_eq!(shell.prompt(), "archive:/> ");
+        run(&mut shell, "cd 'old photos'").unwrap();
+        assert_eq!(run(&mut shell, "pwd").unwrap(), "/old photos\n");
+        assert_eq!(run(&mut shell, "ls").unwrap(), "a.jpg\n");
+        run(&mut shell, "cd ../new/").unwrap();
+        assert_eq!(shell.prompt(), "archive:/new> ");
+        assert_eq!(
+            run(&mut shell, "find JPG").unwrap(),
+            "new/a.jpg\nnew/b.jpg\n"
+        );
+        assert_eq!(
+            run(&mut shell, "dups /").unwrap(),
+            "new/a.jpg\nold photos/a.jpg\n"
+        );
+        let stat = run(&mut shell, "stat a.jpg").unwrap();
+        assert!(stat.contains("Size      1 B (1 bytes)"), "{stat}");
+        assert!(stat.contains("Copies    old photos/a.jpg"), "{stat}");
+        assert_eq!(
+            run(&mut shell, "hash /old\\ photos").unwrap(),
+            format!("{:x}  old photos/a.jpg\n", md5::compute(b"a"))
+        );
+        assert_eq!(
+            run(&mut shell, "diff '/old photos' .").unwrap(),
+            " + b.jpg\n"
+        );
+        assert!(matches!(
+            run(&mut shell, "cd a.jpg"),
+            Err(ShellError::Invalid(_))
+        ));
+        assert!(matches!(
+            run(&mut shell, "ls missing"),
+            Err(ShellError::Invalid(_))
+        ));
+        assert!(matches!(run(&mut shell, "rm"), Err(ShellError::Invalid(_))));
+        run(&mut shell, "cd").unwrap();
+        assert_eq!(run(&mut shell, "pwd").unwrap(), "/\n");
+        let mut renderer = TextRenderer::new(vec![], Verbosity::Brief);
+        assert!(!shell.execute("exit", &mut renderer, &mut vec![]).unwrap());
+    }
+
+    #[test]
+    fn complete_names() {
+        let root = dir(
+            "/archive",
+            vec![
+                subdir(
+                    "/archive/old photos",
+                    vec![file("/archive/old photos/a.jpg", b"a")],
+                ),
+                file("/archive/other", b"o"),
+            ],
+        );
+        let shell = Shell::new(&root);
+        let complete = |line: &str| {
+            let (start, pairs) = shell.names().candidates(line, line.len());
+            let replacements: Vec<String> = pairs.into_iter().map(|p| p.replacement).collect();
+            (start, replacements)
+        };
+        assert_eq!(complete("d"), (0, vec!["dups ".into(), "diff ".into()]));
+        assert_eq!(
+            complete("cd o"),
+            (3, vec!["old\\ photos/".into(), "other ".into()])
+        );
+        assert_eq!(complete("ls old\\ "), (3, vec!["old\\ photos/".into()]));
+        assert_eq!(
+            complete("stat old\\ photos/"),
+            (5, vec!["old\\ photos/a.jpg ".into()])
+        );
+        assert_eq!(complete("stat missing/"), (5, vec![]));
+    }
+}
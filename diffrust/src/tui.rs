@@ -0,0 +1,1012 @@
+//! Terminal user interface browsing scanned collections.
+//!
+//! The tree of the collection is shown next to a details pane for the
+//! selected entry: metadata, digest and the other files with the same
+//! content. Typing `/` starts a find over the names of all entries that
+//! is updated as the pattern is typed, matching substrings or (after
+//! `Tab`) by fuzzy score. With a second collection the two trees are
+//! shown side by side, aligned by relative path and marked with their
+//! diff status.
+//!
+//! The state (`App`) does not depend on the terminal: `App::key` applies
+//! a key press and `draw` renders the state on any ratatui backend.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::{DateTime, Local};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::Frame;
+
+use crate::core::algorithm::{self, dice_coefficient};
+use crate::core::diff::{self, Change};
+use crate::core::model::{ContentType, Dir, File, Link};
+use crate::core::name;
+use crate::render::human_size;
+
+/// Most matches listed by find
+const MAX_MATCHES: usize = 500;
+/// Most duplicates listed in the details pane
+const MAX_DUPLICATES: usize = 10;
+
+/// Diff status of an entry shown side by side
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    /// Same entry in both collections (every entry of a single one)
+    Same,
+    /// Only in the right collection
+    Added,
+    /// Only in the left collection
+    Removed,
+    /// In both collections with different content (or link target)
+    Modified,
+    /// File whose content is at another path in the other collection
+    Renamed,
+    /// Directory with changes within
+    Changed,
+}
+
+impl Status {
+    fn marker(&self) -> char {
+        match self {
+            Status::Same => ' ',
+            Status::Added => '+',
+            Status::Removed => '-',
+            Status::Modified => '~',
+            Status::Renamed => '>',
+            Status::Changed => '*',
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Status::Same => Color::Reset,
+            Status::Added => Color::Green,
+            Status::Removed => Color::Red,
+            Status::Modified | Status::Changed => Color::Yellow,
+            Status::Renamed => Color::Cyan,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Same => "unchanged",
+            Status::Added => "added",
+            Status::Removed => "removed",
+            Status::Modified => "modified",
+            Status::Renamed => "renamed",
+            Status::Changed => "changed within",
+        }
+    }
+}
+
+/// An indexed entry of one of the collections
+#[derive(Clone, Copy)]
+enum Entry<'a> {
+    Dir(&'a Dir),
+    File(&'a File),
+    Link(&'a Link),
+}
+
+impl<'a> Entry<'a> {
+    fn from(content: &'a ContentType) -> Self {
+        match content {
+            ContentType::ContentDir(d) => Entry::Dir(d),
+            ContentType::ContentFile(f) => Entry::File(f),
+            ContentType::ContentLink(l) => Entry::Link(l),
+        }
+    }
+
+    fn content(self) -> &'a [ContentType] {
+        match self {
+            Entry::Dir(d) => &d.content,
+            _ => &[],
+        }
+    }
+}
+
+/// Changed files of two collections, by path in the left and right one,
+/// with the path of the other file for renames
+#[derive(Default)]
+struct Changes<'a> {
+    left: HashMap<&'a Path, (Status, Option<&'a Path>)>,
+    right: HashMap<&'a Path, (Status, Option<&'a Path>)>,
+}
+
+impl<'a> Changes<'a> {
+    fn from(changes: Vec<Change<'a>>) -> Self {
+        let mut result = Changes::default();
+        for change in changes {
+            match change {
+                Change::Added(f) => {
+                    result.right.insert(&f.path, (Status::Added, None));
+                }
+                Change::Removed(f) => {
+                    result.left.insert(&f.path, (Status::Removed, None));
+                }
+                Change::Modified { old, new } => {
+                    result.left.insert(&old.path, (Status::Modified, None));
+                    result.right.insert(&new.path, (Status::Modified, None));
+                }
+                Change::Renamed { from, to } => {
+                    result
+                        .left
+                        .insert(&from.path, (Status::Renamed, Some(&to.path)));
+                    result
+                        .right
+                        .insert(&to.path, (Status::Renamed, Some(&from.path)));
+                }
+            }
+        }
+        result
+    }
+
+    fn get(&self, left: Option<Entry<'a>>, right: Option<Entry<'a>>) -> Option<Status> {
+        let file_status = |entry, map: &HashMap<&Path, (Status, Option<&Path>)>| match entry {
+            Some(Entry::File(f)) => map.get(f.path.as_path()).map(|(s, _)| *s),
+            _ => None,
+        };
+        file_status(left, &self.left).or_else(|| file_status(right, &self.right))
+    }
+}
+
+/// An entry of the browsed tree: the entries at the same path relative
+/// to the root in the left and (side by side) right collection
+struct Node<'a> {
+    name: OsString,
+    /// Path relative to the roots
+    path: PathBuf,
+    left: Option<Entry<'a>>,
+    right: Option<Entry<'a>>,
+    status: Status,
+    expanded: bool,
+    children: Vec<Node<'a>>,
+}
+
+impl<'a> Node<'a> {
+    fn build(
+        name: OsString,
+        path: PathBuf,
+        left: Option<Entry<'a>>,
+        right: Option<Entry<'a>>,
+        changes: Option<&Changes<'a>>,
+    ) -> Self {
+        let mut content: Vec<(OsString, Option<Entry>, Option<Entry>)> = vec![];
+        let mut index: HashMap<OsString, usize> = HashMap::new();
+        for c in left.map(Entry::content).unwrap_or_default() {
+            let name = c.path().file_name().unwrap_or_default().to_os_string();
+            index.insert(name.clone(), content.len());
+            content.push((name, Some(Entry::from(c)), None));
+        }
+        for c in right.map(Entry::content).unwrap_or_default() {
+            let name = c.path().file_name().unwrap_or_default().to_os_string();
+            match index.get(&name) {
+                Some(&i) => content[i].2 = Some(Entry::from(c)),
+                None => content.push((name, None, Some(Entry::from(c)))),
+            }
+        }
+        let mut children: Vec<Node> = content
+            .into_iter()
+            .map(|(name, l, r)| Node::build(name.clone(), path.join(&name), l, r, changes))
+            .collect();
+        children.sort_by(|a, b| {
+            b.is_dir().cmp(&a.is_dir()).then_with(|| {
+                algorithm::natural_cmp(&a.name.to_string_lossy(), &b.name.to_string_lossy())
+            })
+        });
+
+        let status = match changes {
+            None => Status::Same,
+            Some(changes) => {
+                let own = changes.get(left, right).unwrap_or(match (left, right) {
+                    (Some(_), None) => Status::Removed,
+                    (None, Some(_)) => Status::Added,
+                    (Some(Entry::Link(a)), Some(Entry::Link(b))) if a.target != b.target => {
+                        Status::Modified
+                    }
+                    _ => Status::Same,
+                });
+                match own {
+                    Status::Same if children.iter().any(|c| c.status != Status::Same) => {
+                        Status::Changed
+                    }
+                    own => own,
+                }
+            }
+        };
+        Node {
+            name,
+            path,
+            left,
+            right,
+            status,
+            expanded: false,
+            children,
+        }
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(self.left, Some(Entry::Dir(_))) || matches!(self.right, Some(Entry::Dir(_)))
+    }
+
+    /// Indexes of all nodes below this one, in tree order
+    fn walk(&self, index: &mut Vec<usize>, visit: &mut dyn FnMut(&[usize], &Node)) {
+        for (i, child) in self.children.iter().enumerate() {
+            index.push(i);
+            visit(index, child);
+            child.walk(index, visit);
+            index.pop();
+        }
+    }
+}
+
+/// Pattern and results of the find started with `/`
+struct Find {
+    pattern: String,
+    /// Score names with the Dice coefficient instead of substrings
+    fuzzy: bool,
+    matches: Vec<Vec<usize>>,
+    selected: usize,
+}
+
+/// One or two collections
+struct Side<'a> {
+    root: &'a Path,
+    by_md5: HashMap<md5::Digest, Vec<&'a File>>,
+}
+
+impl<'a> Side<'a> {
+    fn from(dir: &'a Dir) -> Self {
+        let mut by_md5: HashMap<md5::Digest, Vec<&File>> = HashMap::new();
+        for file in dir.all_files() {
+            by_md5.entry(file.md5).or_default().push(file);
+        }
+        Side {
+            root: &dir.path,
+            by_md5,
+        }
+    }
+}
+
+/// State of the terminal user interface
+pub struct App<'a> {
+    root: Node<'a>,
+    left: Side<'a>,
+    right: Option<Side<'a>>,
+    changes: Changes<'a>,
+    /// Index paths (child positions from the root) of the visible nodes
+    rows: Vec<Vec<usize>>,
+    selected: usize,
+    /// First row shown
+    offset: usize,
+    /// Rows shown by the last draw
+    page: usize,
+    find: Option<Find>,
+    ascii: bool,
+    quit: bool,
+}
+
+impl<'a> App<'a> {
+    /// Browses the tree of a collection
+    pub fn new(dir: &'a Dir) -> Self {
+        App::build(dir, None)
+    }
+
+    /// Browses the trees of two collections side by side
+    pub fn side_by_side(left: &'a Dir, right: &'a Dir) -> Self {
+        App::build(left, Some(right))
+    }
+
+    /// Uses only ASCII symbols
+    pub fn ascii(mut self, ascii: bool) -> Self {
+        self.ascii = ascii;
+        self
+    }
+
+    /// Whether the user asked to quit
+    pub fn done(&self) -> bool {
+        self.quit
+    }
+
+    fn build(left: &'a Dir, right: Option<&'a Dir>) -> Self {
+        let changes = match right {
+            Some(right) => Changes::from(diff::diff(left, right)),
+            None => Changes::default(),
+        };
+        let mut root = Node::build(
+            OsString::new(),
+            PathBuf::new(),
+            Some(Entry::Dir(left)),
+            right.map(Entry::Dir),
+            right.map(|_| &changes),
+        );
+        root.expanded = true;
+        let mut app = App {
+            root,
+            left: Side::from(left),
+            right: right.map(Side::from),
+            changes,
+            rows: vec![],
+            selected: 0,
+            offset: 0,
+            page: 1,
+            find: None,
+            ascii: false,
+            quit: false,
+        };
+        app.update_rows();
+        app
+    }
+
+    fn node(&self, index: &[usize]) -> &Node<'a> {
+        index.iter().fold(&self.root, |node, &i| &node.children[i])
+    }
+
+    fn node_mut(&mut self, index: &[usize]) -> &mut Node<'a> {
+        index
+            .iter()
+            .fold(&mut self.root, |node, &i| &mut node.children[i])
+    }
+
+    /// Index path of the selected node, in find mode the selected match
+    fn selected_index(&self) -> Option<&[usize]> {
+        match &self.find {
+            Some(find) => find.matches.get(find.selected).map(Vec::as_slice),
+            None => self.rows.get(self.selected).map(Vec::as_slice),
+        }
+    }
+
+    /// Relative path of the selected entry
+    pub fn selected_path(&self) -> Option<&Path> {
+        self.selected_index().map(|i| self.node(i).path.as_path())
+    }
+
+    /// Lists the visible nodes again, keeping the selected one
+    fn update_rows(&mut self) {
+        let selected = self.rows.get(self.selected).cloned();
+        let mut rows = vec![];
+        let mut index = vec![];
+        visible(&self.root, &mut index, &mut rows);
+        self.rows = rows;
+        self.selected = selected
+            .and_then(|s| self.rows.iter().position(|r| *r == s))
+            .unwrap_or(0);
+    }
+
+    /// Expands the ancestors of a node and selects it
+    fn reveal(&mut self, index: &[usize]) {
+        for depth in 1..index.len() {
+            self.node_mut(&index[..depth]).expanded = true;
+        }
+        self.update_rows();
+        if let Some(row) = self.rows.iter().position(|r| r == index) {
+            self.selected = row;
+        }
+    }
+
+    /// Applies a key press
+    pub fn key(&mut self, key: KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+        } else if self.find.is_some() {
+            self.find_key(key.code);
+        } else {
+            self.browse_key(key.code);
+        }
+    }
+
+    fn browse_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Up | KeyCode::Char('k') => self.move_by(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.move_by(1),
+            KeyCode::PageUp => self.move_by(-(self.page as isize)),
+            KeyCode::PageDown => self.move_by(self.page as isize),
+            KeyCode::Home | KeyCode::Char('g') => self.selected = 0,
+            KeyCode::End | KeyCode::Char('G') => self.selected = self.rows.len().saturating_sub(1),
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Enter => self.expand(),
+            KeyCode::Left | KeyCode::Char('h') => self.collapse(),
+            KeyCode::Char('n') if self.right.is_some() => self.next_change(),
+            KeyCode::Char('/') => {
+                self.find = Some(Find {
+                    pattern: String::new(),
+                    fuzzy: false,
+                    matches: vec![],
+                    selected: 0,
+                })
+            }
+            _ => (),
+        }
+    }
+
+    fn find_key(&mut self, code: KeyCode) {
+        let Some(find) = self.find.as_mut() else {
+            return;
+        };
+        match code {
+            KeyCode::Esc => self.find = None,
+            KeyCode::Enter => {
+                let found = find.matches.get(find.selected).cloned();
+                self.find = None;
+                if let Some(index) = found {
+                    self.reveal(&index);
+                }
+            }
+            KeyCode::Up => find.selected = find.selected.saturating_sub(1),
+            KeyCode::Down => {
+                find.selected = (find.selected + 1).min(find.matches.len().saturating_sub(1))
+            }
+            KeyCode::Tab => {
+                find.fuzzy = !find.fuzzy;
+                self.update_matches();
+            }
+            KeyCode::Backspace => {
+                find.pattern.pop();
+                self.update_matches();
+            }
+            KeyCode::Char(c) => {
+                find.pattern.push(c);
+                self.update_matches();
+            }
+            _ => (),
+        }
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        let last = self.rows.len().saturating_sub(1) as isize;
+        self.selected = (self.selected as isize + delta).clamp(0, last) as usize;
+    }
+
+    /// Expands the selected directory, or enters it if already expanded
+    fn expand(&mut self) {
+        let Some(index) = self.rows.get(self.selected).cloned() else {
+            return;
+        };
+        let node = self.node_mut(&index);
+        if !node.is_dir() || node.children.is_empty() {
+            return;
+        }
+        if node.expanded {
+            self.move_by(1);
+        } else {
+            node.expanded = true;
+            self.update_rows();
+        }
+    }
+
+    /// Collapses the selected directory, or selects its parent
+    fn collapse(&mut self) {
+        let Some(index) = self.rows.get(self.selected).cloned() else {
+            return;
+        };
+        let node = self.node_mut(&index);
+        if node.expanded {
+            node.expanded = false;
+            self.update_rows();
+        } else if index.len() > 1 {
+            self.reveal(&index[..index.len() - 1]);
+        }
+    }
+
+    /// Selects the next added, removed, modified or renamed entry
+    fn next_change(&mut self) {
+        let mut changed = vec![];
+        self.root.walk(&mut vec![], &mut |index, node| {
+            if !matches!(node.status, Status::Same | Status::Changed) {
+                changed.push(index.to_vec());
+            }
+        });
+        let current = self.rows.get(self.selected).cloned().unwrap_or_default();
+        let next = changed
+            .iter()
+            .find(|index| **index > current)
+            .or(changed.first())
+            .cloned();
+        if let Some(index) = next {
+            self.reveal(&index);
+        }
+    }
+
+    /// Finds again the entries matching the pattern
+    fn update_matches(&mut self) {
+        let Some(find) = self.find.as_ref() else {
+            return;
+        };
+        let (pattern, fuzzy) = (find.pattern.as_str(), find.fuzzy);
+        let mut scored: Vec<(f64, Vec<usize>)> = vec![];
+        if !pattern.is_empty() {
+            self.root.walk(&mut vec![], &mut |index, node| {
+                let score = match fuzzy {
+                    true => dice_coefficient(&node.name.to_string_lossy(), pattern),
+                    false => match algorithm::substrings_in_name(&node.path, pattern).is_empty() {
+                        true => 0.0,
+                        false => 1.0,
+                    },
+                };
+                if score > 0.0 {
+                    scored.push((score, index.to_vec()));
+                }
+            });
+        }
+        // stable: equal scores stay in tree order
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(MAX_MATCHES);
+        if let Some(find) = self.find.as_mut() {
+            find.matches = scored.into_iter().map(|(_, index)| index).collect();
+            find.selected = 0;
+        }
+    }
+}
+
+/// Appends the index paths of the visible nodes below node
+fn visible(node: &Node, index: &mut Vec<usize>, rows: &mut Vec<Vec<usize>>) {
+    for (i, child) in node.children.iter().enumerate() {
+        index.push(i);
+        rows.push(index.clone());
+        if child.expanded {
+            visible(child, index, rows);
+        }
+        index.pop();
+    }
+}
+
+/// Shows the collections in the terminal until the user quits
+pub fn run(app: &mut App) -> io::Result<()> {
+    if !io::stdout().is_terminal() {
+        return Err(io::Error::other("the terminal interface needs a terminal"));
+    }
+    let mut terminal = ratatui::try_init()?;
+    let result = (|| {
+        while !app.done() {
+            terminal.draw(|frame| draw(frame, app))?;
+            if let Event::Key(key) = event::read()? {
+                app.key(key);
+            }
+        }
+        Ok(())
+    })();
+    ratatui::restore();
+    result
+}
+
+/// Draws the state on a frame
+pub fn draw(frame: &mut Frame, app: &mut App) {
+    let [main, help] =
+        Layout::vertical([Constraint::Min(3), Constraint::Length(1)]).areas(frame.area());
+    let (trees, details) = match app.right {
+        Some(_) => {
+            let [trees, details] =
+                Layout::vertical([Constraint::Percentage(65), Constraint::Percentage(35)])
+                    .areas(main);
+            (trees, details)
+        }
+        None => {
+            let [tree, details] =
+                Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                    .areas(main);
+            (tree, details)
+        }
+    };
+    app.page = trees.height.saturating_sub(2).max(1) as usize;
+    match app.find.is_some() {
+        true => draw_matches(frame, app, trees),
+        false => draw_trees(frame, app, trees),
+    }
+    draw_details(frame, app, details);
+    frame.render_widget(Paragraph::new(help_line(app)), help);
+}
+
+fn draw_trees(frame: &mut Frame, app: &mut App, area: Rect) {
+    app.offset = scroll(app.offset, app.selected, app.page);
+    let rows = &app.rows[app.offset..app.rows.len().min(app.offset + app.page)];
+    let columns: Vec<(&Side, bool)> = match &app.right {
+        Some(right) => vec![(&app.left, true), (right, false)],
+        None => vec![(&app.left, true)],
+    };
+    let areas = Layout::horizontal(vec![Constraint::Fill(1); columns.len()]).split(area);
+    for ((side, is_left), area) in columns.into_iter().zip(areas.iter()) {
+        let lines: Vec<Line> = rows
+            .iter()
+            .enumerate()
+            .map(|(i, index)| {
+                let node = app.node(index);
+                let entry = if is_left { node.left } else { node.right };
+                let mut line = tree_line(app, node, entry, index.len() - 1);
+                if app.offset + i == app.selected {
+                    line = line.patch_style(Style::default().add_modifier(Modifier::REVERSED));
+                }
+                line
+            })
+            .collect();
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title(name::escape_path(side.root));
+        frame.render_widget(Paragraph::new(lines).block(block), *area);
+    }
+}
+
+/// Line of a tree column, blank when the entry is not in this collection
+fn tree_line<'l>(app: &App, node: &Node, entry: Option<Entry>, depth: usize) -> Line<'l> {
+    let mut spans = vec![];
+    if app.right.is_some() {
+        let marker = match entry {
+            Some(_) => node.status.marker(),
+            None => ' ',
+        };
+        spans.push(Span::styled(format!("{marker} "), node.status.color()));
+    }
+    let Some(entry) = entry else {
+        return Line::from(spans);
+    };
+    let arrow = match (entry, node.expanded, app.ascii) {
+        (Entry::Dir(_), true, false) => "▾ ",
+        (Entry::Dir(_), false, false) => "▸ ",
+        (Entry::Dir(_), true, true) => "v ",
+        (Entry::Dir(_), false, true) => "> ",
+        _ => "  ",
+    };
+    spans.push(Span::raw(format!("{}{arrow}", "  ".repeat(depth))));
+    let name = name::escape(&node.name);
+    spans.push(match entry {
+        Entry::Dir(_) => Span::styled(name, Style::default().fg(Color::Blue).bold()),
+        Entry::File(_) => Span::raw(name),
+        Entry::Link(l) => Span::styled(
+            format!("{name} -> {}", name::escape_path(&l.target)),
+            Color::Cyan,
+        ),
+    });
+    Line::from(spans)
+}
+
+fn draw_matches(frame: &mut Frame, app: &App, area: Rect) {
+    let Some(find) = &app.find else {
+        return;
+    };
+    let offset = scroll(0, find.selected, app.page);
+    let lines: Vec<Line> = find
+        .matches
+        .iter()
+        .enumerate()
+        .skip(offset)
+        .take(app.page)
+        .map(|(i, index)| {
+            let node = app.node(index);
+            let mut spans = vec![];
+            if app.right.is_some() {
+                spans.push(Span::styled(
+                    format!("{} ", node.status.marker()),
+                    node.status.color(),
+                ));
+            }
+            spans.extend(highlighted(&node.path, &find.pattern, find.fuzzy));
+            let line = Line::from(spans);
+            match i == find.selected {
+                true => line.patch_style(Style::default().add_modifier(Modifier::REVERSED)),
+                false => line,
+            }
+        })
+        .collect();
+    let mode = if find.fuzzy { "fuzzy" } else { "substring" };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!("{} {mode} match(es)", find.matches.len()));
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// Spans of a relative path, highlighting the substrings of its name
+/// matching the pattern
+fn highlighted<'l>(path: &Path, pattern: &str, fuzzy: bool) -> Vec<Span<'l>> {
+    let text = name::escape_path(path);
+    let name = path.file_name().map(name::escape).unwrap_or_default();
+    let Some(parent) = text.strip_suffix(name.as_str()) else {
+        return vec![Span::raw(text)];
+    };
+    let ranges = match fuzzy {
+        true => vec![],
+        false => algorithm::substrings_in_name(path, pattern),
+    };
+    let mut spans = vec![Span::raw(parent.to_string())];
+    let mut end = 0;
+    for range in ranges {
+        // ranges of lowercased names may not fall on the escaped name
+        let (Some(before), Some(matched)) = (name.get(end..range.start), name.get(range.clone()))
+        else {
+            continue;
+        };
+        spans.push(Span::raw(before.to_string()));
+        spans.push(Span::styled(
+            matched.to_string(),
+            Style::default().fg(Color::Yellow).bold(),
+        ));
+        end = range.end;
+    }
+    spans.push(Span::raw(name.get(end..).unwrap_or_default().to_string()));
+    spans
+}
+
+fn draw_details(frame: &mut Frame, app: &App, area: Rect) {
+    let mut lines = vec![];
+    if let Some(index) = app.selected_index() {
+        let node = app.node(index);
+        match &app.right {
+            Some(right) => {
+                let mut status = vec![
+                    Span::raw("Status    "),
+                    Span::styled(node.status.name(), node.status.color()),
+                ];
+                if let Some(other) = renamed(app, node) {
+                    status.push(Span::raw(format!(" ({})", name::escape_path(other))));
+                }
+                lines.push(Line::from(status));
+                for (title, side, entry) in
+                    [("Left", &app.left, node.left), ("Right", right, node.right)]
+                {
+                    if let Some(entry) = entry {
+                        lines.push(Line::default());
+                        lines.push(Line::from(title.bold()));
+                        lines.extend(entry_details(side, entry));
+                    }
+                }
+            }
+            None => {
+                if let Some(entry) = node.left {
+                    lines.extend(entry_details(&app.left, entry));
+                }
+            }
+        }
+    }
+    let block = Block::default().borders(Borders::ALL).title("Details");
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// Path of the other file of a rename, relative to its root
+fn renamed<'p>(app: &'p App, node: &Node) -> Option<&'p Path> {
+    let (other, root) = match (node.left, node.right) {
+        (Some(Entry::File(f)), _) if app.changes.left.contains_key(f.path.as_path()) => (
+            app.changes.left[f.path.as_path()].1?,
+            app.right.as_ref()?.root,
+        ),
+        (_, Some(Entry::File(f))) if app.changes.right.contains_key(f.path.as_path()) => {
+            (app.changes.right[f.path.as_path()].1?, app.left.root)
+        }
+        _ => return None,
+    };
+    Some(other.strip_prefix(root).unwrap_or(other))
+}
+
+fn entry_details<'l>(side: &Side, entry: Entry) -> Vec<Line<'l>> {
+    let field = |name: &str, value: String| Line::from(format!("{name:<10}{value}"));
+    let relative = |path: &Path| match path.strip_prefix(side.root) {
+        Ok(p) if p.as_os_str().is_empty() => String::from("."),
+        Ok(p) => name::escape_path(p),
+        Err(_) => name::escape_path(path),
+    };
+    let (path, kind, attributes, modified) = match entry {
+        Entry::Dir(d) => (&d.path, "directory", &d.attributes, d.modified),
+        Entry::File(f) => (&f.path, "file", &f.attributes, f.modified),
+        Entry::Link(l) => (&l.path, "link", &l.attributes, l.modified),
+    };
+    let mut lines = vec![
+        field("Path", relative(path)),
+        field("Type", kind.to_string()),
+    ];
+    match entry {
+        Entry::Dir(d) => {
+            lines.push(field("Size", human_size(d.total_size())));
+            lines.push(field(
+                "Content",
+                format!("{} file(s), {} dir(s)", d.file_count(), d.dir_count()),
+            ));
+        }
+        Entry::File(f) => lines.push(field("Size", human_size(f.size))),
+        Entry::Link(l) => lines.push(field("Target", name::escape_path(&l.target))),
+    }
+    lines.push(field("Modified", local_time(modified)));
+    lines.push(field("Mode", format!("{:o}", attributes.mode & 0o7777)));
+    lines.push(field(
+        "Owner",
+        format!("{}:{}", attributes.uid, attributes.gid),
+    ));
+    if let Entry::File(f) = entry {
+        lines.push(field("MIME", f.mime.clone().unwrap_or_else(|| "-".into())));
+        lines.push(field("MD5", format!("{:x}", f.md5)));
+        let others: Vec<&&File> = side.by_md5[&f.md5]
+            .iter()
+            .filter(|o| o.path != f.path)
+            .collect();
+        match others.len() {
+            0 => lines.push(field("Copies", "none".into())),
+            n => {
+                lines.push(field("Copies", format!("{n} other file(s)")));
+                for other in others.iter().take(MAX_DUPLICATES) {
+                    lines.push(Line::from(format!("  {}", relative(&other.path))));
+                }
+                if n > MAX_DUPLICATES {
+                    lines.push(Line::from(format!("  and {} more", n - MAX_DUPLICATES)));
+                }
+            }
+        }
+    }
+    lines
+}
+
+fn local_time(time: SystemTime) -> String {
+    let local: DateTime<Local> = time.into();
+    local.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// First row to show so that the selected one is visible
+fn scroll(offset: usize, selected: usize, height: usize) -> usize {
+    if selected < offset {
+        selected
+    } else if selected >= offset + height {
+        selected + 1 - height
+    } else {
+        offset
+    }
+}
+
+fn help_line(app: &App) -> Line<'static> {
+    let text = match &app.find {
+        Some(find) => format!(
+            "/{}   Tab: {} match  Enter: go to  Esc: cancel",
+            find.pattern,
+            if find.fuzzy { "substring" } else { "fuzzy" }
+        ),
+        None => {
+            let (vertical, horizontal) = match app.ascii {
+                true => ("Up/Down", "Left/Right"),
+                false => ("↑↓", "←→"),
+            };
+            let next = match app.right {
+                Some(_) => "  n: next change",
+                None => "",
+            };
+            format!("{vertical}: move  {horizontal}: collapse/expand  /: find{next}  q: quit")
+        }
+    };
+    Line::from(text).dim()
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use super::*;
+    use crate::core::model::fixtures::{dir, file, subdir};
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            app.key(KeyEvent::from(KeyCode::Char(c)));
+        }
+    }
+
+    fn screen(app: &mut App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|frame| draw(frame, app)).unwrap();
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content
+            .chunks(buffer.area.width as usize)
+            .map(|row| row.iter().map(|c| c.symbol()).collect::<String>() + "\n")
+            .collect()
+    }
+
+    #[test]
+    fn browse_and_find() {
+        let root = dir(
+            "/r",
+            vec![
+                file("/r/b10.txt", b"same"),
+                subdir(
+                    "/r/sub",
+                    vec![file("/r/sub/b2.txt", b"same"), file("/r/sub/c", b"c")],
+                ),
+                file("/r/a", b"a"),
+            ],
+        );
+        let mut app = App::new(&root);
+        // directories first, then natural order
+        assert_eq!(app.selected_path(), Some(Path::new("sub")));
+        assert_eq!(app.rows.len(), 3);
+        press(&mut app, "l");
+        assert_eq!(app.rows.len(), 5);
+        press(&mut app, "lj");
+        assert_eq!(app.selected_path(), Some(Path::new("sub/c")));
+        press(&mut app, "hh");
+        assert_eq!(app.selected_path(), Some(Path::new("sub")));
+        assert_eq!(app.rows.len(), 3);
+        press(&mut app, "G");
+        assert_eq!(app.selected_path(), Some(Path::new("b10.txt")));
+
+        let details = screen(&mut app);
+        assert!(details.contains("Copies    1 other file(s)"), "{details}");
+        assert!(details.contains("  sub/b2.txt"), "{details}");
+
+        press(&mut app, "/B");
+        let matches = app.find.as_ref().unwrap().matches.len();
+        assert_eq!(matches, 3);
+        press(&mut app, "2");
+        assert_eq!(app.selected_path(), Some(Path::new("sub/b2.txt")));
+        assert!(screen(&mut app).contains("1 substring match(es)"));
+        app.key(KeyEvent::from(KeyCode::Enter));
+        assert!(app.find.is_none());
+        assert_eq!(app.selected_path(), Some(Path::new("sub/b2.txt")));
+        assert_eq!(app.rows.len(), 5);
+
+        press(&mut app, "/sub/b2");
+        app.key(KeyEvent::from(KeyCode::Tab));
+        assert!(!app.find.as_ref().unwrap().matches.is_empty());
+        app.key(KeyEvent::from(KeyCode::Esc));
+        press(&mut app, "q");
+        assert!(app.done());
+    }
+
+    #[test]
+    fn side_by_side_status() {
+        let old = dir(
+            "/old",
+            vec![
+                subdir("/old/d", vec![file("/old/d/same", b"same")]),
+                file("/old/changed", b"1"),
+                file("/old/gone", b"gone"),
+                file("/old/moved", b"moved"),
+            ],
+        );
+        let new = dir(
+            "/new",
+            vec![
+                subdir("/new/d", vec![file("/new/d/same", b"same")]),
+                subdir("/new/e", vec![file("/new/e/new", b"new")]),
+                file("/new/changed", b"2"),
+                file("/new/renamed", b"moved"),
+            ],
+        );
+        let mut app = App::side_by_side(&old, &new);
+        let status: Vec<(String, Status)> = app
+            .rows
+            .iter()
+            .map(|i| {
+                let node = app.node(i);
+                (node.path.to_string_lossy().into_owned(), node.status)
+            })
+            .collect();
+        assert_eq!(
+            status,
+            vec![
+                ("d".into(), Status::Same),
+                ("e".into(), Status::Added),
+                ("changed".into(), Status::Modified),
+                ("gone".into(), Status::Removed),
+                ("moved".into(), Status::Renamed),
+                ("renamed".into(), Status::Renamed),
+            ]
+        );
+
+        press(&mut app, "n");
+        assert_eq!(app.selected_path(), Some(Path::new("e")));
+        press(&mut app, "n");
+        assert_eq!(app.selected_path(), Some(Path::new("e/new")));
+        press(&mut app, "nnnn");
+        assert_eq!(app.selected_path(), Some(Path::new("renamed")));
+        let output = screen(&mut app);
+        assert!(
+            output.contains("/old") && output.contains("/new"),
+            "{output}"
+        );
+        assert!(output.contains(">   moved"), "{output}");
+        assert!(output.contains("Status    renamed (moved)"), "{output}");
+        assert!(output.contains("~   changed"), "{output}");
+    }
+}